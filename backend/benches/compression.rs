@@ -0,0 +1,103 @@
+//! CPU time against bytes saved by permessage-deflate for typical events of different sizes.
+//! Run with `cargo bench --bench compression`.
+
+use std::time::{Duration, Instant};
+
+use chat_backend::{
+    compression::PerMessageDeflate, configuration::CompressionConfig, Payload, PayloadEventType,
+};
+use soketto::{
+    base::{Header, OpCode},
+    extension::Extension,
+    Storage,
+};
+
+const ROUNDS: usize = 200;
+
+fn chat_message(id: u64) -> Payload {
+    Payload {
+        username: format!("user{}", id % 5),
+        message: Some(format!("status update number {id}, see the attached notes")),
+        id: Some(id),
+        timestamp: Some(1_700_000_000_000 + id * 1_000),
+        mentions: vec![format!("user{}", (id + 1) % 5)],
+        ..Default::default()
+    }
+}
+
+fn history_batch() -> Payload {
+    Payload {
+        event_type: PayloadEventType::HistoryBatch,
+        message_id: Some(50),
+        history: (1..=50).map(chat_message).collect(),
+        ..Default::default()
+    }
+}
+
+/// Compress `messages` in order on a single connection, returning the compressed size and the
+/// time spent.
+fn measure(messages: &[Vec<u8>]) -> (usize, Duration) {
+    let config = CompressionConfig {
+        enabled: true,
+        threshold_bytes: 0,
+    };
+    let mut deflate = PerMessageDeflate::negotiate("permessage-deflate", &config, usize::MAX)
+        .expect("offer is not accepted");
+
+    let start = Instant::now();
+    let compressed = messages
+        .iter()
+        .map(|message| {
+            let mut header = Header::new(OpCode::Text);
+            let mut data = Storage::Shared(message);
+            deflate.encode(&mut header, &mut data).unwrap();
+            data.as_ref().len()
+        })
+        .sum();
+    (compressed, start.elapsed())
+}
+
+fn main() {
+    let batch = serde_json::to_vec(&history_batch()).unwrap();
+    let workloads = [
+        ("history_batch of 50", vec![batch; ROUNDS]),
+        (
+            "single messages",
+            (1..=ROUNDS as u64)
+                .map(|id| serde_json::to_vec(&chat_message(id)).unwrap())
+                .collect(),
+        ),
+        (
+            "read receipts",
+            (1..=ROUNDS as u64)
+                .map(|id| {
+                    serde_json::to_vec(&Payload {
+                        event_type: PayloadEventType::Read,
+                        username: "user1".into(),
+                        message_id: Some(id),
+                        ..Default::default()
+                    })
+                    .unwrap()
+                })
+                .collect(),
+        ),
+    ];
+
+    println!(
+        "{:<20} {:>12} {:>12} {:>12} {:>8} {:>12}",
+        "workload", "bytes/event", "raw bytes", "sent bytes", "saved", "µs/message"
+    );
+    for (name, messages) in &workloads {
+        let raw: usize = messages.iter().map(Vec::len).sum();
+        let (compressed, elapsed) = measure(messages);
+        println!(
+            "{:<20} {:>12} {:>12} {:>12} {:>7.1}% {:>12.1}",
+            name,
+            raw / messages.len(),
+            raw,
+            compressed,
+            100.0 * (1.0 - compressed as f64 / raw as f64),
+            elapsed.as_secs_f64() * 1e6 / messages.len() as f64,
+        );
+    }
+}
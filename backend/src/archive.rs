@@ -0,0 +1,217 @@
+//! Export of history for archiving, and import of exported JSON Lines dumps.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Payload, PayloadEventType, ServerState};
+
+/// Format of `GET /history/export` response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One JSON payload per line, the format accepted by `POST /history/import`.
+    #[default]
+    Jsonl,
+    /// Events rendered as in the chat view of the frontend, one per line.
+    Txt,
+    /// Standalone HTML page of the plaintext rendering.
+    Html,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jsonl => "application/x-ndjson",
+            Self::Txt => "text/plain; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Txt => "txt",
+            Self::Html => "html",
+        }
+    }
+
+    /// Beginning of the export before the first history event.
+    pub fn header(self) -> &'static str {
+        match self {
+            Self::Jsonl | Self::Txt => "",
+            Self::Html => concat!(
+                "<!DOCTYPE html>\n",
+                "<html>\n",
+                "<head><meta charset=\"utf-8\"><title>Chat history</title></head>\n",
+                "<body>\n",
+                "<ul>\n",
+            ),
+        }
+    }
+
+    /// End of the export after the last history event.
+    pub fn footer(self) -> &'static str {
+        match self {
+            Self::Jsonl | Self::Txt => "",
+            Self::Html => "</ul>\n</body>\n</html>\n",
+        }
+    }
+
+    /// Single history event as a line of the export.
+    pub fn render(self, payload: &Payload) -> String {
+        match self {
+            Self::Jsonl => serde_json::to_string(payload).unwrap() + "\n",
+            Self::Txt => message_line(payload) + "\n",
+            Self::Html => format!("<li>{}</li>\n", escape_html(&message_line(payload))),
+        }
+    }
+}
+
+/// Human-readable rendering of an event, matching `payloadToMessageLine` of the frontend.
+pub fn message_line(payload: &Payload) -> String {
+    let username = &payload.username;
+    let message = payload.message.as_deref().unwrap_or_default();
+    let message_id = payload
+        .message_id
+        .map_or(String::new(), |id| id.to_string());
+    let emoji = payload.emoji.as_deref().unwrap_or_default();
+    let reactions = payload
+        .reactions
+        .iter()
+        .map(|reaction| format!("{} {}", reaction.emoji, reaction.count))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    match payload.event_type {
+        PayloadEventType::Connected => format!("{username} has joined the chat."),
+        PayloadEventType::Disconnected => format!("{username} has left the chat."),
+        PayloadEventType::Message => {
+            let bot = if payload.bot { " (bot)" } else { "" };
+            let reply_to = payload
+                .reply_to
+                .map_or(String::new(), |id| format!(" (reply to #{id})"));
+            let attachments = payload
+                .attachments
+                .iter()
+                .map(|a| format!("[{}](/api/attachments/{})", a.filename, a.id))
+                .collect::<Vec<_>>()
+                .join(" ");
+            [
+                format!("[{username}{bot}]{reply_to}: {message}"),
+                attachments,
+                reactions,
+            ]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("  ")
+        }
+        PayloadEventType::React => {
+            format!("{username} reacted {emoji} to message #{message_id}.")
+        }
+        PayloadEventType::Unreact => {
+            format!("{username} removed {emoji} from message #{message_id}.")
+        }
+        PayloadEventType::Reactions => format!("Reactions on message #{message_id}: {reactions}"),
+        PayloadEventType::Replies => {
+            format!("Replies to message #{message_id}: {}", payload.reply_count)
+        }
+        PayloadEventType::Read => format!("{username} has seen messages until #{message_id}."),
+        PayloadEventType::Mention => format!("{username} mentioned you: {message}"),
+        PayloadEventType::MessagePreview => payload
+            .previews
+            .iter()
+            .map(|preview| {
+                let title = preview.title.as_deref().unwrap_or(&preview.url);
+                match &preview.description {
+                    Some(description) => format!("> {title} - {description}"),
+                    None => format!("> {title}"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        PayloadEventType::Hello => format!(
+            "Connected to chat server using {}.",
+            payload.protocol.as_deref().unwrap_or_default()
+        ),
+        PayloadEventType::System => format!("*** {message}"),
+        PayloadEventType::HistoryBatch => payload
+            .history
+            .iter()
+            .map(message_line)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Result of `POST /history/import`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+
+    /// Events whose identifier is already in history, e.g. from importing the same dump twice.
+    pub skipped: usize,
+}
+
+/// Parse JSON Lines dump of history events. Every event has to have the identifier and timestamp
+/// assigned by the server that it was exported from. Blank lines are ignored.
+pub fn parse_jsonl(dump: &str) -> Result<Vec<Payload>, String> {
+    let mut entries = Vec::new();
+    for (i, line) in dump.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = i + 1;
+        let payload: Payload =
+            serde_json::from_str(line).map_err(|e| format!("line {line_number}: {e}"))?;
+        if payload.id.is_none() || payload.timestamp.is_none() {
+            return Err(format!("line {line_number}: missing `id` or `timestamp`"));
+        }
+        entries.push(payload);
+    }
+    Ok(entries)
+}
+
+/// Merge imported events into history, keeping their identifiers and timestamps. New events
+/// continue from the largest identifier afterwards.
+pub fn import_history(server_state: &mut ServerState, entries: Vec<Payload>) -> ImportSummary {
+    let mut known_ids: HashSet<u64> = server_state
+        .history
+        .iter()
+        .filter_map(|payload| payload.id)
+        .collect();
+
+    let mut summary = ImportSummary {
+        imported: 0,
+        skipped: 0,
+    };
+    for payload in entries {
+        let Some(id) = payload.id else {
+            summary.skipped += 1;
+            continue;
+        };
+        if !known_ids.insert(id) {
+            summary.skipped += 1;
+            continue;
+        }
+        if let Err(e) = server_state.search_index.insert(&payload) {
+            tracing::error!(id, error = %e, "unable to index message");
+        }
+        server_state.last_history_id = server_state.last_history_id.max(id);
+        server_state.history.push(payload);
+        summary.imported += 1;
+    }
+
+    // Lookups by identifier rely on chronological order
+    server_state.history.sort_by_key(|payload| payload.id);
+    summary
+}
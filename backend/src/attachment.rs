@@ -0,0 +1,121 @@
+//! Storage of files and images uploaded through the REST API that chat messages can refer to.
+//!
+//! File contents are kept on local disk, while metadata of uploaded files is kept in memory next
+//! to the message history.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::AttachmentsConfig;
+
+/// Content types served for display in the browser. Anything else, like HTML or SVG that could
+/// run scripts in the origin of the chat, is served as a download.
+const INLINE_CONTENT_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Metadata of an uploaded file. Messages only need to specify the `id` of an attachment, the
+/// rest is filled in by the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+
+    #[serde(default)]
+    pub filename: String,
+
+    #[serde(default)]
+    pub content_type: String,
+
+    #[serde(default)]
+    pub size: usize,
+}
+
+impl Attachment {
+    /// Whether the file can be displayed by the browser instead of being downloaded.
+    pub fn is_inline(&self) -> bool {
+        INLINE_CONTENT_TYPES.contains(&self.content_type.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct AttachmentStore {
+    directory: PathBuf,
+    max_size_bytes: usize,
+    max_total_bytes: usize,
+    total_bytes: usize,
+    attachments: HashMap<String, Attachment>,
+}
+
+impl Default for AttachmentStore {
+    fn default() -> Self {
+        Self::new(&AttachmentsConfig {
+            directory: std::env::temp_dir()
+                .join("chat-attachments")
+                .to_string_lossy()
+                .into(),
+            ..Default::default()
+        })
+    }
+}
+
+impl AttachmentStore {
+    pub fn new(config: &AttachmentsConfig) -> Self {
+        Self {
+            directory: PathBuf::from(&config.directory),
+            max_size_bytes: config.max_size_bytes,
+            max_total_bytes: config.max_total_bytes,
+            total_bytes: 0,
+            attachments: HashMap::new(),
+        }
+    }
+
+    pub fn max_size_bytes(&self) -> usize {
+        self.max_size_bytes
+    }
+
+    /// Space left for uploads before reaching the limit of all files together.
+    pub fn remaining_bytes(&self) -> usize {
+        self.max_total_bytes.saturating_sub(self.total_bytes)
+    }
+
+    /// Generate identifier and disk location for a new upload.
+    pub fn new_upload(&self) -> (String, PathBuf) {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let path = self.directory.join(&id);
+        (id, path)
+    }
+
+    /// Disk location of an attachment, if it was uploaded.
+    pub fn path(&self, id: &str) -> Option<PathBuf> {
+        self.attachments
+            .contains_key(id)
+            .then(|| self.directory.join(id))
+    }
+
+    /// Register a file that is completely written to disk. Fails if it does not fit in the
+    /// remaining space, as concurrent uploads may have taken it.
+    pub fn insert(&mut self, attachment: Attachment) -> Result<(), String> {
+        if attachment.size > self.remaining_bytes() {
+            return Err("attachment storage is full".into());
+        }
+        self.total_bytes += attachment.size;
+        self.attachments.insert(attachment.id.clone(), attachment);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Attachment> {
+        self.attachments.get(id)
+    }
+
+    /// Replace attachments referred to by a message with their stored metadata. Fails if any of
+    /// them were not uploaded.
+    pub fn resolve(&self, attachments: &[Attachment]) -> Result<Vec<Attachment>, String> {
+        attachments
+            .iter()
+            .map(|attachment| {
+                self.get(&attachment.id)
+                    .cloned()
+                    .ok_or_else(|| format!("unknown attachment: {}", attachment.id))
+            })
+            .collect()
+    }
+}
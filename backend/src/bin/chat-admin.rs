@@ -0,0 +1,214 @@
+//! Command-line tool for managing a running backend through the admin endpoints of the REST API.
+
+use std::io::Write;
+
+use chat_backend::{ConnectedUser, Payload, ServerStats};
+use clap::{Parser, Subcommand};
+use reqwest::{Method, RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+#[derive(Parser)]
+#[command(about = "Manage the real-time chat server")]
+struct Args {
+    /// REST API listener of the backend.
+    #[arg(long, default_value = "http://localhost:9000")]
+    url: String,
+
+    /// Admin token configured on the server.
+    #[arg(long, env = "CHAT_ADMIN_TOKEN", hide_env_values = true)]
+    token: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List members of the chat.
+    Users,
+    /// Disconnect a member from the chat.
+    Kick { username: String },
+    /// Prevent a user from joining the chat, disconnecting them if they are in the chat.
+    Ban { username: String },
+    /// Allow a banned user to join the chat again.
+    Unban { username: String },
+    /// List banned users.
+    Bans,
+    /// Send an announcement to every member of the chat.
+    Announce { message: String },
+    /// Show or change the message of the day sent to joining clients.
+    Motd {
+        /// New message of the day.
+        message: Option<String>,
+        /// Stop sending a message of the day.
+        #[arg(long, conflicts_with = "message")]
+        clear: bool,
+    },
+    /// Remove events from history.
+    Purge {
+        /// Only remove events older than this history event.
+        #[arg(long)]
+        before: Option<u64>,
+    },
+    /// Download history.
+    Export {
+        #[arg(long, default_value = "jsonl", value_parser = ["jsonl", "txt", "html"])]
+        format: String,
+        /// File to write to instead of standard output.
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Show an overview of the server.
+    Stats,
+}
+
+struct AdminClient {
+    url: Url,
+    token: String,
+    http: reqwest::Client,
+}
+
+impl AdminClient {
+    /// Request to the path of the given segments, which are percent-encoded, so that usernames
+    /// can not change the route.
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("URL is checked to have a path")
+            .pop_if_empty()
+            .extend(segments);
+        self.http.request(method, url).bearer_auth(&self.token)
+    }
+
+    /// Send a request, failing with the response body on error statuses.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, String> {
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        if body.is_empty() {
+            Err(status.to_string())
+        } else {
+            Err(format!("{status}: {body}"))
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, segments: &[&str]) -> Result<T, String> {
+        let response = self.send(self.request(Method::GET, segments)).await?;
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        serde_json::from_str(&body).map_err(|e| e.to_string())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let args = Args::parse();
+    let url = Url::parse(&args.url).map_err(|e| format!("invalid URL {}: {e}", args.url))?;
+    if url.cannot_be_a_base() {
+        return Err(format!("invalid URL {}", args.url));
+    }
+    let client = AdminClient {
+        url,
+        token: args.token,
+        http: reqwest::Client::new(),
+    };
+
+    match args.command {
+        Command::Users => {
+            let users: Vec<ConnectedUser> = client.get_json(&["admin", "users"]).await?;
+            for user in users {
+                let transport = serde_json::to_value(user.transport).unwrap();
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    user.username,
+                    transport.as_str().unwrap_or_default(),
+                    user.protocol,
+                    user.address.unwrap_or_default(),
+                );
+            }
+        }
+        Command::Kick { username } => {
+            let request = client.request(Method::POST, &["admin", "users", &username, "kick"]);
+            client.send(request).await?;
+            println!("{username} is disconnected");
+        }
+        Command::Ban { username } => {
+            let request = client
+                .request(Method::POST, &["admin", "bans"])
+                .json(&json!({ "username": username }));
+            client.send(request).await?;
+            println!("{username} is banned");
+        }
+        Command::Unban { username } => {
+            let request = client.request(Method::DELETE, &["admin", "bans", &username]);
+            client.send(request).await?;
+            println!("{username} is unbanned");
+        }
+        Command::Bans => {
+            let bans: Vec<String> = client.get_json(&["admin", "bans"]).await?;
+            for username in bans {
+                println!("{username}");
+            }
+        }
+        Command::Announce { message } => {
+            let request = client
+                .request(Method::POST, &["admin", "announcements"])
+                .json(&json!({ "message": message }));
+            let response = client.send(request).await?;
+            let announcement: Payload = response.json().await.map_err(|e| e.to_string())?;
+            println!(
+                "announcement #{} is sent",
+                announcement.id.unwrap_or_default()
+            );
+        }
+        Command::Motd { message, clear } if message.is_some() || clear => {
+            let request = client
+                .request(Method::PUT, &["admin", "motd"])
+                .json(&json!({ "message": message }));
+            client.send(request).await?;
+            println!("message of the day is changed");
+        }
+        Command::Motd { .. } => {
+            let motd: serde_json::Value = client.get_json(&["admin", "motd"]).await?;
+            match motd["message"].as_str() {
+                Some(message) => println!("{message}"),
+                None => println!("no message of the day"),
+            }
+        }
+        Command::Purge { before } => {
+            let mut request = client.request(Method::DELETE, &["admin", "history"]);
+            if let Some(before) = before {
+                request = request.query(&[("before", before)]);
+            }
+            let response = client.send(request).await?;
+            let result: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+            println!("{} events are removed from history", result["removed"]);
+        }
+        Command::Export { format, output } => {
+            let request = client
+                .request(Method::GET, &["history", "export"])
+                .query(&[("format", format)]);
+            let mut response = client.send(request).await?;
+            let mut writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(std::fs::File::create(path).map_err(|e| e.to_string())?),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            // Large exports are written as they arrive
+            while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+                writer.write_all(&chunk).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+        Command::Stats => {
+            let stats: ServerStats = client.get_json(&["admin", "stats"]).await?;
+            let stats = serde_json::to_value(stats).unwrap();
+            for (name, value) in stats.as_object().unwrap() {
+                println!("{name}: {value}");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Terminal chat client connecting to a running backend, for use over SSH sessions where a web
+//! browser is not available.
+
+use std::time::Duration;
+
+use chat_backend::{
+    client::{Client, ClientConfig, Event},
+    tui::{Action, ChatView, ConnectionStatus},
+};
+use clap::Parser;
+use ratatui::{
+    crossterm::event::{self, Event as TerminalEvent},
+    DefaultTerminal,
+};
+use tokio::sync::mpsc;
+
+/// Interval of fetching the server's version of sent messages while they are pending.
+const PENDING_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[command(about = "Terminal client of the real-time chat")]
+struct Args {
+    /// Username to join the chat with.
+    username: String,
+
+    /// WebSocket listener of the backend.
+    #[arg(long, default_value = "ws://localhost:9001")]
+    ws_url: String,
+
+    /// REST API listener of the backend, used for history and connected users.
+    #[arg(long, default_value = "http://localhost:9000")]
+    rest_url: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let args = Args::parse();
+    let config = ClientConfig {
+        rest_url: Some(args.rest_url),
+        ..ClientConfig::new(&args.ws_url, &args.username)
+    };
+    let client = Client::connect(config).await?;
+    let mut view = ChatView::new(&args.username, &args.ws_url);
+    view.load_history(&client.history().await?);
+    view.set_users(client.users().await?);
+
+    let terminal = ratatui::init();
+    let result = run(terminal, client, view).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(
+    mut terminal: DefaultTerminal,
+    mut client: Client,
+    mut view: ChatView,
+) -> Result<(), String> {
+    // Reading the terminal blocks, so key presses are forwarded from a thread of their own
+    let (keys_tx, mut keys) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if keys_tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut pending_poll = tokio::time::interval(PENDING_POLL_INTERVAL);
+    loop {
+        terminal
+            .draw(|frame| view.render(frame))
+            .map_err(|e| e.to_string())?;
+        let connected = view.status() != ConnectionStatus::Disconnected;
+        let pending_since = view.pending_since();
+        tokio::select! {
+            event = client.next_event(), if connected => match event {
+                Some(event) => {
+                    view.apply(&event);
+                    if event == Event::Reconnected {
+                        // Members may have come and gone while disconnected
+                        match client.users().await {
+                            Ok(users) => view.set_users(users),
+                            Err(e) => tracing::info!(error = %e, "unable to fetch users"),
+                        }
+                    }
+                }
+                None => view.set_status(ConnectionStatus::Disconnected),
+            },
+            // Senders do not receive their own messages, so they are fetched from history
+            _ = pending_poll.tick(), if pending_since.is_some() => {
+                match client.history_after(pending_since.unwrap_or_default()).await {
+                    Ok(history) => view.load_history(&history),
+                    Err(e) => tracing::info!(error = %e, "unable to fetch sent messages"),
+                }
+            }
+            // Other terminal events, like resizing, only cause redrawing
+            Some(terminal_event) = keys.recv() => {
+                let TerminalEvent::Key(key) = terminal_event else {
+                    continue;
+                };
+                match view.handle_key(key) {
+                    Some(Action::Send(message)) if client.send_message(&message).is_ok() => {
+                        view.show_sent(&message);
+                    }
+                    Some(Action::Quit) => return Ok(()),
+                    _ => {}
+                }
+            }
+            else => return Ok(()),
+        }
+    }
+}
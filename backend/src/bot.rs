@@ -0,0 +1,302 @@
+//! In-process chat bots compiled into the server binary.
+//!
+//! Each enabled bot joins the chat as a member of its own, so it is listed next to other users
+//! and receives every event broadcasted to members. Bots react to events by sending messages,
+//! which are flagged as sent by a bot. Kinds of bots are registered in a [`BotRegistry`] and
+//! enabled by the `bots` section of configuration.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::{
+    chat, configuration::BotConfig, lock_state, protocol::Protocol, ClientId, Payload,
+    PayloadEventType, SharedServerState,
+};
+
+/// Chat bot reacting to events broadcasted in the chat.
+pub trait Bot: Send + Sync {
+    /// Handle an event received by the bot. Long-running work, like waiting, is spawned as a
+    /// task that replies through a clone of `chat`.
+    fn on_event(&self, event: &Payload, chat: &BotContext);
+}
+
+/// Handle of a bot for sending messages to the chat.
+#[derive(Clone)]
+pub struct BotContext {
+    name: Arc<str>,
+    outbox: mpsc::UnboundedSender<Payload>,
+}
+
+impl BotContext {
+    /// Username of the bot.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Send a chat message.
+    pub fn send(&self, message: impl Into<String>) {
+        self.send_payload(message.into(), None);
+    }
+
+    /// Send a chat message replying to the message `to`.
+    pub fn reply(&self, to: &Payload, message: impl Into<String>) {
+        self.send_payload(message.into(), to.id);
+    }
+
+    fn send_payload(&self, message: String, reply_to: Option<u64>) {
+        let payload = Payload {
+            username: self.name.to_string(),
+            message: Some(message),
+            reply_to,
+            ..Default::default()
+        };
+        // Messages are only dropped once the bot has left the chat
+        let _ = self.outbox.send(payload);
+    }
+}
+
+/// Bot paired with its username.
+pub type NamedBot = (String, Box<dyn Bot>);
+
+/// Creates a bot from its configuration.
+pub type BotFactory = fn(&BotConfig) -> Result<Box<dyn Bot>, String>;
+
+/// Kinds of bots available for configuration, by the `type` used in configuration.
+#[derive(Default)]
+pub struct BotRegistry {
+    factories: HashMap<&'static str, BotFactory>,
+}
+
+impl BotRegistry {
+    /// Registry of the bots shipped with the server.
+    pub fn with_builtin_bots() -> Self {
+        let mut registry = Self::default();
+        registry.register("echo", |_| Ok(Box::new(EchoBot)));
+        registry.register("reminder", |_| Ok(Box::<ReminderBot>::default()));
+        registry
+    }
+
+    pub fn register(&mut self, kind: &'static str, factory: BotFactory) {
+        self.factories.insert(kind, factory);
+    }
+
+    /// Create the bots enabled in configuration, paired with their usernames.
+    pub fn create(&self, configs: &[BotConfig]) -> Result<Vec<NamedBot>, String> {
+        configs
+            .iter()
+            .map(|config| {
+                let factory = self
+                    .factories
+                    .get(config.kind.as_str())
+                    .ok_or_else(|| format!("unknown bot type: {}", config.kind))?;
+                Ok((config.name.clone(), factory(config)?))
+            })
+            .collect()
+    }
+}
+
+/// Join the chat with every bot and run them until the server shuts down.
+pub async fn run_bots(server_state: SharedServerState, bots: Vec<NamedBot>) {
+    let tasks = bots.into_iter().enumerate().map(|(index, (name, bot))| {
+        let span = tracing::info_span!("bot", username = %name);
+        run_bot(server_state.clone(), index, name, bot).instrument(span)
+    });
+    futures_util::future::join_all(tasks).await;
+}
+
+async fn run_bot(server_state: SharedServerState, index: usize, name: String, bot: Box<dyn Bot>) {
+    let client_id = ClientId::Bot(index);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (outbox, mut outgoing) = mpsc::unbounded_channel();
+    let protocol = Protocol::LATEST;
+    {
+        let mut state = lock_state(&server_state).await;
+        if let Err(e) = chat::add_client(&mut state, client_id, tx, protocol, &name) {
+            tracing::error!(error = %e, "bot is unable to join");
+            return;
+        }
+        let joined = Payload {
+            event_type: PayloadEventType::Connected,
+            username: name.clone(),
+            ..Default::default()
+        };
+        chat::broadcast(&mut state, joined, Some(client_id)).await;
+    }
+    tracing::info!("bot joined the chat");
+
+    let context = BotContext {
+        name: name.as_str().into(),
+        outbox,
+    };
+    loop {
+        tokio::select! {
+            Some(msg) = rx.recv() => {
+                // Bot is disconnected by the server, e.g. kicked by an admin
+                if msg.is_close() {
+                    chat::remove_client(server_state.clone(), client_id).await;
+                    break;
+                }
+                match protocol.decode(&msg) {
+                    Ok(event) => bot.on_event(&event, &context),
+                    Err(e) => tracing::error!(error = %e, "bot received invalid event"),
+                }
+            }
+            Some(payload) = outgoing.recv() => {
+                let mut state = lock_state(&server_state).await;
+                let result =
+                    chat::send_message(&mut state, server_state.clone(), payload, Some(client_id));
+                if let Err(e) = result.await {
+                    tracing::info!(error = %e, "message of bot is rejected");
+                }
+            }
+            else => break,
+        }
+    }
+}
+
+/// Repeats the text of `/echo <text>` commands.
+pub struct EchoBot;
+
+impl Bot for EchoBot {
+    fn on_event(&self, event: &Payload, chat: &BotContext) {
+        if event.event_type != PayloadEventType::Message || event.bot {
+            return;
+        }
+        let Some(text) = event
+            .message
+            .as_deref()
+            .and_then(|message| message.strip_prefix("/echo "))
+        else {
+            return;
+        };
+        chat.reply(event, text.trim());
+    }
+}
+
+/// Reminds users of something after a delay with `/remind <delay> <subject>` commands, e.g.
+/// `/remind 10m standup`. Pending reminders are limited per user and in total, as each of them
+/// holds a task until it is due.
+#[derive(Default)]
+pub struct ReminderBot {
+    pending: Arc<Mutex<PendingReminders>>,
+}
+
+#[derive(Default)]
+struct PendingReminders {
+    total: usize,
+    by_user: HashMap<String, usize>,
+}
+
+impl ReminderBot {
+    pub const MAX_PENDING_PER_USER: usize = 5;
+    pub const MAX_PENDING: usize = 1000;
+
+    /// Count a new pending reminder of `user`, unless a limit is reached.
+    fn reserve(&self, user: &str) -> Result<(), String> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.total >= Self::MAX_PENDING {
+            return Err("too many reminders are pending, try again later".to_string());
+        }
+        let count = pending.by_user.entry(user.to_string()).or_default();
+        if *count >= Self::MAX_PENDING_PER_USER {
+            return Err(format!(
+                "you already have {} pending reminders",
+                Self::MAX_PENDING_PER_USER
+            ));
+        }
+        *count += 1;
+        pending.total += 1;
+        Ok(())
+    }
+}
+
+impl PendingReminders {
+    fn release(&mut self, user: &str) {
+        self.total -= 1;
+        if let Some(count) = self.by_user.get_mut(user) {
+            *count -= 1;
+            if *count == 0 {
+                self.by_user.remove(user);
+            }
+        }
+    }
+}
+
+impl Bot for ReminderBot {
+    fn on_event(&self, event: &Payload, chat: &BotContext) {
+        if event.event_type != PayloadEventType::Message || event.bot {
+            return;
+        }
+        let Some(command) = event
+            .message
+            .as_deref()
+            .and_then(|message| message.strip_prefix("/remind"))
+            .filter(|command| command.is_empty() || command.starts_with(' '))
+        else {
+            return;
+        };
+        let user = &event.username;
+        let mut words = command.split_whitespace();
+        let delay = words.next().and_then(parse_delay);
+        let subject = words.collect::<Vec<_>>().join(" ");
+        let Some(delay) = delay.filter(|_| !subject.is_empty()) else {
+            chat.reply(
+                event,
+                format!("@{user} usage: /remind <delay like 30s, 10m, 2h or 1d> <subject>"),
+            );
+            return;
+        };
+        if let Err(e) = self.reserve(user) {
+            chat.reply(event, format!("@{user} {e}"));
+            return;
+        }
+        chat.reply(
+            event,
+            format!("@{user} I will remind you in {}", format_delay(delay)),
+        );
+
+        let chat = chat.clone();
+        let event = event.clone();
+        let pending = self.pending.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            chat.reply(&event, format!("@{} reminder: {subject}", event.username));
+            pending.lock().unwrap().release(&event.username);
+        });
+    }
+}
+
+/// Parse a delay like `30s`, `10m`, `2h` or `1d`.
+fn parse_delay(delay: &str) -> Option<Duration> {
+    const MAX_SECS: u64 = 365 * 24 * 60 * 60;
+
+    let unit_index = delay.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = delay.split_at(unit_index);
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    let secs = amount.parse::<u64>().ok()?.checked_mul(unit_secs)?;
+    (1..=MAX_SECS)
+        .contains(&secs)
+        .then(|| Duration::from_secs(secs))
+}
+
+/// Format a delay with the largest unit that it is a whole multiple of.
+fn format_delay(delay: Duration) -> String {
+    let secs = delay.as_secs();
+    let (amount, unit) = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")]
+        .into_iter()
+        .find(|(unit_secs, _)| secs.is_multiple_of(*unit_secs))
+        .map_or((secs, "s"), |(unit_secs, unit)| (secs / unit_secs, unit));
+    format!("{amount}{unit}")
+}
@@ -0,0 +1,467 @@
+//! Chat operations shared by the WebSocket and HTTP transports: membership of the chat, fan-out
+//! of events to its members and history.
+
+use std::time::Instant;
+
+use tokio_tungstenite::tungstenite::Message;
+use tracing::Instrument;
+
+use crate::{
+    lock_state, logging,
+    protocol::{EncodedPayload, Protocol},
+    unfurl::LinkPreview,
+    unix_timestamp_millis, ChatClient, ClientId, Payload, PayloadEventType, Reaction, ServerState,
+    SharedServerState, Tx,
+};
+
+/// Add a member to the chat. Fails if the username is already taken or banned.
+pub fn add_client(
+    server_state: &mut ServerState,
+    client_id: ClientId,
+    tx: Tx,
+    protocol: Protocol,
+    username: &str,
+) -> Result<(), String> {
+    if server_state
+        .clients
+        .values()
+        .any(|client| client.username == username)
+    {
+        return Err(format!("user already exists: {username}"));
+    }
+    if server_state.banned_users.contains(username) {
+        return Err(format!("user is banned: {username}"));
+    }
+
+    server_state.clients.insert(
+        client_id,
+        ChatClient {
+            username: username.into(),
+            tx,
+            protocol,
+        },
+    );
+
+    Ok(())
+}
+
+/// Send the latest history events to a client that has just joined the chat. The batch ends at
+/// the latest history identifier, even if that event is not part of the batch.
+pub fn send_history_batch(server_state: &ServerState, client: &ChatClient) {
+    if server_state.history_replay_count == 0 {
+        return;
+    }
+    let history = &server_state.history;
+    let start = history
+        .len()
+        .saturating_sub(server_state.history_replay_count);
+    let batch = Payload {
+        event_type: PayloadEventType::HistoryBatch,
+        message_id: Some(server_state.last_history_id),
+        history: history[start..].to_vec(),
+        ..Default::default()
+    };
+    // Clients of older protocol versions query history from REST API instead
+    let Some(msg) = client.protocol.encode(&batch) else {
+        return;
+    };
+    // Receivers of leaving clients are dropped before the clients are removed from the chat
+    if client.tx.send(msg).is_err() {
+        return;
+    }
+    tracing::trace!(
+        count = batch.history.len(),
+        recipient = %client.username,
+        "sent history events"
+    );
+}
+
+/// Send the message of the day privately to a client that has just joined the chat.
+pub fn send_motd(server_state: &ServerState, client: &ChatClient) {
+    let Some(motd) = &server_state.motd else {
+        return;
+    };
+    let payload = Payload {
+        event_type: PayloadEventType::System,
+        message: Some(motd.clone()),
+        ..Default::default()
+    };
+    if let Some(msg) = client.protocol.encode(&payload) {
+        // Client is leaving if its receiver is dropped
+        let _ = client.tx.send(msg);
+    }
+}
+
+/// Send out message to multiple users in the chat and save it to history. `sender` is excluded
+/// from the list of message recipients. If `sender` is not specified, all members of the
+/// chat receive the message and is treated as a server status message.
+pub async fn broadcast(
+    server_state: &mut ServerState,
+    mut payload: Payload,
+    sender: Option<ClientId>,
+) -> u64 {
+    server_state.last_history_id += 1;
+    let id = server_state.last_history_id;
+    payload.id = Some(id);
+    payload.timestamp = Some(unix_timestamp_millis());
+
+    send_to_all(server_state, &payload, sender);
+    notify_mentioned_users(server_state, &payload);
+    server_state.webhooks.notify(&payload);
+
+    if let Err(e) = server_state.search_index.insert(&payload) {
+        tracing::error!(id, error = %e, "unable to index message");
+    }
+
+    // Save message to history, and presence events only if configured
+    match payload.event_type {
+        PayloadEventType::Connected | PayloadEventType::Disconnected
+            if !server_state.retention.keep_presence_events => {}
+        _ => server_state.history.push(payload),
+    }
+    server_state.history_updates.send_replace(id);
+    id
+}
+
+/// Fetch previews of links in an already broadcasted message in the background, then notify all
+/// members of the chat about them with a `message_preview` event.
+fn unfurl_links(
+    server_state: &ServerState,
+    shared_state: SharedServerState,
+    message_id: u64,
+    message: &str,
+) {
+    let Some(unfurler) = server_state.link_unfurler.clone() else {
+        return;
+    };
+    let urls = unfurler.extract_urls(message);
+    if urls.is_empty() {
+        return;
+    }
+
+    tokio::spawn(
+        async move {
+            let previews: Vec<LinkPreview> =
+                futures_util::future::join_all(urls.iter().map(|url| unfurler.preview(url)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect();
+            if previews.is_empty() {
+                return;
+            }
+
+            let mut server_state = lock_state(&shared_state).await;
+            if let Some(entry) = server_state.history_entry_mut(message_id) {
+                entry.previews = previews.clone();
+            }
+            let update = Payload {
+                event_type: PayloadEventType::MessagePreview,
+                message_id: Some(message_id),
+                previews,
+                ..Default::default()
+            };
+            send_to_all(&server_state, &update, None);
+            tracing::trace!(message_id, "link previews are sent");
+        }
+        .in_current_span(),
+    );
+}
+
+/// Send out message to multiple users in the chat without saving it to history. `sender` is
+/// excluded from the list of message recipients.
+pub fn send_to_all(server_state: &ServerState, payload: &Payload, sender: Option<ClientId>) {
+    // Serialize only once for each protocol version instead of for each broadcast target
+    let start = Instant::now();
+    let mut encoded = EncodedPayload::new(payload);
+    let broadcast_recipients = server_state
+        .clients
+        .iter()
+        .filter(|(id, _client)| {
+            // Exclude message sender from broadcast
+            sender != Some(**id)
+        })
+        .map(|(_, ws_sink)| ws_sink);
+    for broadcast_user in broadcast_recipients {
+        let Some(msg) = encoded.get(broadcast_user.protocol) else {
+            continue;
+        };
+        // Receivers of leaving members are dropped before the members are removed from the
+        // chat, e.g. once their connection is closed
+        if broadcast_user.tx.send(msg.clone()).is_err() {
+            tracing::trace!(recipient = %broadcast_user.username, "recipient is leaving");
+            continue;
+        }
+        server_state.metrics.messages_sent.inc();
+        tracing::trace!(
+            recipient = %broadcast_user.username,
+            body = logging::message_body(&msg, server_state.log_message_bodies),
+            "sent event"
+        );
+    }
+    server_state
+        .metrics
+        .broadcast_fanout_seconds
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Validate a chat message of `sender`, then broadcast it and save it to history. Messages of bots
+/// and messages without a sending chat member are flagged as sent by a bot. Returns the history identifier of the
+/// message.
+pub async fn send_message(
+    server_state: &mut ServerState,
+    shared_state: SharedServerState,
+    mut payload: Payload,
+    sender: Option<ClientId>,
+) -> Result<u64, String> {
+    server_state.metrics.messages_received.inc();
+    prepare_message(server_state, &mut payload)?;
+    payload.bot = sender.is_none_or(|sender| matches!(sender, ClientId::Bot(_)));
+    let message = payload.message.clone().unwrap_or_default();
+    let reply_to = payload.reply_to;
+    let id = broadcast(server_state, payload, sender).await;
+    if let Some(parent_id) = reply_to {
+        send_reply_count(server_state, parent_id);
+    }
+    unfurl_links(server_state, shared_state, id, &message);
+    Ok(id)
+}
+
+/// Validate chat message and fill in fields maintained by the server before broadcasting it.
+fn prepare_message(server_state: &mut ServerState, payload: &mut Payload) -> Result<(), String> {
+    payload.reactions.clear();
+    payload.reply_count = 0;
+
+    let message = payload.message.take().unwrap_or_default();
+    let message = server_state.message_filters.apply(message)?;
+    payload.mentions = parse_mentions(&message);
+    payload.message = Some(message);
+    payload.attachments = server_state.attachments.resolve(&payload.attachments)?;
+
+    register_reply(server_state, payload)
+}
+
+/// Collect unique `@username` mentions from message text in order of appearance.
+fn parse_mentions(message: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for word in message.split_whitespace() {
+        let Some(username) = word.strip_prefix('@') else {
+            continue;
+        };
+        let username: String = username
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            .collect();
+        let username = username.trim_end_matches('.');
+        if !username.is_empty() && !mentions.iter().any(|m| m == username) {
+            mentions.push(username.into());
+        }
+    }
+    mentions
+}
+
+/// Send `mention` notification to every connected user mentioned in the message, except the
+/// sender. Webhooks receive a single notification listing all mentioned users.
+fn notify_mentioned_users(server_state: &ServerState, payload: &Payload) {
+    if payload.mentions.is_empty() {
+        return;
+    }
+    let notification = Payload {
+        event_type: PayloadEventType::Mention,
+        username: payload.username.clone(),
+        message: payload.message.clone(),
+        message_id: payload.id,
+        ..Default::default()
+    };
+    let mut encoded = EncodedPayload::new(&notification);
+    let recipients = server_state.clients.values().filter(|client| {
+        client.username != payload.username && payload.mentions.contains(&client.username)
+    });
+    for recipient in recipients {
+        let Some(msg) = encoded.get(recipient.protocol) else {
+            continue;
+        };
+        // Recipient is leaving if its receiver is dropped
+        if recipient.tx.send(msg).is_err() {
+            continue;
+        }
+        tracing::trace!(recipient = %recipient.username, "notified about mention");
+    }
+    server_state.webhooks.notify(&Payload {
+        mentions: payload.mentions.clone(),
+        ..notification
+    });
+}
+
+/// Increase reply count of the message that `payload` replies to, if any. Fails if the replied
+/// message does not exist.
+fn register_reply(server_state: &mut ServerState, payload: &Payload) -> Result<(), String> {
+    let Some(parent_id) = payload.reply_to else {
+        return Ok(());
+    };
+    let parent = server_state
+        .history_entry_mut(parent_id)
+        .filter(|entry| entry.event_type == PayloadEventType::Message)
+        .ok_or_else(|| format!("replied message {parent_id} does not exist"))?;
+    parent.reply_count += 1;
+
+    Ok(())
+}
+
+/// Notify all members of the chat about the updated reply count of a message, including the
+/// sender of the reply.
+fn send_reply_count(server_state: &ServerState, message_id: u64) {
+    let Some(message) = server_state.history_entry(message_id) else {
+        return;
+    };
+    let update = Payload {
+        event_type: PayloadEventType::Replies,
+        message_id: Some(message_id),
+        reply_count: message.reply_count,
+        ..Default::default()
+    };
+    send_to_all(server_state, &update, None);
+}
+
+/// Add or remove reaction of a user on a message, then notify all members of the chat about the
+/// updated reaction counts of the message, including the user who reacted.
+pub fn update_reactions(server_state: &mut ServerState, payload: Payload) -> Result<(), String> {
+    const MAX_EMOJI_CHARS: usize = 16;
+
+    let (Some(message_id), Some(emoji)) = (payload.message_id, payload.emoji) else {
+        return Err("missing message_id or emoji".into());
+    };
+    if emoji.is_empty()
+        || emoji.chars().count() > MAX_EMOJI_CHARS
+        || emoji.contains(char::is_whitespace)
+    {
+        return Err(format!("invalid emoji: {emoji:?}"));
+    }
+    let message = server_state
+        .history_entry_mut(message_id)
+        .filter(|entry| entry.event_type == PayloadEventType::Message)
+        .ok_or_else(|| format!("no message with id {message_id}"))?;
+
+    let username = payload.username;
+    let position = message.reactions.iter().position(|r| r.emoji == emoji);
+    match (payload.event_type, position) {
+        (PayloadEventType::React, Some(i)) => {
+            let reaction = &mut message.reactions[i];
+            if reaction.usernames.contains(&username) {
+                return Ok(());
+            }
+            reaction.usernames.push(username);
+            reaction.count = reaction.usernames.len();
+        }
+        (PayloadEventType::React, None) => message.reactions.push(Reaction {
+            emoji,
+            count: 1,
+            usernames: vec![username],
+        }),
+        (PayloadEventType::Unreact, Some(i)) => {
+            let reaction = &mut message.reactions[i];
+            let Some(user_index) = reaction.usernames.iter().position(|u| *u == username) else {
+                return Ok(());
+            };
+            reaction.usernames.remove(user_index);
+            reaction.count = reaction.usernames.len();
+            if reaction.count == 0 {
+                message.reactions.remove(i);
+            }
+        }
+        _ => return Ok(()),
+    }
+    tracing::trace!(message_id, "reactions are updated");
+
+    let update = Payload {
+        event_type: PayloadEventType::Reactions,
+        message_id: Some(message_id),
+        reactions: message.reactions.clone(),
+        ..Default::default()
+    };
+    send_to_all(server_state, &update, None);
+
+    Ok(())
+}
+
+/// Move read position of a user forward, then notify other members of the chat about it.
+pub fn update_read_position(
+    server_state: &mut ServerState,
+    payload: Payload,
+    sender: ClientId,
+) -> Result<(), String> {
+    let Some(message_id) = payload.message_id else {
+        return Err("missing message_id".into());
+    };
+    if message_id > server_state.last_history_id {
+        return Err(format!("no message with id {message_id}"));
+    }
+    // Position is saved for the member the client has joined as
+    let Some(username) = server_state
+        .clients
+        .get(&sender)
+        .map(|client| client.username.clone())
+    else {
+        return Err("client has not joined".into());
+    };
+    let position = server_state
+        .read_positions
+        .entry(username.clone())
+        .or_default();
+    if *position >= message_id {
+        return Ok(());
+    }
+    *position = message_id;
+    tracing::trace!(%username, message_id, "read position is updated");
+
+    let update = Payload {
+        event_type: PayloadEventType::Read,
+        username,
+        message_id: Some(message_id),
+        ..Default::default()
+    };
+    send_to_all(server_state, &update, Some(sender));
+
+    Ok(())
+}
+
+/// Close the connection of the member named `username`, who then leaves the chat like on any
+/// disconnect. Returns `false` if nobody by that name is in the chat.
+pub fn disconnect_user(server_state: &ServerState, username: &str) -> bool {
+    let Some(client) = server_state
+        .clients
+        .values()
+        .find(|client| client.username == username)
+    else {
+        return false;
+    };
+    // Connection handlers close the connection once they receive this message
+    let _ = client.tx.send(Message::Close(None));
+    tracing::info!(%username, "user is disconnected by the server");
+    true
+}
+
+/// Remove user from the list of users. Notifies remaining members in the chat about
+/// the disconnected users.
+pub async fn remove_client(server_state: SharedServerState, disconnected_client_id: ClientId) {
+    let mut server_state = lock_state(&server_state).await;
+    let Some(disconnected_client) = server_state.clients.get(&disconnected_client_id) else {
+        return;
+    };
+    let username = disconnected_client.username.clone();
+
+    // Update client list
+    server_state.clients.remove(&disconnected_client_id);
+    server_state
+        .sessions
+        .retain(|_, client_id| *client_id != disconnected_client_id);
+    tracing::trace!(%username, "user left the chat");
+
+    // Notify remaining chat members
+    let payload = Payload {
+        event_type: PayloadEventType::Disconnected,
+        username: username.clone(),
+        ..Default::default()
+    };
+    broadcast(&mut server_state, payload, None).await;
+}
@@ -0,0 +1,449 @@
+//! Async Rust client of the chat, for tools and integration tests.
+//!
+//! The client joins the chat over WebSocket with the latest protocol version and turns incoming
+//! payloads into typed [`Event`]s. If the connection is lost, it reconnects and joins again with
+//! exponential backoff, keeping messages sent meanwhile until they can be delivered. History and
+//! connected members are fetched from the REST API.
+
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, Stream, StreamExt};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, http::HeaderValue},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    protocol::Protocol, unfurl::LinkPreview, Payload, PayloadEventType, Reaction, UnreadCount,
+};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Secret of the session of the latest connection, which authenticates REST API requests.
+type SessionToken = Arc<Mutex<Option<String>>>;
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// WebSocket server, e.g. `ws://localhost:8080`.
+    pub ws_url: String,
+
+    /// REST API server, e.g. `http://localhost:8000/api`. Required for fetching history.
+    pub rest_url: Option<String>,
+
+    pub username: String,
+
+    /// Wait before the first reconnection attempt, doubled for further attempts. The client
+    /// does not reconnect if not set.
+    pub reconnect_delay: Option<Duration>,
+
+    /// Longest wait between reconnection attempts.
+    pub max_reconnect_delay: Duration,
+}
+
+impl ClientConfig {
+    pub fn new(ws_url: impl Into<String>, username: impl Into<String>) -> Self {
+        Self {
+            ws_url: ws_url.into(),
+            rest_url: None,
+            username: username.into(),
+            reconnect_delay: Some(Duration::from_secs(1)),
+            max_reconnect_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Event received from the chat.
+// Messages are the most common events, so they are not boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Negotiated protocol version and features of the server, received before other events.
+    Hello {
+        protocol: String,
+        capabilities: Vec<String>,
+    },
+    /// Latest history replayed after joining. Events newer than `last_id` are received live.
+    History {
+        events: Vec<Payload>,
+        last_id: u64,
+    },
+    Joined {
+        username: String,
+    },
+    Left {
+        username: String,
+    },
+    Message(Payload),
+    /// Updated reaction counts of a message.
+    Reactions {
+        message_id: u64,
+        reactions: Vec<Reaction>,
+    },
+    /// Updated number of replies to a message.
+    Replies {
+        message_id: u64,
+        reply_count: usize,
+    },
+    /// User has seen every event up to and including `message_id`.
+    Read {
+        username: String,
+        message_id: u64,
+    },
+    /// The user of the client is mentioned by `username` in a message.
+    Mention {
+        username: String,
+        message: String,
+        message_id: u64,
+    },
+    /// Link previews of a message became available.
+    Previews {
+        message_id: u64,
+        previews: Vec<LinkPreview>,
+    },
+    /// Announcement or message of the day from the server.
+    System {
+        message: String,
+    },
+    /// Connection to the server is lost. No more events follow unless the client reconnects.
+    ConnectionLost,
+    /// Connection to the server is restored and the client has joined the chat again.
+    Reconnected,
+}
+
+impl Event {
+    /// Typed event of a payload sent by the server. Events only sent by clients are ignored.
+    pub fn from_payload(payload: Payload) -> Option<Self> {
+        let message_id = payload.message_id.unwrap_or_default();
+        let event = match payload.event_type {
+            PayloadEventType::Hello => Self::Hello {
+                protocol: payload.protocol.unwrap_or_default(),
+                capabilities: payload.capabilities,
+            },
+            PayloadEventType::HistoryBatch => Self::History {
+                events: payload.history,
+                last_id: message_id,
+            },
+            PayloadEventType::Connected => Self::Joined {
+                username: payload.username,
+            },
+            PayloadEventType::Disconnected => Self::Left {
+                username: payload.username,
+            },
+            PayloadEventType::Message => Self::Message(payload),
+            PayloadEventType::Reactions => Self::Reactions {
+                message_id,
+                reactions: payload.reactions,
+            },
+            PayloadEventType::Replies => Self::Replies {
+                message_id,
+                reply_count: payload.reply_count,
+            },
+            PayloadEventType::Read => Self::Read {
+                username: payload.username,
+                message_id,
+            },
+            PayloadEventType::Mention => Self::Mention {
+                username: payload.username,
+                message: payload.message.unwrap_or_default(),
+                message_id,
+            },
+            PayloadEventType::MessagePreview => Self::Previews {
+                message_id,
+                previews: payload.previews,
+            },
+            PayloadEventType::System => Self::System {
+                message: payload.message.unwrap_or_default(),
+            },
+            PayloadEventType::React | PayloadEventType::Unreact => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Member of the chat. Events are received with [`Client::next_event`] or by using the client
+/// as a [`Stream`].
+pub struct Client {
+    config: ClientConfig,
+    outgoing: mpsc::UnboundedSender<Payload>,
+    events: mpsc::UnboundedReceiver<Event>,
+    session_token: SessionToken,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Connect to the server and join the chat. Joining fails afterwards if the username is
+    /// taken, in which case the server closes the connection.
+    pub async fn connect(config: ClientConfig) -> Result<Self, String> {
+        let socket = open(&config).await?;
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let session_token = SessionToken::default();
+        tokio::spawn(run_connection(
+            config.clone(),
+            socket,
+            outgoing_rx,
+            events_tx,
+            session_token.clone(),
+        ));
+        Ok(Self {
+            config,
+            outgoing,
+            events,
+            session_token,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.config.username
+    }
+
+    /// Next event of the chat, or `None` once the connection is lost for good.
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    /// Send an event of any type as the user of the client.
+    pub fn send(&self, mut payload: Payload) -> Result<(), String> {
+        payload.username.clone_from(&self.config.username);
+        self.outgoing
+            .send(payload)
+            .map_err(|_| "connection is closed".to_string())
+    }
+
+    pub fn send_message(&self, message: impl Into<String>) -> Result<(), String> {
+        self.send(Payload {
+            message: Some(message.into()),
+            ..Default::default()
+        })
+    }
+
+    /// Send a message replying to message `reply_to`.
+    pub fn reply(&self, reply_to: u64, message: impl Into<String>) -> Result<(), String> {
+        self.send(Payload {
+            message: Some(message.into()),
+            reply_to: Some(reply_to),
+            ..Default::default()
+        })
+    }
+
+    pub fn react(&self, message_id: u64, emoji: impl Into<String>) -> Result<(), String> {
+        self.send(reaction(PayloadEventType::React, message_id, emoji.into()))
+    }
+
+    pub fn unreact(&self, message_id: u64, emoji: impl Into<String>) -> Result<(), String> {
+        self.send(reaction(
+            PayloadEventType::Unreact,
+            message_id,
+            emoji.into(),
+        ))
+    }
+
+    /// Acknowledge every event up to and including `message_id`.
+    pub fn mark_read(&self, message_id: u64) -> Result<(), String> {
+        self.send(Payload {
+            event_type: PayloadEventType::Read,
+            message_id: Some(message_id),
+            ..Default::default()
+        })
+    }
+
+    /// Whole message and activity history of the chat.
+    pub async fn history(&self) -> Result<Vec<Payload>, String> {
+        self.get_json(&["history"], &[]).await
+    }
+
+    /// Message and activity history newer than the event `after`, up to 500 events oldest first.
+    pub async fn history_after(&self, after: u64) -> Result<Vec<Payload>, String> {
+        let batch: Payload = self
+            .get_json(&["poll"], &[("after", after), ("timeout", 0)])
+            .await?;
+        Ok(batch.history)
+    }
+
+    /// Usernames of connected chat members in alphabetical order.
+    pub async fn users(&self) -> Result<Vec<String>, String> {
+        self.get_json(&["users"], &[]).await
+    }
+
+    /// Number of messages the user of the client has not acknowledged yet.
+    pub async fn unread(&self) -> Result<UnreadCount, String> {
+        self.get_json(&["users", &self.config.username, "unread"], &[])
+            .await
+    }
+
+    /// Fetch JSON from the REST API path of the given segments, which are percent-encoded, so
+    /// that usernames can not change the route. Requests are authenticated as the user of the
+    /// client once it has joined.
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        segments: &[&str],
+        query: &[(&str, u64)],
+    ) -> Result<T, String> {
+        let rest_url = self
+            .config
+            .rest_url
+            .as_deref()
+            .ok_or("REST API URL is not configured")?;
+        let mut url = reqwest::Url::parse(rest_url).map_err(|e| e.to_string())?;
+        url.path_segments_mut()
+            .map_err(|_| format!("invalid REST API URL: {rest_url}"))?
+            .pop_if_empty()
+            .extend(segments);
+        let mut request = self.http.get(url).query(query);
+        if let Some(token) = self.session_token.lock().unwrap().as_deref() {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        serde_json::from_str(&body).map_err(|e| e.to_string())
+    }
+}
+
+impl Stream for Client {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+fn reaction(event_type: PayloadEventType, message_id: u64, emoji: String) -> Payload {
+    Payload {
+        event_type,
+        message_id: Some(message_id),
+        emoji: Some(emoji),
+        ..Default::default()
+    }
+}
+
+/// Open a WebSocket connection and join the chat.
+async fn open(config: &ClientConfig) -> Result<WebSocket, String> {
+    let mut request = config
+        .ws_url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| e.to_string())?;
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(Protocol::LATEST.subprotocol()),
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(|e| format!("unable to connect to {}: {e}", config.ws_url))?;
+    let join = Payload {
+        event_type: PayloadEventType::Connected,
+        username: config.username.clone(),
+        ..Default::default()
+    };
+    send(&mut socket, &join).await?;
+    Ok(socket)
+}
+
+async fn send(socket: &mut WebSocket, payload: &Payload) -> Result<(), String> {
+    let msg = Protocol::LATEST.encoding.encode(payload);
+    socket.send(msg).await.map_err(|e| e.to_string())
+}
+
+/// Forward events between the server and the client until the client is dropped, reconnecting
+/// whenever the connection is lost.
+async fn run_connection(
+    config: ClientConfig,
+    mut socket: WebSocket,
+    mut outgoing: mpsc::UnboundedReceiver<Payload>,
+    events: mpsc::UnboundedSender<Event>,
+    session_token: SessionToken,
+) {
+    loop {
+        let unsent = match exchange(&mut socket, &mut outgoing, &events, &session_token).await {
+            Interruption::ClientDropped => {
+                let _ = socket.close(None).await;
+                return;
+            }
+            Interruption::ConnectionLost { unsent } => unsent,
+        };
+        if events.send(Event::ConnectionLost).is_err() {
+            return;
+        }
+        let Some(mut delay) = config.reconnect_delay else {
+            return;
+        };
+        socket = loop {
+            tokio::time::sleep(delay).await;
+            match open(&config).await {
+                Ok(socket) => break socket,
+                Err(e) => tracing::info!(error = %e, "reconnection failed"),
+            }
+            delay = (delay * 2).min(config.max_reconnect_delay);
+        };
+        if events.send(Event::Reconnected).is_err() {
+            return;
+        }
+        // Payload that could not be sent before the connection was lost is sent again
+        if let Some(payload) = unsent {
+            if send(&mut socket, &payload).await.is_err() {
+                tracing::info!(
+                    event_type = ?payload.event_type,
+                    "payload is dropped after reconnecting"
+                );
+            }
+        }
+    }
+}
+
+enum Interruption {
+    ClientDropped,
+    /// `unsent` is the payload that failed to be sent, if any.
+    ConnectionLost {
+        unsent: Option<Box<Payload>>,
+    },
+}
+
+/// Exchange events over a connection until it is interrupted.
+async fn exchange(
+    socket: &mut WebSocket,
+    outgoing: &mut mpsc::UnboundedReceiver<Payload>,
+    events: &mpsc::UnboundedSender<Event>,
+    session_token: &SessionToken,
+) -> Interruption {
+    loop {
+        tokio::select! {
+            payload = outgoing.recv() => {
+                let Some(payload) = payload else {
+                    return Interruption::ClientDropped;
+                };
+                if send(socket, &payload).await.is_err() {
+                    return Interruption::ConnectionLost { unsent: Some(Box::new(payload)) };
+                }
+            }
+            msg = socket.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) if !msg.is_close() => msg,
+                    _ => return Interruption::ConnectionLost { unsent: None },
+                };
+                let Ok(payload) = Protocol::LATEST.decode(&msg) else {
+                    continue;
+                };
+                // Every connection has a session of its own
+                if payload.session_token.is_some() {
+                    session_token.lock().unwrap().clone_from(&payload.session_token);
+                }
+                if let Some(event) = Event::from_payload(payload) {
+                    if events.send(event).is_err() {
+                        return Interruption::ClientDropped;
+                    }
+                }
+            }
+        }
+    }
+}
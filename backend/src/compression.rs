@@ -0,0 +1,150 @@
+//! permessage-deflate WebSocket extension ([RFC 7692]), negotiated with clients that offer it in
+//! the `Sec-WebSocket-Extensions` header.
+//!
+//! Messages are compressed by the `Deflate` extension of soketto, which compresses and
+//! decompresses each message on its own. Clients are therefore asked for
+//! `client_no_context_takeover`, which browsers honor, as their messages could not be decompressed
+//! otherwise.
+//!
+//! [RFC 7692]: https://www.rfc-editor.org/rfc/rfc7692
+
+use soketto::{
+    base::{Header, OpCode},
+    connection::Mode,
+    extension::{deflate::Deflate, Extension, Param},
+    BoxedError, Storage,
+};
+
+use crate::configuration::CompressionConfig;
+
+pub const EXTENSION_NAME: &str = "permessage-deflate";
+
+const CLIENT_NO_CONTEXT_TAKEOVER: &str = "client_no_context_takeover";
+
+/// Compression of a single connection.
+#[derive(Debug)]
+pub struct PerMessageDeflate {
+    deflate: Deflate,
+    /// Parameters of the response to the offer of the client.
+    params: Vec<Param<'static>>,
+    threshold_bytes: usize,
+    max_message_bytes: usize,
+}
+
+impl PerMessageDeflate {
+    /// Accept the first acceptable offer in the comma-separated `Sec-WebSocket-Extensions`
+    /// header value of a client. Returns `None` if compression is disabled or no offer is
+    /// acceptable, in which case messages are sent uncompressed. Decompressed messages larger
+    /// than `max_message_bytes` are rejected.
+    pub fn negotiate(
+        offered: &str,
+        config: &CompressionConfig,
+        max_message_bytes: usize,
+    ) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        offered.split(',').find_map(|offer| {
+            let mut parts = offer.split(';').map(str::trim);
+            if parts.next() != Some(EXTENSION_NAME) {
+                return None;
+            }
+            let offered_params: Vec<Param> = parts
+                .map(|param| match param.split_once('=') {
+                    Some((name, value)) => {
+                        let mut param = Param::new(name.trim());
+                        param.set_value(Some(value.trim().trim_matches('"')));
+                        param
+                    }
+                    None => Param::new(param),
+                })
+                .collect();
+            let mut deflate = Deflate::new(Mode::Server);
+            deflate.configure(&offered_params).ok()?;
+            // Offers with unsupported parameters leave the extension disabled
+            if !deflate.is_enabled() {
+                return None;
+            }
+            let mut params: Vec<Param<'static>> = deflate
+                .params()
+                .iter()
+                .map(|param| {
+                    let mut owned = Param::new(param.name().to_string());
+                    owned.set_value(param.value().map(str::to_string));
+                    owned
+                })
+                .collect();
+            if !params
+                .iter()
+                .any(|param| param.name() == CLIENT_NO_CONTEXT_TAKEOVER)
+            {
+                params.push(Param::new(CLIENT_NO_CONTEXT_TAKEOVER));
+            }
+            Some(Self {
+                deflate,
+                params,
+                threshold_bytes: config.threshold_bytes,
+                max_message_bytes,
+            })
+        })
+    }
+
+    /// Value of the `Sec-WebSocket-Extensions` response header.
+    pub fn response_header(&self) -> String {
+        let mut header = EXTENSION_NAME.to_string();
+        for param in &self.params {
+            header.push_str("; ");
+            header.push_str(param.name());
+            if let Some(value) = param.value() {
+                header.push('=');
+                header.push_str(value);
+            }
+        }
+        header
+    }
+
+    /// Messages that are sent compressed.
+    pub fn should_compress(&self, data: &[u8]) -> bool {
+        data.len() >= self.threshold_bytes
+    }
+}
+
+impl Extension for PerMessageDeflate {
+    fn is_enabled(&self) -> bool {
+        self.deflate.is_enabled()
+    }
+
+    fn name(&self) -> &str {
+        EXTENSION_NAME
+    }
+
+    fn params(&self) -> &[Param<'_>] {
+        &self.params
+    }
+
+    // Negotiated during the handshake, see `PerMessageDeflate::negotiate()`
+    fn configure(&mut self, _params: &[Param]) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn encode(&mut self, header: &mut Header, data: &mut Storage) -> Result<(), BoxedError> {
+        if !matches!(header.opcode(), OpCode::Text | OpCode::Binary)
+            || !self.should_compress(data.as_ref())
+        {
+            return Ok(());
+        }
+        self.deflate.encode(header, data)
+    }
+
+    fn decode(&mut self, header: &mut Header, data: &mut Vec<u8>) -> Result<(), BoxedError> {
+        self.deflate.decode(header, data)?;
+        if data.len() > self.max_message_bytes {
+            return Err("decompressed message is too large".into());
+        }
+        Ok(())
+    }
+
+    fn reserved_bits(&self) -> (bool, bool, bool) {
+        self.deflate.reserved_bits()
+    }
+}
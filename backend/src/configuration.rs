@@ -1,5 +1,10 @@
-use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::{
+    deserialize_bool_from_anything, deserialize_number_from_string,
+    deserialize_option_number_from_string,
+};
 
 #[derive(Clone, Deserialize)]
 pub struct Config {
@@ -14,6 +19,281 @@ pub struct BackendConfig {
 
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub ws_port: u16,
+
+    /// Content filters applied to chat messages, in order of declaration.
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+
+    #[serde(default)]
+    pub attachments: AttachmentsConfig,
+
+    #[serde(default)]
+    pub link_previews: LinkPreviewConfig,
+
+    #[serde(default)]
+    pub retention: RetentionConfig,
+
+    /// permessage-deflate compression of WebSocket messages.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Number of latest history events sent to clients when they join the chat. History is not
+    /// replayed if zero.
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub history_replay_count: usize,
+
+    /// Secret expected in `Authorization: Bearer <token>` header of admin endpoints. Admin
+    /// endpoints are disabled if not set.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    /// Message of the day sent to clients joining the chat. Can be changed at runtime with admin
+    /// endpoints.
+    #[serde(default)]
+    pub motd: Option<String>,
+
+    /// Keys of bots and integrations that send messages with `POST /messages`. More keys can be
+    /// created at runtime with admin endpoints.
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+
+    /// In-process bots joining the chat on startup.
+    #[serde(default)]
+    pub bots: Vec<BotConfig>,
+
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AttachmentsConfig {
+    /// Directory of uploaded files. Relative paths are resolved from the working directory.
+    pub directory: String,
+
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_size_bytes: usize,
+
+    /// Limit of all uploaded files together, after which uploads are rejected.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_total_bytes: usize,
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        Self {
+            directory: "attachments".into(),
+            max_size_bytes: 10 * 1024 * 1024,
+            max_total_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterConfig {
+    MaxLength {
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        max_chars: usize,
+    },
+    Profanity {
+        /// Path of word list file relative to the configuration directory.
+        word_list: String,
+        action: ProfanityAction,
+    },
+    Links {
+        mode: LinkFilterMode,
+        #[serde(default)]
+        allowed_domains: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfanityAction {
+    /// Mask forbidden words with asterisks.
+    Redact,
+    /// Drop the whole message.
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkFilterMode {
+    /// Remove every link.
+    Strip,
+    /// Remove links except the ones pointing to allowed domains or their subdomains.
+    Allowlist,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct LinkPreviewConfig {
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub enabled: bool,
+
+    /// Time limit of fetching a single page.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_ms: u64,
+
+    /// Number of URLs whose previews are kept in memory.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cache_capacity: usize,
+
+    /// Further URLs of a message are not unfurled.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_links_per_message: usize,
+
+    /// Time after which URLs that had no preview are fetched again.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failed_lookup_ttl_ms: u64,
+}
+
+impl Default for LinkPreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_ms: 5000,
+            cache_capacity: 1000,
+            max_links_per_message: 3,
+            failed_lookup_ttl_ms: 5 * 60 * 1000,
+        }
+    }
+}
+
+/// Secret `key` of a bot or integration, whose messages are sent as user `name`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+}
+
+/// Bot of kind `type`, registered in `bot::BotRegistry`, joining the chat as user `name`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BotConfig {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+}
+
+/// Limits of message history, enforced periodically. Unset limits are not enforced.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_count: Option<usize>,
+
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_age_secs: Option<u64>,
+
+    /// Whether `connected` and `disconnected` events are saved to history next to messages.
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub keep_presence_events: bool,
+
+    /// Time between enforcements of the limits.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_count: None,
+            max_age_secs: None,
+            keep_presence_events: true,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Delivery of outgoing webhooks, which are registered with admin endpoints. Relative paths are
+/// resolved from the working directory.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookConfig {
+    /// File of registered webhooks. Webhooks are kept only in memory if not set.
+    #[serde(default)]
+    pub file: Option<String>,
+
+    /// JSON Lines file of deliveries given up after `max_attempts`. Given up deliveries are only
+    /// logged if not set.
+    #[serde(default)]
+    pub dead_letter_file: Option<String>,
+
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+
+    /// Wait before the first retry, doubled for each further retry.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub initial_backoff_ms: u64,
+
+    /// Time limit of a single delivery attempt.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            dead_letter_file: None,
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// Log output of the server, written to standard error.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+
+    /// Filter directives like `chat_backend=trace`, overridden by `RUST_LOG` environment variable.
+    pub filter: String,
+
+    /// Whether chat message bodies are included in logs. They are redacted by default for privacy.
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub message_bodies: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            filter: "chat_backend=trace".into(),
+            message_bodies: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, including fields of the enclosing spans.
+    Json,
+}
+
+/// Negotiation of the permessage-deflate WebSocket extension with clients that offer it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CompressionConfig {
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub enabled: bool,
+
+    /// Messages smaller than this are sent uncompressed, as compressing them saves little.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub threshold_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_bytes: 256,
+        }
+    }
 }
 
 pub enum Environment {
@@ -45,9 +325,14 @@ impl TryFrom<String> for Environment {
     }
 }
 
-pub fn get_config() -> Result<Config, config::ConfigError> {
+/// Directory of YAML configuration files and other files referenced by them.
+pub fn configuration_directory() -> PathBuf {
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let configuration_directory = base_path.join("../config");
+    base_path.join("../config")
+}
+
+pub fn get_config() -> Result<Config, config::ConfigError> {
+    let configuration_directory = configuration_directory();
     // Detect the running environment.
     // Default to `local` if unspecified.
     let environment: Environment = std::env::var("CHAT_APP_ENVIRONMENT")
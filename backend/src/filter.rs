@@ -0,0 +1,247 @@
+//! Content filtering pipeline applied to chat messages before they are broadcasted.
+//!
+//! Filters run in the order they are declared in configuration. Each filter either passes the
+//! (possibly rewritten) message text on to the next filter or rejects the message altogether,
+//! in which case it is not broadcasted nor saved to history.
+
+use std::{collections::HashSet, fmt, path::Path};
+
+use crate::{
+    configuration::{FilterConfig, LinkFilterMode, ProfanityAction},
+    unfurl::TRAILING_PUNCTUATION,
+};
+
+/// Single stage of the message filtering pipeline.
+pub trait MessageFilter: Send + Sync {
+    /// Returns the message text to pass on to the next stage, or the reason of rejection.
+    fn apply(&self, message: String) -> Result<String, String>;
+}
+
+/// Ordered chain of filters that a message goes through.
+#[derive(Default)]
+pub struct MessageFilterChain {
+    filters: Vec<Box<dyn MessageFilter>>,
+}
+
+impl MessageFilterChain {
+    pub fn new(filters: Vec<Box<dyn MessageFilter>>) -> Self {
+        Self { filters }
+    }
+
+    /// Build filter chain declared in configuration. Files referenced by filters (like word
+    /// lists) are resolved relative to `config_directory`.
+    pub fn from_config(configs: &[FilterConfig], config_directory: &Path) -> Result<Self, String> {
+        let mut filters: Vec<Box<dyn MessageFilter>> = Vec::with_capacity(configs.len());
+        for config in configs {
+            let filter: Box<dyn MessageFilter> = match config {
+                FilterConfig::MaxLength { max_chars } => Box::new(MaxLengthFilter::new(*max_chars)),
+                FilterConfig::Profanity { word_list, action } => {
+                    let path = config_directory.join(word_list);
+                    let contents = std::fs::read_to_string(&path)
+                        .map_err(|e| format!("unable to read word list {}: {e}", path.display()))?;
+                    Box::new(ProfanityFilter::from_word_list(&contents, *action))
+                }
+                FilterConfig::Links {
+                    mode,
+                    allowed_domains,
+                } => Box::new(LinkFilter::new(*mode, allowed_domains.clone())),
+            };
+            filters.push(filter);
+        }
+
+        Ok(Self::new(filters))
+    }
+
+    pub fn apply(&self, message: String) -> Result<String, String> {
+        self.filters
+            .iter()
+            .try_fold(message, |message, filter| filter.apply(message))
+    }
+}
+
+impl fmt::Debug for MessageFilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageFilterChain")
+            .field("len", &self.filters.len())
+            .finish()
+    }
+}
+
+/// Truncates messages longer than the allowed number of characters.
+pub struct MaxLengthFilter {
+    max_chars: usize,
+}
+
+impl MaxLengthFilter {
+    pub fn new(max_chars: usize) -> Self {
+        Self { max_chars }
+    }
+}
+
+impl MessageFilter for MaxLengthFilter {
+    fn apply(&self, message: String) -> Result<String, String> {
+        match message.char_indices().nth(self.max_chars) {
+            Some((byte_index, _)) => Ok(message[..byte_index].to_string()),
+            None => Ok(message),
+        }
+    }
+}
+
+/// Matches words of a message against a list of forbidden words, case-insensitively.
+pub struct ProfanityFilter {
+    words: HashSet<String>,
+    action: ProfanityAction,
+}
+
+impl ProfanityFilter {
+    pub fn new(words: impl IntoIterator<Item = String>, action: ProfanityAction) -> Self {
+        Self {
+            words: words.into_iter().map(|w| w.to_lowercase()).collect(),
+            action,
+        }
+    }
+
+    /// Parse word list with one word per line. Empty lines and lines starting with `#` are
+    /// skipped.
+    pub fn from_word_list(contents: &str, action: ProfanityAction) -> Self {
+        let words = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from);
+        Self::new(words, action)
+    }
+}
+
+impl MessageFilter for ProfanityFilter {
+    fn apply(&self, message: String) -> Result<String, String> {
+        let mut filtered = String::with_capacity(message.len());
+        let mut found = false;
+        for (is_word, part) in split_words(&message) {
+            if is_word && self.words.contains(&part.to_lowercase()) {
+                found = true;
+                filtered.extend(std::iter::repeat_n('*', part.chars().count()));
+            } else {
+                filtered.push_str(part);
+            }
+        }
+
+        match (found, self.action) {
+            (true, ProfanityAction::Reject) => Err("message contains profanity".into()),
+            (true, ProfanityAction::Redact) => Ok(filtered),
+            (false, _) => Ok(message),
+        }
+    }
+}
+
+/// Split text into alternating runs of word and non-word characters, keeping every character so
+/// that the original text can be reassembled.
+fn split_words(text: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_word = None;
+    for (i, c) in text.char_indices() {
+        let is_word = c.is_alphanumeric();
+        match in_word {
+            Some(current) if current != is_word => {
+                parts.push((current, &text[start..i]));
+                start = i;
+            }
+            _ => (),
+        }
+        in_word = Some(is_word);
+    }
+    if let Some(current) = in_word {
+        parts.push((current, &text[start..]));
+    }
+    parts
+}
+
+/// Removes links from messages, optionally keeping the ones pointing to allowed domains.
+pub struct LinkFilter {
+    mode: LinkFilterMode,
+    allowed_domains: Vec<String>,
+}
+
+impl LinkFilter {
+    const REPLACEMENT: &'static str = "[link removed]";
+
+    pub fn new(mode: LinkFilterMode, allowed_domains: Vec<String>) -> Self {
+        Self {
+            mode,
+            allowed_domains: allowed_domains
+                .into_iter()
+                .map(|d| d.to_lowercase())
+                .collect(),
+        }
+    }
+
+    fn is_allowed(&self, link: &str) -> bool {
+        match self.mode {
+            LinkFilterMode::Strip => false,
+            LinkFilterMode::Allowlist => {
+                let host = link_host(link).to_lowercase();
+                self.allowed_domains
+                    .iter()
+                    .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")))
+            }
+        }
+    }
+}
+
+impl MessageFilter for LinkFilter {
+    fn apply(&self, message: String) -> Result<String, String> {
+        // Links may follow any whitespace, which is kept as is
+        let mut filtered = String::with_capacity(message.len());
+        for part in message.split_inclusive(char::is_whitespace) {
+            let token = part.trim_end_matches(char::is_whitespace);
+            match link_start(token) {
+                // Punctuation around the link, like in `(https://example.com).`, is kept
+                Some(start) => {
+                    let link = token[start..].trim_end_matches(TRAILING_PUNCTUATION);
+                    filtered.push_str(&token[..start]);
+                    if self.is_allowed(link) {
+                        filtered.push_str(link);
+                    } else {
+                        filtered.push_str(Self::REPLACEMENT);
+                    }
+                    filtered.push_str(&token[start + link.len()..]);
+                }
+                None => filtered.push_str(token),
+            }
+            filtered.push_str(&part[token.len()..]);
+        }
+        Ok(filtered)
+    }
+}
+
+/// Byte offset of the first link in a token, which starts at the beginning of the token or
+/// after punctuation, but not within a word.
+fn link_start(token: &str) -> Option<usize> {
+    let mut previous = None;
+    for (index, c) in token.char_indices() {
+        if !previous.is_some_and(char::is_alphanumeric) {
+            let rest = &token[index..];
+            let starts_with = |prefix: &str| {
+                rest.get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+            };
+            if ["http://", "https://", "www."].into_iter().any(starts_with) {
+                return Some(index);
+            }
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+/// Extract host part of a link, e.g. `example.com` from `https://user@example.com:80/path`.
+fn link_host(link: &str) -> &str {
+    let without_scheme = link.split_once("://").map_or(link, |(_, rest)| rest);
+    let authority = without_scheme
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    let host_and_port = authority.rsplit('@').next().unwrap_or_default();
+    host_and_port.split(':').next().unwrap_or_default()
+}
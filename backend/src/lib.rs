@@ -1,50 +1,413 @@
 //! Collection of POD (Plain Old Data) types shared by both REST API and WebSocket components.
 
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+    sync::Arc,
+    time::Instant,
+};
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc::UnboundedSender, Mutex};
+use tokio::sync::{mpsc::UnboundedSender, watch, Mutex, MutexGuard};
+use tokio_tungstenite::tungstenite::Message;
 
+use crate::{
+    attachment::{Attachment, AttachmentStore},
+    configuration::{ApiKey, CompressionConfig, RetentionConfig},
+    filter::MessageFilterChain,
+    metrics::Metrics,
+    protocol::Protocol,
+    search::SearchIndex,
+    unfurl::{LinkPreview, LinkUnfurler},
+    webhook::WebhookRegistry,
+};
+
+pub mod archive;
+pub mod attachment;
+pub mod bot;
+pub mod chat;
+pub mod client;
+pub mod compression;
 pub mod configuration;
+pub mod filter;
+pub mod logging;
+pub mod metrics;
+pub mod protocol;
 pub mod rest_server;
+pub mod retention;
+pub mod search;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod unfurl;
+pub mod webhook;
 pub mod ws_server;
 
 /// Message payload that is passed around on WebSocket as JSON string.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Payload {
     pub event_type: PayloadEventType,
     pub username: String,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
+
+    /// Identifier assigned by the server to events saved to history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+
+    /// Time of saving event to history as Unix timestamp in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    /// History event referred to by reaction and read events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+
+    /// Reactions received by a message, or by the message referred to by `message_id` for
+    /// reaction count updates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+
+    /// Message that this message is a reply to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+
+    /// Number of replies received by a message.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reply_count: usize,
+
+    /// Message is sent by a bot or integration instead of a chat member.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub bot: bool,
+
+    /// Users mentioned in a message with `@username`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<String>,
+
+    /// Files uploaded with `POST /attachments` that a message refers to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+
+    /// Previews of links in a message, or in the message referred to by `message_id` for
+    /// preview events.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previews: Vec<LinkPreview>,
+
+    /// Latest history events in chronological order, sent in `history_batch` events.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Payload>,
+
+    /// Negotiated protocol version, sent in `hello` events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+
+    /// Features supported by the server, sent in `hello` events.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+
+    /// Secret of the session of a member for authenticating REST API requests, sent in `hello`
+    /// events. Valid once the client has joined the chat.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Number of messages a user has not acknowledged yet, available for
+/// `GET /users/{name}/unread` endpoint response.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct UnreadCount {
+    pub username: String,
+    pub last_read_id: Option<u64>,
+    pub unread: usize,
 }
 
+/// Member of the chat as listed by `GET /admin/users` endpoint.
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectedUser {
+    pub username: String,
+    pub transport: Transport,
+    /// Remote address of WebSocket connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Subprotocol that events are sent to the member with.
+    pub protocol: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    WebSocket,
+    /// Server-Sent Events stream of `GET /events`.
+    Http,
+    Bot,
+}
+
+/// Overview of the server, available for `GET /admin/stats` endpoint response.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServerStats {
+    pub connected_users: usize,
+    pub websocket_clients: usize,
+    pub http_sessions: usize,
+    pub bots: usize,
+    pub history_events: usize,
+    pub messages: usize,
+    pub last_history_id: u64,
+    pub banned_users: usize,
+    pub api_keys: usize,
+    pub webhooks: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum PayloadEventType {
     Connected,
     Disconnected,
+    #[default]
     Message,
+    /// Add `emoji` reaction of user to message `message_id`.
+    React,
+    /// Remove `emoji` reaction of user from message `message_id`.
+    Unreact,
+    /// Updated reaction counts of message `message_id`, sent by the server.
+    Reactions,
+    /// Updated `reply_count` of message `message_id`, sent by the server.
+    Replies,
+    /// User has seen every event up to and including `message_id`.
+    Read,
+    /// User is mentioned in message `message_id` sent by `username`, sent by the server.
+    Mention,
+    /// Link previews of message `message_id` became available, sent by the server.
+    #[serde(rename = "message_preview")]
+    MessagePreview,
+    /// Latest `history` replayed to a client after joining, sent by the server. Events newer
+    /// than `message_id` are delivered live.
+    #[serde(rename = "history_batch")]
+    HistoryBatch,
+    /// Negotiated `protocol` version and `capabilities` of the server, sent by the server before
+    /// any other event.
+    Hello,
+    /// Announcement or notice of the server in `message`, like the message of the day, sent by
+    /// the server.
+    System,
+}
+
+/// Summary of a single emoji reaction on a message.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: usize,
+    pub usernames: Vec<String>,
 }
 
 /// "Global" state of server application shared between REST API and WebSocket components.
 #[derive(Debug, Default)]
 pub struct ServerState {
     /// Flat store of all available clients for easy lookup during accepting client connections.
-    pub clients: HashMap<SocketAddr, ChatClient>,
+    pub clients: HashMap<ClientId, ChatClient>,
+
+    /// Members of the chat by the secret token of their session, which authenticates their REST
+    /// API requests.
+    pub sessions: HashMap<String, ClientId>,
+
+    /// Identifier of the latest WebSocket connection or HTTP session, recorded in logs.
+    pub last_connection_id: u64,
 
     /// List of messages and connection status event logs in chronological order, available for
     /// `GET /history` endpoint response.
     pub history: Vec<Payload>,
+
+    /// Identifier of the last history event acknowledged by each user with a read event.
+    pub read_positions: HashMap<String, u64>,
+
+    /// Identifier of the latest event saved to history.
+    pub last_history_id: u64,
+
+    /// Identifier of the latest broadcasted event, watched by long-polling clients.
+    pub history_updates: watch::Sender<u64>,
+
+    /// Content filters that chat messages go through before being broadcasted.
+    pub message_filters: MessageFilterChain,
+
+    /// Uploaded files that messages can refer to.
+    pub attachments: AttachmentStore,
+
+    /// Link preview fetching, disabled if not set.
+    pub link_unfurler: Option<Arc<LinkUnfurler>>,
+
+    /// Full-text index of chat messages in history.
+    pub search_index: SearchIndex,
+
+    /// Limits of history, enforced by background task.
+    pub retention: RetentionConfig,
+
+    /// Secret of admin endpoints, which are disabled if not set.
+    pub admin_token: Option<String>,
+
+    /// Message of the day sent privately to clients joining the chat, disabled if not set.
+    pub motd: Option<String>,
+
+    /// Usernames that are not allowed to join the chat, managed with admin endpoints.
+    pub banned_users: BTreeSet<String>,
+
+    /// Keys of bots and integrations sending messages with `POST /messages`.
+    pub api_keys: Vec<ApiKey>,
+
+    /// Receivers of chat events registered with admin endpoints.
+    pub webhooks: WebhookRegistry,
+
+    /// Number of latest history events replayed to joining clients, disabled if zero.
+    pub history_replay_count: usize,
+
+    /// permessage-deflate compression offered to WebSocket clients.
+    pub compression: CompressionConfig,
+
+    /// Prometheus metrics exposed by `GET /metrics` endpoint.
+    pub metrics: Arc<Metrics>,
+
+    /// Whether chat message bodies are included in logs instead of being redacted.
+    pub log_message_bodies: bool,
+}
+
+impl ServerState {
+    /// Identify a new WebSocket connection or HTTP session.
+    pub fn next_connection_id(&mut self) -> u64 {
+        self.last_connection_id += 1;
+        self.last_connection_id
+    }
+
+    /// Look up history event by its identifier.
+    pub fn history_entry(&self, id: u64) -> Option<&Payload> {
+        self.history_index(id).map(|index| &self.history[index])
+    }
+
+    /// Look up history event by its identifier for modification.
+    pub fn history_entry_mut(&mut self, id: u64) -> Option<&mut Payload> {
+        self.history_index(id).map(|index| &mut self.history[index])
+    }
+
+    /// Count messages sent by others since the last read position of `username`.
+    pub fn unread_count(&self, username: &str) -> UnreadCount {
+        let last_read_id = self.read_positions.get(username).copied();
+        let unread = self
+            .history
+            .iter()
+            .filter(|entry| {
+                entry.event_type == PayloadEventType::Message
+                    && entry.username != username
+                    && entry.id > last_read_id
+            })
+            .count();
+        UnreadCount {
+            username: username.into(),
+            last_read_id,
+            unread,
+        }
+    }
+
+    /// Members of the chat in alphabetical order of usernames.
+    pub fn connected_users(&self) -> Vec<ConnectedUser> {
+        let mut users: Vec<ConnectedUser> = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| {
+                let (transport, address) = match client_id {
+                    ClientId::WebSocket(address) => {
+                        (Transport::WebSocket, Some(address.to_string()))
+                    }
+                    ClientId::Session(_) => (Transport::Http, None),
+                    ClientId::Bot(_) => (Transport::Bot, None),
+                };
+                ConnectedUser {
+                    username: client.username.clone(),
+                    transport,
+                    address,
+                    protocol: client.protocol.subprotocol().into(),
+                }
+            })
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        users
+    }
+
+    pub fn stats(&self) -> ServerStats {
+        let count_clients = |predicate: fn(&ClientId) -> bool| {
+            self.clients.keys().filter(|id| predicate(id)).count()
+        };
+        ServerStats {
+            connected_users: self.clients.len(),
+            websocket_clients: count_clients(|id| matches!(id, ClientId::WebSocket(_))),
+            http_sessions: count_clients(|id| matches!(id, ClientId::Session(_))),
+            bots: count_clients(|id| matches!(id, ClientId::Bot(_))),
+            history_events: self.history.len(),
+            messages: self
+                .history
+                .iter()
+                .filter(|payload| payload.event_type == PayloadEventType::Message)
+                .count(),
+            last_history_id: self.last_history_id,
+            banned_users: self.banned_users.len(),
+            api_keys: self.api_keys.len(),
+            webhooks: self.webhooks.list().len(),
+        }
+    }
+
+    fn history_index(&self, id: u64) -> Option<usize> {
+        // History is in chronological order, so identifiers are ascending
+        self.history
+            .binary_search_by_key(&Some(id), |payload| payload.id)
+            .ok()
+    }
 }
 pub type SharedServerState = Arc<Mutex<ServerState>>;
 
+/// Lock the shared server state, recording the time spent waiting for it in metrics.
+pub async fn lock_state(server_state: &SharedServerState) -> MutexGuard<'_, ServerState> {
+    let start = Instant::now();
+    let guard = server_state.lock().await;
+    guard
+        .metrics
+        .lock_wait_seconds
+        .observe(start.elapsed().as_secs_f64());
+    guard
+}
+
+/// Current time as Unix timestamp in milliseconds.
+pub fn unix_timestamp_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Connection of a chat member, regardless of its transport.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClientId {
+    /// WebSocket connection from a remote address.
+    WebSocket(SocketAddr),
+    /// Server-Sent Events stream of an HTTP session, see `rest_server`.
+    Session(u64),
+    /// In-process bot, by its position in configuration, see `bot`.
+    Bot(usize),
+}
+
 #[derive(Debug)]
 pub struct ChatClient {
     pub username: String,
     pub tx: Tx,
+
+    /// Protocol that events are translated and encoded to before sending them to the client.
+    pub protocol: Protocol,
 }
 
-// Kept as String instead of Payload to avoid wasted repeated deserializations for each
-// broadcast target.
-pub type Tx = UnboundedSender<String>;
+// Kept as encoded WebSocket message instead of Payload to avoid wasted repeated serializations for
+// each broadcast target.
+pub type Tx = UnboundedSender<Message>;
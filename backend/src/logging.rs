@@ -0,0 +1,34 @@
+//! Log output of the server with `tracing`. Events carry structured fields, and are recorded
+//! within spans of client connections and REST API requests, so that every event of a connection
+//! can be found by its client address, username or connection ID.
+
+use tokio_tungstenite::tungstenite::Message;
+use tracing_subscriber::EnvFilter;
+
+use crate::configuration::{LogFormat, LoggingConfig};
+
+/// Replacement of chat message bodies in logs, unless logging them is enabled.
+const REDACTED: &str = "[redacted]";
+
+/// Install the global subscriber writing log events to standard error.
+pub fn init(config: &LoggingConfig) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.filter));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match config.format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+/// Content of a message exchanged with a client for logs. Text is redacted unless
+/// `message_bodies` is enabled, as it may contain what members write in the chat.
+pub fn message_body(msg: &Message, message_bodies: bool) -> &str {
+    match msg {
+        Message::Text(_) if !message_bodies => REDACTED,
+        Message::Text(text) => text.as_str(),
+        _ => "<binary>",
+    }
+}
@@ -1,17 +1,51 @@
-//! Server application entrypoint that acts as logger setup, REST API and WebSocket listener
+//! Server application entrypoint that acts as log output setup, REST API and WebSocket listener
 //! startup and CTRL+C interrupt handling.
 
-use chat_backend::{configuration, rest_server, ws_server, SharedServerState};
-use env_logger::Env;
+use std::sync::Arc;
+
+use chat_backend::{
+    attachment::AttachmentStore,
+    bot::{self, BotRegistry},
+    configuration,
+    filter::MessageFilterChain,
+    logging, rest_server, retention,
+    unfurl::LinkUnfurler,
+    webhook::WebhookRegistry,
+    ws_server, ServerState, SharedServerState,
+};
+use tokio::sync::Mutex;
 
 #[tokio::main]
 async fn main() {
-    // TODO: Switch to `tracing` crate and ecosystem once project becomes larger in scope
-    let log_env = Env::default().default_filter_or("chat_backend=trace");
-    env_logger::Builder::from_env(log_env).init();
-
     let config = configuration::get_config().expect("failed to read configuration");
-    let server_state = SharedServerState::default();
+    logging::init(&config.backend.logging);
+
+    let message_filters = MessageFilterChain::from_config(
+        &config.backend.filters,
+        &configuration::configuration_directory(),
+    )
+    .expect("failed to set up message filters");
+    let bots = BotRegistry::with_builtin_bots()
+        .create(&config.backend.bots)
+        .expect("failed to set up bots");
+    let link_previews = &config.backend.link_previews;
+    let server_state: SharedServerState = Arc::new(Mutex::new(ServerState {
+        message_filters,
+        attachments: AttachmentStore::new(&config.backend.attachments),
+        link_unfurler: link_previews
+            .enabled
+            .then(|| Arc::new(LinkUnfurler::from_config(link_previews))),
+        retention: config.backend.retention.clone(),
+        admin_token: config.backend.admin_token.clone(),
+        motd: config.backend.motd.clone(),
+        api_keys: config.backend.api_keys.clone(),
+        webhooks: WebhookRegistry::from_config(&config.backend.webhooks)
+            .expect("failed to load webhooks"),
+        history_replay_count: config.backend.history_replay_count,
+        compression: config.backend.compression.clone(),
+        log_message_bodies: config.backend.logging.message_bodies,
+        ..Default::default()
+    }));
 
     let rest_address = format!("{}:{}", config.host, config.backend.rest_port);
     let rest_listener =
@@ -21,23 +55,28 @@ async fn main() {
         server_state.clone(),
     ));
 
+    let retention_task = tokio::spawn(retention::run_retention_task(server_state.clone()));
+    tokio::spawn(bot::run_bots(server_state.clone(), bots));
+
     let ws_address = format!("{}:{}", config.host, config.backend.ws_port);
     let ws_listener = tokio::net::TcpListener::bind(&ws_address)
         .await
         .expect("unable to bind WebSocket port");
     let ws_task = tokio::spawn(ws_server::run_ws_server(ws_listener, server_state.clone()));
 
-    log::info!("real-time chat server backend is functional");
-    log::info!("REST API listener is on {}", &rest_address);
-    log::info!("WebSocket listener is on {}", &ws_address);
+    tracing::info!(
+        %rest_address,
+        %ws_address,
+        "real-time chat server backend is functional"
+    );
 
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
-            log::info!("CTRL+C received, initiating graceful shutdown...");
+            tracing::info!("CTRL+C received, initiating graceful shutdown...");
         }
-        res = async { tokio::try_join!(rest_task, ws_task) } => {
+        res = async { tokio::try_join!(rest_task, ws_task, retention_task) } => {
             if let Err(e) = res {
-                log::error!("abnormal server shutdown: {e}");
+                tracing::error!(error = %e, "abnormal server shutdown");
             }
         }
     }
@@ -0,0 +1,151 @@
+//! Prometheus metrics of the server, exposed by the `GET /metrics` endpoint of the REST API.
+//!
+//! Counters and histograms are updated as events flow through the chat, while gauges describing
+//! the current state are computed when metrics are scraped.
+
+use std::fmt;
+
+use crate::ServerState;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+
+pub struct Metrics {
+    registry: Registry,
+    connected_clients: IntGaugeVec,
+    history_events: IntGauge,
+    /// Chat messages sent by members, including rejected ones.
+    pub messages_received: IntCounter,
+    /// Broadcasted events queued for delivery to members of the chat.
+    pub messages_sent: IntCounter,
+    /// Time of queueing a broadcasted event for every member of the chat.
+    pub broadcast_fanout_seconds: Histogram,
+    /// Number of events waiting in the outbound queue of a client, sampled whenever its
+    /// connection takes the next event from the queue.
+    pub outbound_queue_depth: Histogram,
+    pub handshake_failures: IntCounter,
+    /// Time spent waiting for the lock of the shared server state, see [`crate::lock_state()`].
+    pub lock_wait_seconds: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let connected_clients = IntGaugeVec::new(
+            Opts::new(
+                "chat_connected_clients",
+                "Members of the chat by transport.",
+            ),
+            &["transport"],
+        )
+        .unwrap();
+        let history_events =
+            IntGauge::new("chat_history_events", "Events saved to history.").unwrap();
+        let messages_received = IntCounter::new(
+            "chat_messages_received_total",
+            "Chat messages sent by members.",
+        )
+        .unwrap();
+        let messages_sent = IntCounter::new(
+            "chat_messages_sent_total",
+            "Broadcasted events queued for delivery to members.",
+        )
+        .unwrap();
+        let broadcast_fanout_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "chat_broadcast_fanout_seconds",
+                "Time of queueing a broadcasted event for every member.",
+            )
+            .buckets(exponential_buckets(0.000_01, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let outbound_queue_depth = Histogram::with_opts(
+            HistogramOpts::new(
+                "chat_outbound_queue_depth",
+                "Events waiting in the outbound queue of a client when it is sending the next one.",
+            )
+            .buckets(vec![0.0, 1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0]),
+        )
+        .unwrap();
+        let handshake_failures = IntCounter::new(
+            "chat_websocket_handshake_failures_total",
+            "WebSocket connections closed before completing the opening handshake.",
+        )
+        .unwrap();
+        let lock_wait_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "chat_state_lock_wait_seconds",
+                "Time spent waiting for the lock of the shared server state.",
+            )
+            .buckets(exponential_buckets(0.000_01, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(connected_clients.clone()))
+            .unwrap();
+        registry.register(Box::new(history_events.clone())).unwrap();
+        registry
+            .register(Box::new(messages_received.clone()))
+            .unwrap();
+        registry.register(Box::new(messages_sent.clone())).unwrap();
+        registry
+            .register(Box::new(broadcast_fanout_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(outbound_queue_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(handshake_failures.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(lock_wait_seconds.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            connected_clients,
+            history_events,
+            messages_received,
+            messages_sent,
+            broadcast_fanout_seconds,
+            outbound_queue_depth,
+            handshake_failures,
+            lock_wait_seconds,
+        }
+    }
+
+    /// Render all metrics in Prometheus text format, updating gauges from current server state.
+    pub fn encode(&self, server_state: &ServerState) -> String {
+        let stats = server_state.stats();
+        for (transport, count) in [
+            ("websocket", stats.websocket_clients),
+            ("http", stats.http_sessions),
+            ("bot", stats.bots),
+        ] {
+            self.connected_clients
+                .with_label_values(&[transport])
+                .set(count as i64);
+        }
+        self.history_events.set(stats.history_events as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("unable to encode metrics");
+        String::from_utf8(buffer).expect("metrics are not valid UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
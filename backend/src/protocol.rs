@@ -0,0 +1,249 @@
+//! Versions and encodings of the WebSocket wire protocol, negotiated with the
+//! `Sec-WebSocket-Protocol` header.
+//!
+//! Clients that do not request a protocol speak `chat.v1`, the original protocol of bare
+//! `connected`, `disconnected` and `message` events. Events are translated for such clients, so
+//! that new event types and fields can be added to the latest version without breaking them.
+//!
+//! The latest version is also available in binary MessagePack (`chat.v2.msgpack`) and CBOR
+//! (`chat.v2.cbor`) encodings, sent in binary frames. JSON in text frames stays the default.
+
+use std::collections::HashMap;
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{Payload, PayloadEventType, ServerState};
+
+/// Sender of `system` events translated to messages for `chat.v1` clients.
+pub const V1_SYSTEM_USERNAME: &str = "server";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// Original protocol, assumed if a client does not request a protocol.
+    #[default]
+    V1,
+    /// Every event type and field of [`Payload`], starting with a `hello` event.
+    V2,
+}
+
+impl ProtocolVersion {
+    pub fn supports(self, event_type: &PayloadEventType) -> bool {
+        match self {
+            Self::V1 => matches!(
+                event_type,
+                PayloadEventType::Connected
+                    | PayloadEventType::Disconnected
+                    | PayloadEventType::Message
+            ),
+            Self::V2 => true,
+        }
+    }
+
+    /// Translate an event of the latest version to this version. Returns `None` if the event has
+    /// no equivalent in this version.
+    pub fn translate(self, payload: &Payload) -> Option<Payload> {
+        // Announcements are too important to drop, so they appear as messages of the server
+        if self == Self::V1 && payload.event_type == PayloadEventType::System {
+            return Some(Payload {
+                event_type: PayloadEventType::Message,
+                username: V1_SYSTEM_USERNAME.into(),
+                message: payload.message.clone(),
+                ..Default::default()
+            });
+        }
+        if !self.supports(&payload.event_type) {
+            return None;
+        }
+        match self {
+            Self::V1 => Some(Payload {
+                event_type: payload.event_type.clone(),
+                username: payload.username.clone(),
+                message: payload.message.clone(),
+                ..Default::default()
+            }),
+            Self::V2 => Some(payload.clone()),
+        }
+    }
+}
+
+/// Serialization format of events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// JSON in text frames.
+    #[default]
+    Json,
+    /// MessagePack maps with field names in binary frames.
+    MessagePack,
+    /// CBOR maps in binary frames.
+    Cbor,
+}
+
+impl Encoding {
+    pub fn encode(self, payload: &Payload) -> Message {
+        match self {
+            Self::Json => Message::text(serde_json::to_string(payload).unwrap()),
+            Self::MessagePack => Message::binary(rmp_serde::to_vec_named(payload).unwrap()),
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(payload, &mut bytes).unwrap();
+                Message::binary(bytes)
+            }
+        }
+    }
+
+    /// Parse an event from a frame. Text frames are always JSON.
+    pub fn decode(self, msg: &Message) -> Result<Payload, String> {
+        match (msg, self) {
+            (Message::Text(text), _) => serde_json::from_str(text).map_err(|e| e.to_string()),
+            (Message::Binary(bytes), Self::MessagePack) => {
+                rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+            }
+            (Message::Binary(bytes), Self::Cbor) => {
+                ciborium::from_reader(bytes.as_ref()).map_err(|e| e.to_string())
+            }
+            (Message::Binary(_), Self::Json) => {
+                Err("binary frames require a binary encoding".into())
+            }
+            _ => Err("not a data frame".into()),
+        }
+    }
+}
+
+/// Negotiated version and encoding of a client connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Protocol {
+    pub version: ProtocolVersion,
+    pub encoding: Encoding,
+}
+
+impl Protocol {
+    pub const LATEST: Self = Self {
+        version: ProtocolVersion::V2,
+        encoding: Encoding::Json,
+    };
+    const ALL: [Self; 4] = [
+        Self {
+            version: ProtocolVersion::V1,
+            encoding: Encoding::Json,
+        },
+        Self::LATEST,
+        Self {
+            version: ProtocolVersion::V2,
+            encoding: Encoding::MessagePack,
+        },
+        Self {
+            version: ProtocolVersion::V2,
+            encoding: Encoding::Cbor,
+        },
+    ];
+
+    /// Name of the protocol in `Sec-WebSocket-Protocol` header.
+    pub fn subprotocol(self) -> &'static str {
+        match (self.version, self.encoding) {
+            // Original protocol is only available as JSON
+            (ProtocolVersion::V1, _) => "chat.v1",
+            (ProtocolVersion::V2, Encoding::Json) => "chat.v2",
+            (ProtocolVersion::V2, Encoding::MessagePack) => "chat.v2.msgpack",
+            (ProtocolVersion::V2, Encoding::Cbor) => "chat.v2.cbor",
+        }
+    }
+
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|protocol| protocol.subprotocol() == name)
+    }
+
+    /// Pick a protocol of the latest supported version from the comma-separated
+    /// `Sec-WebSocket-Protocol` header value of a client. Encodings of the same version are
+    /// chosen in the order of the client's preference.
+    pub fn negotiate(offered: &str) -> Option<Self> {
+        offered
+            .split(',')
+            .filter_map(|name| Self::from_subprotocol(name.trim()))
+            .fold(None, |best: Option<Self>, protocol| match best {
+                Some(best) if best.version >= protocol.version => Some(best),
+                _ => Some(protocol),
+            })
+    }
+
+    /// Serialize an event of the latest version for clients of this protocol.
+    pub fn encode(self, payload: &Payload) -> Option<Message> {
+        if self.version == Self::LATEST.version {
+            return Some(self.encoding.encode(payload));
+        }
+        self.version
+            .translate(payload)
+            .map(|payload| self.encoding.encode(&payload))
+    }
+
+    /// Parse an event sent by a client of this protocol.
+    pub fn decode(self, msg: &Message) -> Result<Payload, String> {
+        let payload = self.encoding.decode(msg)?;
+        if !self.version.supports(&payload.event_type) {
+            return Err(format!(
+                "{:?} event is not part of {}",
+                payload.event_type,
+                self.subprotocol()
+            ));
+        }
+        Ok(payload)
+    }
+}
+
+/// Features of the server that clients can rely on, announced in the `hello` event.
+pub fn capabilities(server_state: &ServerState) -> Vec<String> {
+    let mut capabilities = vec![
+        "reactions",
+        "replies",
+        "read_receipts",
+        "mentions",
+        "attachments",
+        "search",
+    ];
+    if server_state.link_unfurler.is_some() {
+        capabilities.push("link_previews");
+    }
+    if server_state.history_replay_count > 0 {
+        capabilities.push("history_batch");
+    }
+    capabilities.into_iter().map(String::from).collect()
+}
+
+/// First event sent to clients of versions that know about it.
+pub fn hello(server_state: &ServerState, protocol: Protocol) -> Option<Payload> {
+    let hello = Payload {
+        event_type: PayloadEventType::Hello,
+        protocol: Some(protocol.subprotocol().into()),
+        capabilities: capabilities(server_state),
+        ..Default::default()
+    };
+    protocol
+        .version
+        .supports(&hello.event_type)
+        .then_some(hello)
+}
+
+/// Event that is serialized at most once for each protocol among its recipients.
+pub struct EncodedPayload<'a> {
+    payload: &'a Payload,
+    encoded: HashMap<Protocol, Option<Message>>,
+}
+
+impl<'a> EncodedPayload<'a> {
+    pub fn new(payload: &'a Payload) -> Self {
+        Self {
+            payload,
+            encoded: HashMap::new(),
+        }
+    }
+
+    /// Serialized event for a client of `protocol`, or `None` if the event is not sent to it.
+    /// Frames share their buffer, so cloning them is cheap.
+    pub fn get(&mut self, protocol: Protocol) -> Option<Message> {
+        self.encoded
+            .entry(protocol)
+            .or_insert_with(|| protocol.encode(self.payload))
+            .clone()
+    }
+}
@@ -1,11 +1,42 @@
 //! REST API component for exposing queryable endpoints both for a REST API client
 //! user and the fronted part of application for features like message history.
 
-use std::net::TcpListener;
+use std::{
+    convert::Infallible,
+    future::ready,
+    net::TcpListener,
+    time::{Duration, Instant},
+};
 
-use actix_web::{get, http::header::ContentType, web, App, HttpResponse, HttpServer, Responder};
+use actix_multipart::Multipart;
+use actix_web::{
+    delete,
+    dev::Service,
+    get,
+    http::header::{self, ContentDisposition, ContentType, DispositionParam, DispositionType},
+    post, put,
+    web::{self, Bytes},
+    App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{Instrument, Span};
+use uuid::Uuid;
 
-use crate::SharedServerState;
+use crate::{
+    archive::{self, ExportFormat},
+    attachment::Attachment,
+    chat,
+    configuration::ApiKey,
+    lock_state,
+    protocol::{self, Protocol},
+    retention,
+    search::SearchQuery,
+    webhook::{self, Webhook},
+    ClientId, Payload, PayloadEventType, ServerState, SharedServerState,
+};
 
 #[get("/health")]
 async fn health() -> impl Responder {
@@ -14,19 +45,1130 @@ async fn health() -> impl Responder {
 
 #[get("/history")]
 async fn get_history(server_state: web::Data<SharedServerState>) -> impl Responder {
-    let history = &server_state.get_ref().lock().await.history;
-    log::trace!("history is queried: '/history'");
+    let history = &lock_state(server_state.get_ref()).await.history;
+    tracing::trace!("history is queried");
     let j = serde_json::to_string(&history).unwrap();
     HttpResponse::Ok().content_type(ContentType::json()).body(j)
 }
 
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Stream history in the requested format. History is read in chunks, so that chat is not blocked
+/// while a large export is being sent.
+#[get("/history/export")]
+async fn export_history(
+    query: web::Query<ExportQuery>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    const CHUNK_SIZE: usize = 500;
+
+    let format = query.format;
+    let server_state = server_state.get_ref().clone();
+    tracing::trace!(?format, "history is exported");
+
+    // Continue after the last exported identifier, as retention may remove events meanwhile
+    let entries = stream::unfold(Some(None), move |last_exported_id| {
+        let server_state = server_state.clone();
+        async move {
+            let last_exported_id = last_exported_id?;
+            let server_state = lock_state(&server_state).await;
+            let start = server_state
+                .history
+                .partition_point(|payload| payload.id <= last_exported_id);
+            let chunk = &server_state.history[start..];
+            let chunk = &chunk[..chunk.len().min(CHUNK_SIZE)];
+            let last = chunk.last()?;
+            let body: String = chunk.iter().map(|payload| format.render(payload)).collect();
+            Some((Bytes::from(body), last.id.map(Some)))
+        }
+    });
+    let body = stream::once(ready(Bytes::from_static(format.header().as_bytes())))
+        .chain(entries)
+        .chain(stream::once(ready(Bytes::from_static(
+            format.footer().as_bytes(),
+        ))))
+        .map(Ok::<_, Infallible>);
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "chat-history.{}",
+                format.file_extension()
+            ))],
+        })
+        .streaming(body)
+}
+
+/// Merge JSON Lines dump of `GET /history/export` into history. Nothing is imported if any line
+/// is invalid. Registered with a body size limit of [`MAX_IMPORT_BYTES`].
+async fn import_history(
+    request: HttpRequest,
+    body: web::Bytes,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&request, &*lock_state(server_state.get_ref()).await) {
+        return response;
+    }
+    // Dumps are parsed without holding the lock, which would stall the chat
+    let Ok(dump) = std::str::from_utf8(&body) else {
+        return HttpResponse::BadRequest().body("dump is not valid UTF-8");
+    };
+    let entries = match archive::parse_jsonl(dump) {
+        Ok(entries) => entries,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    let summary = archive::import_history(&mut server_state, entries);
+    tracing::info!(
+        imported = summary.imported,
+        skipped = summary.skipped,
+        "history is imported"
+    );
+    let j = serde_json::to_string(&summary).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[get("/history/{id}/thread")]
+async fn get_thread(
+    path: web::Path<u64>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let server_state = lock_state(server_state.get_ref()).await;
+    tracing::trace!(id, "thread is queried");
+    let Some(parent) = server_state
+        .history_entry(id)
+        .filter(|entry| entry.event_type == PayloadEventType::Message)
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    // Parent first, then replies in chronological order
+    let thread: Vec<&Payload> = std::iter::once(parent)
+        .chain(
+            server_state
+                .history
+                .iter()
+                .filter(|entry| entry.reply_to == Some(id)),
+        )
+        .collect();
+    let j = serde_json::to_string(&thread).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+/// Unread count of the authenticated member, who can not query the ones of others.
+#[get("/users/{name}/unread")]
+async fn get_unread_count(
+    request: HttpRequest,
+    path: web::Path<String>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    let Some((caller, _)) = authenticate(&request, &server_state) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let username = path.into_inner();
+    if username != caller {
+        return HttpResponse::Forbidden().body("unread count of another user");
+    }
+    tracing::trace!(%username, "unread count is queried");
+    let unread = server_state.unread_count(&username);
+    let j = serde_json::to_string(&unread).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+/// Usernames of connected chat members in alphabetical order.
+#[get("/users")]
+async fn get_users(server_state: web::Data<SharedServerState>) -> impl Responder {
+    tracing::trace!("users are queried");
+    let mut usernames: Vec<String> = lock_state(server_state.get_ref())
+        .await
+        .clients
+        .values()
+        .map(|client| client.username.clone())
+        .collect();
+    usernames.sort();
+    let j = serde_json::to_string(&usernames).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[derive(Deserialize)]
+struct MentionsQuery {
+    /// Only return mentions newer than this history event.
+    after: Option<u64>,
+}
+
+/// Messages mentioning the authenticated member.
+#[get("/mentions")]
+async fn get_mentions(
+    request: HttpRequest,
+    query: web::Query<MentionsQuery>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    let Some((username, _)) = authenticate(&request, &server_state) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    tracing::trace!(%username, "mentions are queried");
+    let mentions: Vec<&Payload> = server_state
+        .history
+        .iter()
+        .filter(|entry| entry.mentions.contains(&username) && entry.id > query.after)
+        .collect();
+    let j = serde_json::to_string(&mentions).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[get("/search")]
+async fn search(
+    query: web::Query<SearchQuery>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    if query.query.trim().is_empty() {
+        return HttpResponse::BadRequest().body("missing search query `q`");
+    }
+    tracing::trace!("history is searched");
+    let result = lock_state(server_state.get_ref())
+        .await
+        .search_index
+        .search(&query);
+    match result {
+        Ok(hits) => {
+            let j = serde_json::to_string(&hits).unwrap();
+            HttpResponse::Ok().content_type(ContentType::json()).body(j)
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "search failed");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Upload a single file in the `file` field of a multipart form. Responds with metadata of the
+/// stored attachment, to be referred to in chat messages.
+#[post("/attachments")]
+async fn upload_attachment(
+    mut multipart: Multipart,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let ((id, path), max_size_bytes, remaining_bytes) = {
+        let server_state = lock_state(server_state.get_ref()).await;
+        (
+            server_state.attachments.new_upload(),
+            server_state.attachments.max_size_bytes(),
+            server_state.attachments.remaining_bytes(),
+        )
+    };
+
+    while let Ok(Some(mut field)) = multipart.try_next().await {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let filename = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .unwrap_or(&id)
+            .to_string();
+        let content_type = field
+            .content_type()
+            .map_or("application/octet-stream".into(), |m| m.to_string());
+
+        if let Some(directory) = path.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(directory).await {
+                tracing::error!(error = %e, "unable to create attachment directory");
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+        let Ok(mut file) = tokio::fs::File::create(&path).await else {
+            tracing::error!(path = %path.display(), "unable to create attachment file");
+            return HttpResponse::InternalServerError().finish();
+        };
+
+        let mut size = 0;
+        while let Some(chunk) = field.next().await {
+            let response = match chunk {
+                Ok(chunk) if size + chunk.len() > max_size_bytes => HttpResponse::PayloadTooLarge()
+                    .body(format!(
+                        "attachment exceeds the limit of {max_size_bytes} bytes"
+                    )),
+                Ok(chunk) if size + chunk.len() > remaining_bytes => {
+                    HttpResponse::InsufficientStorage().body("attachment storage is full")
+                }
+                Ok(chunk) => {
+                    size += chunk.len();
+                    match file.write_all(&chunk).await {
+                        Ok(()) => continue,
+                        Err(e) => {
+                            tracing::error!(error = %e, "unable to write attachment");
+                            HttpResponse::InternalServerError().finish()
+                        }
+                    }
+                }
+                Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+            };
+
+            // Discard partially written file
+            drop(file);
+            let _ = tokio::fs::remove_file(&path).await;
+            tracing::info!(status = %response.status(), "attachment upload failed");
+            return response;
+        }
+        if let Err(e) = file.flush().await {
+            tracing::error!(error = %e, "unable to write attachment");
+            return HttpResponse::InternalServerError().finish();
+        }
+
+        let attachment = Attachment {
+            id,
+            filename,
+            content_type,
+            size,
+        };
+        tracing::trace!(
+            id = %attachment.id,
+            filename = %attachment.filename,
+            size = attachment.size,
+            "attachment is uploaded"
+        );
+        let j = serde_json::to_string(&attachment).unwrap();
+        let inserted = lock_state(server_state.get_ref())
+            .await
+            .attachments
+            .insert(attachment);
+        if let Err(e) = inserted {
+            let _ = tokio::fs::remove_file(&path).await;
+            tracing::info!(error = %e, "attachment upload failed");
+            return HttpResponse::InsufficientStorage().body(e);
+        }
+        return HttpResponse::Created()
+            .content_type(ContentType::json())
+            .body(j);
+    }
+
+    HttpResponse::BadRequest().body("missing `file` field")
+}
+
+#[get("/attachments/{id}")]
+async fn get_attachment(
+    path: web::Path<String>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let (attachment, file_path) = {
+        let server_state = lock_state(server_state.get_ref()).await;
+        let attachments = &server_state.attachments;
+        match (attachments.get(&id), attachments.path(&id)) {
+            (Some(attachment), Some(file_path)) => (attachment.clone(), file_path),
+            _ => return HttpResponse::NotFound().finish(),
+        }
+    };
+    tracing::trace!(%id, "attachment is downloaded");
+
+    let disposition = if attachment.is_inline() {
+        DispositionType::Inline
+    } else {
+        DispositionType::Attachment
+    };
+    match tokio::fs::read(&file_path).await {
+        Ok(contents) => HttpResponse::Ok()
+            .content_type(attachment.content_type)
+            .insert_header(ContentDisposition {
+                disposition,
+                parameters: vec![DispositionParam::Filename(attachment.filename)],
+            })
+            // Browsers must not guess a content type that is displayed
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+            .body(contents),
+        Err(e) => {
+            tracing::error!(path = %file_path.display(), error = %e, "unable to read attachment");
+            HttpResponse::NotFound().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    username: String,
+}
+
+/// Member of the chat over HTTP, who leaves the chat once their event stream is dropped.
+struct HttpSession {
+    server_state: SharedServerState,
+    client_id: ClientId,
+    span: Span,
+}
+
+impl Drop for HttpSession {
+    fn drop(&mut self) {
+        let removal = chat::remove_client(self.server_state.clone(), self.client_id);
+        tokio::spawn(removal.instrument(self.span.clone()));
+    }
+}
+
+/// Join the chat as `username` and receive its events as Server-Sent Events, for clients that can
+/// not use WebSocket. The first `hello` event carries the session token for `POST /messages`.
+/// Closing the stream leaves the chat.
+#[get("/events")]
+async fn stream_events(
+    query: web::Query<EventsQuery>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    // Comments keep proxies from closing idle streams and detect disconnected clients
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+    let username = query.into_inner().username;
+    if username.trim().is_empty() {
+        return HttpResponse::BadRequest().body("missing `username`");
+    }
+    let shared_state = server_state.get_ref().clone();
+    let mut server_state = lock_state(&shared_state).await;
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connection_id = server_state.next_connection_id();
+    let client_id = ClientId::Session(connection_id);
+    if let Err(e) = chat::add_client(
+        &mut server_state,
+        client_id,
+        tx.clone(),
+        Protocol::LATEST,
+        &username,
+    ) {
+        return HttpResponse::Conflict().body(e);
+    }
+    let session_token = Uuid::new_v4().to_string();
+    server_state
+        .sessions
+        .insert(session_token.clone(), client_id);
+    // Events of the session are logged within its span, like the ones of a WebSocket connection
+    let span = tracing::info_span!("connection", connection_id, %username);
+
+    span.in_scope(|| {
+        tracing::trace!("user joined the chat over HTTP");
+        if let Some(hello) = protocol::hello(&server_state, Protocol::LATEST) {
+            let hello = Payload {
+                session_token: Some(session_token),
+                ..hello
+            };
+            tx.send(Protocol::LATEST.encoding.encode(&hello))
+                .expect("unable to send hello");
+        }
+        chat::send_history_batch(&server_state, &server_state.clients[&client_id]);
+        chat::send_motd(&server_state, &server_state.clients[&client_id]);
+    });
+    let connected = Payload {
+        event_type: PayloadEventType::Connected,
+        username,
+        ..Default::default()
+    };
+    chat::broadcast(&mut server_state, connected, Some(client_id))
+        .instrument(span.clone())
+        .await;
+
+    let session = HttpSession {
+        server_state: shared_state.clone(),
+        client_id,
+        span,
+    };
+    let keep_alive = tokio::time::interval_at(
+        tokio::time::Instant::now() + KEEP_ALIVE_INTERVAL,
+        KEEP_ALIVE_INTERVAL,
+    );
+    // Session lives as long as the stream, ending when the client is removed from the chat
+    let metrics = server_state.metrics.clone();
+    let events = stream::unfold(
+        (rx, keep_alive, session, metrics),
+        |(mut rx, mut keep_alive, session, metrics)| async move {
+            let chunk = tokio::select! {
+                msg = rx.recv() => {
+                    metrics.outbound_queue_depth.observe(rx.len() as f64);
+                    match msg? {
+                        Message::Text(json) => format!("data: {}\n\n", json.as_str()),
+                        // Client is disconnected by the server, e.g. kicked by an admin
+                        Message::Close(_) => return None,
+                        _ => String::new(),
+                    }
+                }
+                _ = keep_alive.tick() => ":\n\n".into(),
+            };
+            Some((
+                Ok::<_, Infallible>(Bytes::from(chunk)),
+                (rx, keep_alive, session, metrics),
+            ))
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoCache]))
+        .streaming(events)
+}
+
+#[derive(Deserialize)]
+struct NewMessage {
+    message: String,
+    reply_to: Option<u64>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+/// Send a chat message as the authenticated member or bot. Responds with the message saved to
+/// history, which is not sent to the event stream of the sender.
+#[post("/messages")]
+async fn post_message(
+    request: HttpRequest,
+    body: web::Json<NewMessage>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let shared_state = server_state.get_ref().clone();
+    let mut server_state = lock_state(&shared_state).await;
+    let Some((username, sender)) = authenticate(&request, &server_state) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let NewMessage {
+        message,
+        reply_to,
+        attachments,
+    } = body.into_inner();
+    let payload = Payload {
+        username,
+        message: Some(message),
+        reply_to,
+        attachments,
+        ..Default::default()
+    };
+    let session_id = match sender {
+        Some(ClientId::Session(session_id)) => Some(session_id),
+        _ => None,
+    };
+    tracing::trace!(
+        username = %payload.username,
+        session_id,
+        "message is sent over HTTP"
+    );
+
+    match chat::send_message(&mut server_state, shared_state.clone(), payload, sender).await {
+        Ok(id) => {
+            let j = serde_json::to_string(&server_state.history_entry(id)).unwrap();
+            HttpResponse::Created()
+                .content_type(ContentType::json())
+                .body(j)
+        }
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct PollQuery {
+    /// Identifier of the latest event known to the client. Only events broadcasted after the
+    /// request are returned if not set.
+    after: Option<u64>,
+    /// Seconds to wait for new events.
+    timeout: Option<u64>,
+}
+
+/// Wait for events newer than `after`, then respond with them in a `history_batch` event, for
+/// clients that can not keep a connection open. The `message_id` of the batch is the `after` of
+/// the next poll. Responds with an empty batch if nothing happens before the timeout.
+#[get("/poll")]
+async fn poll_events(
+    query: web::Query<PollQuery>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    const DEFAULT_TIMEOUT_SECS: u64 = 30;
+    const MAX_TIMEOUT_SECS: u64 = 60;
+
+    let timeout = query
+        .timeout
+        .unwrap_or(DEFAULT_TIMEOUT_SECS)
+        .min(MAX_TIMEOUT_SECS);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout);
+    tracing::trace!(after = query.after, timeout, "events are polled");
+
+    let (after, mut updates) = {
+        let server_state = lock_state(server_state.get_ref()).await;
+        (
+            query.after.unwrap_or(server_state.last_history_id),
+            server_state.history_updates.subscribe(),
+        )
+    };
+    let batch = loop {
+        {
+            let server_state = lock_state(server_state.get_ref()).await;
+            if let Some(batch) = poll_batch(&server_state, after) {
+                break batch;
+            }
+            // Events are broadcasted while holding the lock, so none is missed until waiting
+            updates.borrow_and_update();
+        }
+        let changed = tokio::time::timeout_at(deadline, updates.changed()).await;
+        if !matches!(changed, Ok(Ok(()))) {
+            break Payload {
+                event_type: PayloadEventType::HistoryBatch,
+                message_id: Some(after),
+                ..Default::default()
+            };
+        }
+    };
+    let j = serde_json::to_string(&batch).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+/// History events newer than `after`, or `None` if there are no newer events to wait for.
+/// Large backlogs are returned in several batches.
+fn poll_batch(server_state: &ServerState, after: u64) -> Option<Payload> {
+    const MAX_BATCH_SIZE: usize = 500;
+
+    if after == server_state.last_history_id {
+        return None;
+    }
+    let history = &server_state.history;
+    let start = history.partition_point(|payload| payload.id <= Some(after));
+    let events = &history[start..];
+    let events = &events[..events.len().min(MAX_BATCH_SIZE)];
+    // Events that are not kept in history are skipped, and clients ahead of the server, e.g.
+    // after a restart, start over from the latest event
+    let last_id = match events.last() {
+        Some(last) if events.len() == MAX_BATCH_SIZE => last.id,
+        _ => Some(server_state.last_history_id),
+    };
+    Some(Payload {
+        event_type: PayloadEventType::HistoryBatch,
+        message_id: last_id,
+        history: events.to_vec(),
+        ..Default::default()
+    })
+}
+
+/// Secret in `Authorization: Bearer <token>` header.
+fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Username of the sender of a request authenticated with `Authorization: Bearer <token>`
+/// header, and the client of the member if it is one. The token is either the session token of
+/// a member, sent in the `hello` event of their WebSocket connection or `GET /events` stream, or
+/// an API key of a bot.
+fn authenticate(
+    request: &HttpRequest,
+    server_state: &ServerState,
+) -> Option<(String, Option<ClientId>)> {
+    let token = bearer_token(request)?;
+    if let Some(&client_id) = server_state.sessions.get(token) {
+        let client = server_state.clients.get(&client_id)?;
+        return Some((client.username.clone(), Some(client_id)));
+    }
+    find_api_key(server_state, token).map(|api_key| (api_key.name.clone(), None))
+}
+
+/// Check secret of admin endpoints in `Authorization: Bearer <token>` header. Responds with the
+/// error response to return, if the request is not authorized.
+fn authorize_admin(request: &HttpRequest, server_state: &ServerState) -> Result<(), HttpResponse> {
+    let Some(admin_token) = &server_state.admin_token else {
+        return Err(HttpResponse::Forbidden().body("admin endpoints are disabled"));
+    };
+    match bearer_token(request) {
+        Some(token) if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) => Ok(()),
+        _ => Err(HttpResponse::Unauthorized().finish()),
+    }
+}
+
+/// Compare secrets without leaking the position of the first difference through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[get("/admin/retention")]
+async fn get_retention_policy(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("retention policy is queried");
+    let j = serde_json::to_string(&server_state.retention).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+/// API key matching the secret `token`.
+fn find_api_key<'a>(server_state: &'a ServerState, token: &str) -> Option<&'a ApiKey> {
+    server_state
+        .api_keys
+        .iter()
+        .find(|api_key| constant_time_eq(token.as_bytes(), api_key.key.as_bytes()))
+}
+
+/// Names of bots and integrations having an API key. Keys themselves are only shown once
+/// created.
+#[get("/admin/api-keys")]
+async fn list_api_keys(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("API keys are listed");
+    let names: Vec<&str> = server_state
+        .api_keys
+        .iter()
+        .map(|api_key| api_key.name.as_str())
+        .collect();
+    let j = serde_json::to_string(&names).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[derive(Deserialize)]
+struct NewApiKey {
+    name: String,
+}
+
+/// Create a random API key for a bot or integration. Keys created at runtime are lost on
+/// restart, so long-lived integrations are configured with `api_keys` instead.
+#[post("/admin/api-keys")]
+async fn create_api_key(
+    request: HttpRequest,
+    body: web::Json<NewApiKey>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    let name = body.into_inner().name;
+    if name.trim().is_empty() {
+        return HttpResponse::BadRequest().body("missing `name`");
+    }
+    if server_state
+        .api_keys
+        .iter()
+        .any(|api_key| api_key.name == name)
+    {
+        return HttpResponse::Conflict().body(format!("API key already exists: {name}"));
+    }
+    let api_key = ApiKey {
+        name,
+        key: Uuid::new_v4().simple().to_string(),
+    };
+    tracing::info!(name = %api_key.name, "API key is created");
+    let j = serde_json::to_string(&api_key).unwrap();
+    server_state.api_keys.push(api_key);
+    HttpResponse::Created()
+        .content_type(ContentType::json())
+        .body(j)
+}
+
+#[delete("/admin/api-keys/{name}")]
+async fn delete_api_key(
+    request: HttpRequest,
+    path: web::Path<String>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    let count = server_state.api_keys.len();
+    server_state.api_keys.retain(|api_key| api_key.name != name);
+    if server_state.api_keys.len() == count {
+        return HttpResponse::NotFound().finish();
+    }
+    tracing::info!(%name, "API key is deleted");
+    HttpResponse::NoContent().finish()
+}
+
+/// Registered webhooks, without their secrets.
+#[get("/admin/webhooks")]
+async fn list_webhooks(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("webhooks are listed");
+    let webhooks: Vec<Webhook> = server_state
+        .webhooks
+        .list()
+        .iter()
+        .map(Webhook::redacted)
+        .collect();
+    let j = serde_json::to_string(&webhooks).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[derive(Deserialize)]
+struct NewWebhook {
+    url: String,
+    events: Vec<PayloadEventType>,
+    secret: Option<String>,
+}
+
+/// Register a webhook receiving events of the given types. Responds with the webhook including
+/// its secret, which is generated unless given.
+#[post("/admin/webhooks")]
+async fn create_webhook(
+    request: HttpRequest,
+    body: web::Json<NewWebhook>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&request, &*lock_state(server_state.get_ref()).await) {
+        return response;
+    }
+    let NewWebhook {
+        url,
+        events,
+        secret,
+    } = body.into_inner();
+    let webhook = match Webhook::new(url, events, secret) {
+        Ok(webhook) => webhook,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let j = serde_json::to_string(&webhook).unwrap();
+    let id = webhook.id.clone();
+    if let Err(e) = webhook::register(server_state.get_ref(), webhook).await {
+        tracing::error!(error = %e, "unable to save webhooks");
+        return HttpResponse::InternalServerError().finish();
+    }
+    tracing::info!(%id, "webhook is registered");
+    HttpResponse::Created()
+        .content_type(ContentType::json())
+        .body(j)
+}
+
+#[delete("/admin/webhooks/{id}")]
+async fn delete_webhook(
+    request: HttpRequest,
+    path: web::Path<String>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = authorize_admin(&request, &*lock_state(server_state.get_ref()).await) {
+        return response;
+    }
+    match webhook::unregister(server_state.get_ref(), &id).await {
+        Ok(true) => {
+            tracing::info!(%id, "webhook is deleted");
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!(error = %e, "unable to save webhooks");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Members of the chat with their transport.
+#[get("/admin/users")]
+async fn list_connected_users(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("connected users are listed");
+    let j = serde_json::to_string(&server_state.connected_users()).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+/// Disconnect a member from the chat. Nothing prevents the user from joining again.
+#[post("/admin/users/{name}/kick")]
+async fn kick_user(
+    request: HttpRequest,
+    path: web::Path<String>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let username = path.into_inner();
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    if !chat::disconnect_user(&server_state, &username) {
+        return HttpResponse::NotFound().finish();
+    }
+    tracing::info!(%username, "user is kicked");
+    HttpResponse::NoContent().finish()
+}
+
+#[get("/admin/bans")]
+async fn list_bans(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("banned users are listed");
+    let j = serde_json::to_string(&server_state.banned_users).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[derive(Deserialize)]
+struct NewBan {
+    username: String,
+}
+
+/// Prevent a user from joining the chat, disconnecting them if they are in the chat. Bans are
+/// lost on restart.
+#[post("/admin/bans")]
+async fn ban_user(
+    request: HttpRequest,
+    body: web::Json<NewBan>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    let username = body.into_inner().username;
+    if username.trim().is_empty() {
+        return HttpResponse::BadRequest().body("missing `username`");
+    }
+    if !server_state.banned_users.insert(username.clone()) {
+        return HttpResponse::Conflict().body(format!("user is already banned: {username}"));
+    }
+    chat::disconnect_user(&server_state, &username);
+    tracing::info!(%username, "user is banned");
+    HttpResponse::Created().finish()
+}
+
+#[delete("/admin/bans/{name}")]
+async fn unban_user(
+    request: HttpRequest,
+    path: web::Path<String>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let username = path.into_inner();
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    if !server_state.banned_users.remove(&username) {
+        return HttpResponse::NotFound().finish();
+    }
+    tracing::info!(%username, "user is unbanned");
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Deserialize)]
+struct NewAnnouncement {
+    message: String,
+}
+
+/// Send a `system` event to every member of the chat. Announcements are saved to history.
+#[post("/admin/announcements")]
+async fn create_announcement(
+    request: HttpRequest,
+    body: web::Json<NewAnnouncement>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    let message = body.into_inner().message;
+    if message.trim().is_empty() {
+        return HttpResponse::BadRequest().body("missing `message`");
+    }
+    let announcement = Payload {
+        event_type: PayloadEventType::System,
+        message: Some(message),
+        ..Default::default()
+    };
+    let id = chat::broadcast(&mut server_state, announcement, None).await;
+    tracing::info!(id, "announcement is sent");
+    let j = serde_json::to_string(&server_state.history_entry(id)).unwrap();
+    HttpResponse::Created()
+        .content_type(ContentType::json())
+        .body(j)
+}
+
+/// Current message of the day, `null` if disabled.
+#[get("/admin/motd")]
+async fn get_motd(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("message of the day is queried");
+    let j = serde_json::to_string(&Motd {
+        message: server_state.motd.clone(),
+    })
+    .unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[derive(Deserialize, Serialize)]
+struct Motd {
+    message: Option<String>,
+}
+
+/// Replace the message of the day sent to clients joining from now on. An empty or `null`
+/// message disables it.
+#[put("/admin/motd")]
+async fn set_motd(
+    request: HttpRequest,
+    body: web::Json<Motd>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    server_state.motd = body
+        .into_inner()
+        .message
+        .filter(|message| !message.trim().is_empty());
+    tracing::info!(
+        enabled = server_state.motd.is_some(),
+        "message of the day is changed"
+    );
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Deserialize)]
+struct PurgeQuery {
+    /// Only remove events older than this history event.
+    before: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct PurgeResult {
+    removed: usize,
+}
+
+/// Remove events from history, all of them unless `before` is given. Members of the chat keep
+/// events they have already received.
+#[delete("/admin/history")]
+async fn purge_history(
+    request: HttpRequest,
+    query: web::Query<PurgeQuery>,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let mut server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    let removed = retention::purge_history(&mut server_state, query.before);
+    tracing::info!(removed, before = query.before, "history is purged");
+    let j = serde_json::to_string(&PurgeResult { removed }).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+#[get("/admin/stats")]
+async fn get_stats(
+    request: HttpRequest,
+    server_state: web::Data<SharedServerState>,
+) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    if let Err(response) = authorize_admin(&request, &server_state) {
+        return response;
+    }
+    tracing::trace!("stats are queried");
+    let j = serde_json::to_string(&server_state.stats()).unwrap();
+    HttpResponse::Ok().content_type(ContentType::json()).body(j)
+}
+
+/// Metrics of the server in Prometheus text format. Like `/health`, it is not protected by the
+/// admin token, so that it can be scraped from within the cluster.
+#[get("/metrics")]
+async fn get_metrics(server_state: web::Data<SharedServerState>) -> impl Responder {
+    let server_state = lock_state(server_state.get_ref()).await;
+    tracing::trace!("metrics are scraped");
+    let body = server_state.metrics.encode(&server_state);
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body)
+}
+
+/// Size limit of history import dumps. Other request bodies have the default limit of actix-web.
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
 /// Entry for starting REST API server.
 pub async fn run_rest_server(listener: TcpListener, server_state: SharedServerState) {
     HttpServer::new(move || {
         let web_data = web::Data::new(server_state.clone());
         App::new()
+            // Events logged by handlers are recorded within the span of their request. Paths
+            // are logged without query strings, which may contain search terms.
+            .wrap_fn(|request, service| {
+                let span = tracing::info_span!(
+                    "request",
+                    method = %request.method(),
+                    path = %request.path()
+                );
+                let start = Instant::now();
+                let response = span.in_scope(|| service.call(request));
+                async move {
+                    let response = response.await;
+                    match &response {
+                        Ok(response) => tracing::debug!(
+                            status = response.status().as_u16(),
+                            elapsed_ms = start.elapsed().as_millis() as u64,
+                            "request is handled"
+                        ),
+                        Err(e) => tracing::error!(error = %e, "request failed"),
+                    }
+                    response
+                }
+                .instrument(span)
+            })
             .service(health)
             .service(get_history)
+            .service(export_history)
+            .service(
+                web::resource("/history/import")
+                    .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                    .route(web::post().to(import_history)),
+            )
+            .service(get_thread)
+            .service(get_users)
+            .service(get_unread_count)
+            .service(get_mentions)
+            .service(search)
+            .service(upload_attachment)
+            .service(get_attachment)
+            .service(stream_events)
+            .service(post_message)
+            .service(poll_events)
+            .service(get_retention_policy)
+            .service(list_api_keys)
+            .service(create_api_key)
+            .service(delete_api_key)
+            .service(list_webhooks)
+            .service(create_webhook)
+            .service(delete_webhook)
+            .service(list_connected_users)
+            .service(kick_user)
+            .service(list_bans)
+            .service(ban_user)
+            .service(unban_user)
+            .service(create_announcement)
+            .service(get_motd)
+            .service(set_motd)
+            .service(purge_history)
+            .service(get_stats)
+            .service(get_metrics)
             .app_data(web_data)
     })
     .listen(listener)
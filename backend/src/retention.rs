@@ -0,0 +1,93 @@
+//! History retention enforced by a background task, so that history does not grow forever.
+
+use std::time::Duration;
+
+use crate::{
+    lock_state, unix_timestamp_millis, Payload, PayloadEventType, ServerState, SharedServerState,
+};
+
+/// Periodically remove history events exceeding the limits of the retention policy.
+pub async fn run_retention_task(server_state: SharedServerState) {
+    let interval_secs = lock_state(&server_state).await.retention.interval_secs;
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        let mut server_state = lock_state(&server_state).await;
+        let removed = enforce_retention(&mut server_state, unix_timestamp_millis());
+        if removed > 0 {
+            tracing::info!(
+                removed,
+                "events are removed from history by retention policy"
+            );
+        }
+    }
+}
+
+/// Remove history events older than the maximum age, then the oldest events beyond the maximum
+/// count. Returns the number of removed events.
+pub fn enforce_retention(server_state: &mut ServerState, now_millis: u64) -> usize {
+    let policy = server_state.retention.clone();
+    let expiry_millis = policy
+        .max_age_secs
+        .map(|max_age_secs| now_millis.saturating_sub(max_age_secs.saturating_mul(1000)));
+
+    let is_expired = |payload: &Payload| {
+        let too_old = expiry_millis
+            .zip(payload.timestamp)
+            .is_some_and(|(expiry, timestamp)| timestamp < expiry);
+        let unwanted_presence = !policy.keep_presence_events
+            && matches!(
+                payload.event_type,
+                PayloadEventType::Connected | PayloadEventType::Disconnected
+            );
+        too_old || unwanted_presence
+    };
+    let mut removed_ids = Vec::new();
+    server_state.history.retain(|payload| {
+        if is_expired(payload) {
+            removed_ids.extend(payload.id);
+            return false;
+        }
+        true
+    });
+
+    if let Some(max_count) = policy.max_count {
+        let excess = server_state.history.len().saturating_sub(max_count);
+        removed_ids.extend(
+            server_state
+                .history
+                .drain(..excess)
+                .filter_map(|payload| payload.id),
+        );
+    }
+
+    forget(server_state, &removed_ids);
+    removed_ids.len()
+}
+
+/// Remove history events older than event `before`, or the whole history if not set. Returns the
+/// number of removed events.
+pub fn purge_history(server_state: &mut ServerState, before: Option<u64>) -> usize {
+    let end = match before {
+        Some(before) => server_state
+            .history
+            .partition_point(|payload| payload.id < Some(before)),
+        None => server_state.history.len(),
+    };
+    let removed_ids: Vec<u64> = server_state
+        .history
+        .drain(..end)
+        .filter_map(|payload| payload.id)
+        .collect();
+    forget(server_state, &removed_ids);
+    removed_ids.len()
+}
+
+/// Remove leftovers of removed history events.
+fn forget(server_state: &ServerState, removed_ids: &[u64]) {
+    for id in removed_ids {
+        if let Err(e) = server_state.search_index.remove(*id) {
+            tracing::error!(id, error = %e, "unable to remove message from search index");
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Full-text search over chat messages, backed by an embedded SQLite FTS5 index.
+//!
+//! The index is kept in memory next to the message history and is updated incrementally as
+//! messages are broadcasted.
+
+use std::fmt;
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{archive::escape_html, Payload, PayloadEventType};
+
+/// Filters of a search query. `query` is a list of words and `"quoted phrases"` that all have
+/// to appear in a message.
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+    #[serde(rename = "q")]
+    pub query: String,
+
+    /// Only messages sent by this user.
+    pub user: Option<String>,
+
+    /// Only messages sent at or after this Unix timestamp in milliseconds.
+    pub from: Option<u64>,
+
+    /// Only messages sent at or before this Unix timestamp in milliseconds.
+    pub to: Option<u64>,
+
+    pub limit: Option<usize>,
+}
+
+/// Message matching a search query.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub id: u64,
+    pub username: String,
+    pub timestamp: Option<u64>,
+
+    /// HTML-escaped part of the message around matching words, highlighted with `<mark>` tags.
+    pub snippet: String,
+}
+
+pub struct SearchIndex {
+    connection: Connection,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self::new().expect("failed to create search index")
+    }
+}
+
+impl fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SearchIndex").finish_non_exhaustive()
+    }
+}
+
+impl SearchIndex {
+    const DEFAULT_LIMIT: usize = 50;
+    const MAX_LIMIT: usize = 200;
+
+    pub fn new() -> Result<Self, String> {
+        let connection = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        connection
+            .execute_batch(
+                "CREATE VIRTUAL TABLE messages USING fts5(
+                    body,
+                    id UNINDEXED,
+                    username UNINDEXED,
+                    timestamp UNINDEXED,
+                    tokenize = 'unicode61'
+                );",
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(Self { connection })
+    }
+
+    /// Add chat message to the index. Other kinds of events are ignored.
+    pub fn insert(&self, payload: &Payload) -> Result<(), String> {
+        let (PayloadEventType::Message, Some(id), Some(body)) =
+            (&payload.event_type, payload.id, &payload.message)
+        else {
+            return Ok(());
+        };
+        self.connection
+            .execute(
+                "INSERT INTO messages (rowid, body, id, username, timestamp)
+                 VALUES (?1, ?2, ?1, ?3, ?4)",
+                params![id, body, payload.username, payload.timestamp],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Remove message from the index, e.g. when it is removed from history.
+    pub fn remove(&self, id: u64) -> Result<(), String> {
+        self.connection
+            .execute("DELETE FROM messages WHERE rowid = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Find messages matching all words and phrases of the query, most relevant first.
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, String> {
+        let match_expression = to_match_expression(&query.query);
+        if match_expression.is_empty() {
+            return Ok(Vec::new());
+        }
+        let limit = query
+            .limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .min(Self::MAX_LIMIT);
+
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT id, username, timestamp,
+                        snippet(messages, 0, char(2), char(3), '…', 16)
+                 FROM messages
+                 WHERE messages MATCH ?1
+                   AND (?2 IS NULL OR username = ?2)
+                   AND (?3 IS NULL OR timestamp >= ?3)
+                   AND (?4 IS NULL OR timestamp <= ?4)
+                 ORDER BY rank
+                 LIMIT ?5",
+            )
+            .map_err(|e| e.to_string())?;
+        let hits = statement
+            .query_map(
+                params![match_expression, query.user, query.from, query.to, limit],
+                |row| {
+                    Ok(SearchHit {
+                        id: row.get(0)?,
+                        username: row.get(1)?,
+                        timestamp: row.get(2)?,
+                        snippet: highlight(&row.get::<_, String>(3)?),
+                    })
+                },
+            )
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok(hits)
+    }
+}
+
+/// Escape snippet text and replace the control characters delimiting matches, as set by
+/// `SearchIndex::search`, with `<mark>` tags.
+fn highlight(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace('\u{2}', "<mark>")
+        .replace('\u{3}', "</mark>")
+}
+
+/// Translate user input to FTS5 query syntax. Every word and `"quoted phrase"` becomes an FTS5
+/// string, so that operators and column filters of user input are treated as plain text.
+fn to_match_expression(query: &str) -> String {
+    let mut terms = Vec::new();
+    for (i, part) in query.split('"').enumerate() {
+        // Odd parts are between quotes
+        if i % 2 == 1 {
+            if !part.trim().is_empty() {
+                terms.push(part.trim().to_string());
+            }
+        } else {
+            terms.extend(part.split_whitespace().map(String::from));
+        }
+    }
+    terms
+        .iter()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,350 @@
+//! Terminal user interface of the `chat-tui` binary, kept apart from terminal handling so that it
+//! can be tested with [`ratatui::backend::TestBackend`].
+//!
+//! The view consists of scrollback of chat events, a pane listing connected members, an input
+//! line and a status line of the connection.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use ratatui::{
+    crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Position},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, Paragraph},
+    Frame,
+};
+
+use crate::{archive, client::Event, Payload, PayloadEventType};
+
+/// Width of the pane listing connected members, including borders.
+const USERS_PANE_WIDTH: u16 = 24;
+
+/// Marks sent messages in scrollback until the server's version of them is received.
+const PENDING_MARKER: &str = " (sending)";
+
+/// Marks sent messages that the server has rejected, as it has handled later ones.
+const REJECTED_MARKER: &str = " (not sent)";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected,
+    /// Connection is lost and the client is trying to reconnect.
+    Reconnecting,
+    /// Connection is lost for good.
+    Disconnected,
+}
+
+/// Outcome of a key press that the caller acts on.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Send the entered chat message.
+    Send(String),
+    Quit,
+}
+
+/// State of the chat as shown in the terminal.
+#[derive(Debug)]
+pub struct ChatView {
+    username: String,
+    server: String,
+    status: ConnectionStatus,
+    /// Rendered events in chronological order.
+    scrollback: Vec<String>,
+    /// Identifier of the latest history event in scrollback, to skip replayed duplicates.
+    last_id: Option<u64>,
+    /// Sent messages shown before the server's version of them, oldest first.
+    pending: VecDeque<PendingMessage>,
+    users: BTreeSet<String>,
+    input: String,
+    /// Number of lines scrolled up from the latest event.
+    scroll: usize,
+}
+
+impl ChatView {
+    /// Empty view of `username` connected to `server`.
+    pub fn new(username: impl Into<String>, server: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            server: server.into(),
+            status: ConnectionStatus::Connected,
+            scrollback: Vec::new(),
+            last_id: None,
+            pending: VecDeque::new(),
+            users: BTreeSet::new(),
+            input: String::new(),
+            scroll: 0,
+        }
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: ConnectionStatus) {
+        self.status = status;
+    }
+
+    pub fn scrollback(&self) -> &[String] {
+        &self.scrollback
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &str> {
+        self.users.iter().map(String::as_str)
+    }
+
+    pub fn set_users(&mut self, users: impl IntoIterator<Item = String>) {
+        self.users = users.into_iter().collect();
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Identifier of the latest event when the oldest pending message was sent, after which the
+    /// server's versions of pending messages are found in history. `None` if nothing is pending.
+    pub fn pending_since(&self) -> Option<u64> {
+        self.pending.front().map(|pending| pending.since)
+    }
+
+    /// Append history events newer than the ones already in scrollback.
+    pub fn load_history(&mut self, history: &[Payload]) {
+        for payload in history {
+            if self.confirm_sent(payload) {
+                continue;
+            }
+            if payload.id.is_some() && payload.id <= self.last_id {
+                continue;
+            }
+            self.last_id = payload.id.or(self.last_id);
+            if matches!(
+                payload.event_type,
+                PayloadEventType::Message
+                    | PayloadEventType::Connected
+                    | PayloadEventType::Disconnected
+                    | PayloadEventType::System
+            ) {
+                self.push_line(archive::message_line(payload));
+            }
+        }
+    }
+
+    /// Update the view with an event received from the chat.
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::History { events, .. } => self.load_history(events),
+            Event::Message(payload) => self.load_history(std::slice::from_ref(payload)),
+            Event::Joined { username } => {
+                self.users.insert(username.clone());
+                self.push_line(format!("{username} has joined the chat."));
+            }
+            Event::Left { username } => {
+                self.users.remove(username);
+                self.push_line(format!("{username} has left the chat."));
+            }
+            Event::Mention {
+                username, message, ..
+            } => self.push_line(format!("{username} mentioned you: {message}")),
+            Event::System { message } => self.push_line(format!("*** {message}")),
+            Event::ConnectionLost => self.status = ConnectionStatus::Reconnecting,
+            Event::Reconnected => self.status = ConnectionStatus::Connected,
+            Event::Hello { .. }
+            | Event::Reactions { .. }
+            | Event::Replies { .. }
+            | Event::Read { .. }
+            | Event::Previews { .. } => {}
+        }
+    }
+
+    /// Show a message sent by the user as pending, since the server does not echo it back. The
+    /// line is replaced once the server's version of it is loaded from history, which may differ
+    /// after content filters.
+    pub fn show_sent(&mut self, message: &str) {
+        let payload = Payload {
+            username: self.username.clone(),
+            message: Some(message.into()),
+            ..Default::default()
+        };
+        self.pending.push_back(PendingMessage {
+            index: self.scrollback.len(),
+            since: self.last_id.unwrap_or_default(),
+            message: message.into(),
+        });
+        self.push_line(archive::message_line(&payload) + PENDING_MARKER);
+    }
+
+    /// Replace the line of a pending message with the server's version of it. Returns whether
+    /// `payload` is that version.
+    fn confirm_sent(&mut self, payload: &Payload) -> bool {
+        let Some(since) = self.pending_since() else {
+            return false;
+        };
+        let Some(id) = payload.id.filter(|&id| id > since) else {
+            return false;
+        };
+        if payload.event_type != PayloadEventType::Message || payload.username != self.username {
+            return false;
+        }
+        // Filtered messages differ from the sent ones, in which case the oldest one is assumed
+        let position = self
+            .pending
+            .iter()
+            .position(|pending| payload.message.as_ref() == Some(&pending.message))
+            .unwrap_or(0);
+        // Messages are handled in order, so older pending messages are rejected
+        for rejected in self.pending.drain(..position) {
+            let line = &mut self.scrollback[rejected.index];
+            line.truncate(line.len() - PENDING_MARKER.len());
+            line.push_str(REJECTED_MARKER);
+        }
+        let confirmed = self.pending.pop_front().expect("pending message exists");
+        self.scrollback[confirmed.index] = archive::message_line(payload);
+        // Replayed history contains the message again
+        for pending in &mut self.pending {
+            pending.since = pending.since.max(id);
+        }
+        self.last_id = self.last_id.max(Some(id));
+        true
+    }
+
+    /// Edit the input line or scroll according to a key press.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.kind == KeyEventKind::Release {
+            return None;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Char('c' | 'd') if control => return Some(Action::Quit),
+            KeyCode::Char(c) if !control => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                let message = std::mem::take(&mut self.input);
+                if !message.trim().is_empty() {
+                    self.scroll = 0;
+                    return Some(Action::Send(message));
+                }
+            }
+            KeyCode::Up => self.scroll_up(1),
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll_up(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render(&self, frame: &mut Frame) {
+        let [main_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [scrollback_area, users_area] =
+            Layout::horizontal([Constraint::Min(10), Constraint::Length(USERS_PANE_WIDTH)])
+                .areas(main_area);
+
+        let block = Block::bordered().title(" Chat ");
+        let inner = block.inner(scrollback_area);
+        let lines = self.visible_lines(inner.width.into(), inner.height.into());
+        frame.render_widget(Paragraph::new(lines).block(block), scrollback_area);
+
+        let users = List::new(self.users.iter().map(|user| {
+            if *user == self.username {
+                Line::from(user.as_str()).bold()
+            } else {
+                Line::from(user.as_str())
+            }
+        }))
+        .block(Block::bordered().title(format!(" Users ({}) ", self.users.len())));
+        frame.render_widget(users, users_area);
+
+        // Keep the end of a long input visible
+        let input_width = usize::from(input_area.width.saturating_sub(3));
+        let input_chars = self.input.chars().count();
+        let visible_input: String = self
+            .input
+            .chars()
+            .skip(input_chars.saturating_sub(input_width))
+            .collect();
+        let cursor_x = input_area.x + 1 + visible_input.chars().count() as u16;
+        frame.render_widget(
+            Paragraph::new(visible_input).block(Block::bordered().title(" Message ")),
+            input_area,
+        );
+        frame.set_cursor_position(Position::new(cursor_x, input_area.y + 1));
+
+        let (status, color) = match self.status {
+            ConnectionStatus::Connected => ("connected", Color::Green),
+            ConnectionStatus::Reconnecting => ("reconnecting", Color::Yellow),
+            ConnectionStatus::Disconnected => ("disconnected", Color::Red),
+        };
+        let status_line = Line::from(vec![
+            format!(" {status} ").fg(Color::Black).bg(color),
+            format!(" {} at {}", self.username, self.server).into(),
+            "  Enter: send, Up/Down/PgUp/PgDn: scroll, Esc: quit".dark_gray(),
+        ]);
+        frame.render_widget(Paragraph::new(status_line), status_area);
+    }
+
+    fn push_line(&mut self, line: String) {
+        self.scrollback.push(line);
+        // Keep the scrolled position in place while new events arrive
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.scrollback.len().saturating_sub(1));
+    }
+
+    /// Wrapped scrollback lines fitting the given area, ending `scroll` events before the latest.
+    fn visible_lines(&self, width: usize, height: usize) -> Vec<Line<'_>> {
+        let end = self.scrollback.len().saturating_sub(self.scroll);
+        let mut lines = Vec::new();
+        for event in self.scrollback[..end].iter().rev() {
+            if lines.len() >= height {
+                break;
+            }
+            let style = if event.starts_with(&format!("[{}]", self.username)) {
+                Style::new().fg(Color::Cyan)
+            } else {
+                Style::new()
+            };
+            let mut wrapped: Vec<_> = wrap(event, width)
+                .into_iter()
+                .map(|line| Line::styled(line, style))
+                .collect();
+            wrapped.reverse();
+            lines.extend(wrapped);
+        }
+        lines.truncate(height);
+        lines.reverse();
+        lines
+    }
+}
+
+#[derive(Debug)]
+struct PendingMessage {
+    /// Index of the line in scrollback.
+    index: usize,
+    /// Identifier of the latest event when the message was sent.
+    since: u64,
+    message: String,
+}
+
+/// Split a line into pieces of at most `width` characters.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() || width == 0 {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(width)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
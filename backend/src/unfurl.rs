@@ -0,0 +1,397 @@
+//! Link preview unfurling of URLs found in chat messages.
+//!
+//! Metadata (title and description) of linked pages is fetched in the background after a message
+//! is broadcasted, so delivery of the message itself never waits for remote servers. Results are
+//! cached to avoid fetching the same page for every message, failed lookups only for a while.
+//!
+//! As members choose the URLs, only servers on public addresses are contacted, so that the chat
+//! server cannot be used to reach its own network or cloud metadata services.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::BoxFuture;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, Url,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::LinkPreviewConfig;
+
+/// Punctuation of the surrounding sentence that is not part of a link ending with it.
+pub(crate) const TRAILING_PUNCTUATION: [char; 9] = ['.', ',', ';', ':', '!', '?', ')', '"', '\''];
+
+/// Title and description of a linked page.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Source of link preview metadata.
+pub trait LinkFetcher: Send + Sync {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<LinkPreview, String>>;
+}
+
+/// Fetches pages over HTTP and extracts metadata from their HTML `<head>`.
+pub struct HttpLinkFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpLinkFetcher {
+    /// Only the beginning of pages is read, which is expected to contain the metadata.
+    const MAX_BODY_BYTES: usize = 256 * 1024;
+    const MAX_REDIRECTS: usize = 5;
+
+    pub fn new(timeout: Duration) -> Self {
+        // Every redirect is checked like the original URL, while host names only resolve to
+        // public addresses, which are then the ones connected to
+        let redirect_policy = redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= Self::MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if let Err(e) = check_url(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        });
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!("chat-backend/", env!("CARGO_PKG_VERSION")))
+            .redirect(redirect_policy)
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .expect("failed to create HTTP client");
+        Self { client }
+    }
+}
+
+impl LinkFetcher for HttpLinkFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<LinkPreview, String>> {
+        Box::pin(async move {
+            let parsed = Url::parse(url).map_err(|e| e.to_string())?;
+            check_url(&parsed)?;
+            let mut response = self
+                .client
+                .get(parsed)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| error_chain(&e))?;
+            let is_html = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.contains("text/html"));
+            if !is_html {
+                return Err(format!("{url} is not an HTML page"));
+            }
+
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+                body.extend_from_slice(&chunk);
+                if body.len() >= Self::MAX_BODY_BYTES {
+                    break;
+                }
+            }
+
+            Ok(parse_metadata(url, &String::from_utf8_lossy(&body)))
+        })
+    }
+}
+
+/// Check scheme and address of a URL before connecting to it. Host names are checked when they are
+/// resolved by [`PublicResolver`].
+fn check_url(url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme of {url}"));
+    }
+    let Some(host) = url.host_str() else {
+        return Err(format!("{url} has no host"));
+    };
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) if !is_public(ip) => Err(format!("{ip} is not a public address")),
+        _ => Ok(()),
+    }
+}
+
+/// Whether an address is reachable on the internet, unlike loopback, private, link-local (like
+/// `169.254.169.254` of cloud metadata services) and other special-purpose addresses.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", shared address space of carrier-grade NAT, IETF protocol assignments,
+        // benchmarking and reserved addresses
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, link-local and documentation addresses
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || first == 0x2001 && ip.segments()[1] == 0x0db8)
+}
+
+/// Resolves host names like the system resolver, dropping addresses that are not public.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Error with its sources, which tell why a request failed, e.g. that a host is not public.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+/// Extract title and description of a page from OpenGraph tags, falling back to standard HTML
+/// `<title>` and description `<meta>` tags.
+pub fn parse_metadata(url: &str, html: &str) -> LinkPreview {
+    // ASCII lowercasing keeps byte offsets valid for the original text
+    let lowercase = html.to_ascii_lowercase();
+
+    let mut meta = HashMap::new();
+    let mut offset = 0;
+    while let Some(start) = lowercase[offset..].find("<meta") {
+        let start = offset + start;
+        let Some(end) = lowercase[start..].find('>') else {
+            break;
+        };
+        let tag = &html[start..start + end];
+        let key = attribute(tag, "property").or_else(|| attribute(tag, "name"));
+        if let (Some(key), Some(content)) = (key, attribute(tag, "content")) {
+            meta.entry(key.to_ascii_lowercase()).or_insert(content);
+        }
+        offset = start + end;
+    }
+
+    let title_tag = lowercase.find("<title").and_then(|start| {
+        let content_start = start + lowercase[start..].find('>')? + 1;
+        let content_end = content_start + lowercase[content_start..].find("</title")?;
+        Some(decode_entities(html[content_start..content_end].trim()))
+    });
+
+    let non_empty = |s: String| (!s.is_empty()).then_some(s);
+    LinkPreview {
+        url: url.into(),
+        title: meta.remove("og:title").or(title_tag).and_then(non_empty),
+        description: meta
+            .remove("og:description")
+            .or_else(|| meta.remove("description"))
+            .and_then(non_empty),
+    }
+}
+
+/// Value of `name="value"` or `name='value'` attribute of an HTML tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lowercase = tag.to_ascii_lowercase();
+    let pattern = format!("{name}=");
+    let mut offset = 0;
+    while let Some(position) = lowercase[offset..].find(&pattern) {
+        let position = offset + position;
+        offset = position + pattern.len();
+        // Avoid matching the end of another attribute, like `data-name=`
+        let preceded_by_space = lowercase[..position]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+        if !preceded_by_space {
+            continue;
+        }
+        let quote = tag[offset..].chars().next()?;
+        if quote != '"' && quote != '\'' {
+            continue;
+        }
+        let value_start = offset + 1;
+        let value_end = value_start + tag[value_start..].find(quote)?;
+        return Some(decode_entities(tag[value_start..value_end].trim()));
+    }
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Collect unique `http` and `https` URLs from message text in order of appearance.
+pub fn extract_urls(message: &str, max_urls: usize) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for word in message.split_whitespace() {
+        if urls.len() >= max_urls {
+            break;
+        }
+        let lowercase = word.to_ascii_lowercase();
+        if !lowercase.starts_with("http://") && !lowercase.starts_with("https://") {
+            continue;
+        }
+        let url = word.trim_end_matches(TRAILING_PUNCTUATION);
+        if !urls.iter().any(|u| u == url) {
+            urls.push(url.into());
+        }
+    }
+    urls
+}
+
+/// Fetches link previews through a [`LinkFetcher`], caching results.
+pub struct LinkUnfurler {
+    fetcher: Box<dyn LinkFetcher>,
+    cache: Mutex<LinkCache>,
+    max_links_per_message: usize,
+}
+
+impl LinkUnfurler {
+    pub fn new(fetcher: Box<dyn LinkFetcher>, config: &LinkPreviewConfig) -> Self {
+        Self {
+            fetcher,
+            cache: Mutex::new(LinkCache::new(
+                config.cache_capacity,
+                Duration::from_millis(config.failed_lookup_ttl_ms),
+            )),
+            max_links_per_message: config.max_links_per_message,
+        }
+    }
+
+    /// Unfurler fetching pages over HTTP.
+    pub fn from_config(config: &LinkPreviewConfig) -> Self {
+        let fetcher = HttpLinkFetcher::new(Duration::from_millis(config.timeout_ms));
+        Self::new(Box::new(fetcher), config)
+    }
+
+    pub fn extract_urls(&self, message: &str) -> Vec<String> {
+        extract_urls(message, self.max_links_per_message)
+    }
+
+    /// Preview of a single URL, either from cache or freshly fetched. Returns `None` if the page
+    /// is unavailable or has no metadata.
+    pub async fn preview(&self, url: &str) -> Option<LinkPreview> {
+        if let Some(cached) = self.cache.lock().unwrap().get(url) {
+            return cached;
+        }
+
+        let preview = match self.fetcher.fetch(url).await {
+            Ok(preview) if preview.title.is_some() || preview.description.is_some() => {
+                Some(preview)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                tracing::debug!(%url, error = %e, "unable to unfurl");
+                None
+            }
+        };
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(url.into(), preview.clone());
+        preview
+    }
+}
+
+impl fmt::Debug for LinkUnfurler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LinkUnfurler")
+            .field("max_links_per_message", &self.max_links_per_message)
+            .finish()
+    }
+}
+
+/// Bounded cache evicting the oldest entries first. URLs without preview expire after
+/// `failure_ttl`, as pages may be unavailable only for a while.
+struct LinkCache {
+    capacity: usize,
+    failure_ttl: Duration,
+    entries: HashMap<String, (Option<LinkPreview>, Instant)>,
+    insertion_order: VecDeque<String>,
+}
+
+impl LinkCache {
+    fn new(capacity: usize, failure_ttl: Duration) -> Self {
+        Self {
+            capacity,
+            failure_ttl,
+            entries: HashMap::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, url: &str) -> Option<Option<LinkPreview>> {
+        match self.entries.get(url)? {
+            (None, inserted) if inserted.elapsed() >= self.failure_ttl => None,
+            (preview, _) => Some(preview.clone()),
+        }
+    }
+
+    fn insert(&mut self, url: String, preview: Option<LinkPreview>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = (preview, Instant::now());
+        if self.entries.insert(url.clone(), entry).is_none() {
+            self.insertion_order.push_back(url);
+        }
+        while self.entries.len() > self.capacity {
+            let Some(oldest) = self.insertion_order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
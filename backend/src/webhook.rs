@@ -0,0 +1,381 @@
+//! Outgoing webhooks notifying external services about chat events with signed HTTP POST requests.
+//!
+//! Deliveries run in the background, so slow or unavailable receivers never hold up broadcasting.
+//! Failed deliveries are retried with exponential backoff and appended to a dead-letter log once
+//! given up. Webhooks are registered through admin endpoints and saved to a JSON file, so they
+//! survive restarts. The file is written without holding the lock of the server state.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    configuration::WebhookConfig, lock_state, unix_timestamp_millis, Payload, PayloadEventType,
+    SharedServerState,
+};
+
+/// Header carrying `sha256=<hex>` HMAC-SHA256 signature of the timestamp and the request body,
+/// keyed with the secret of the webhook. See [`sign()`].
+pub const SIGNATURE_HEADER: &str = "X-Chat-Signature";
+
+/// Header carrying the time of sending the request as Unix timestamp in milliseconds. Receivers
+/// reject requests that are too old, so that captured requests can not be replayed.
+pub const TIMESTAMP_HEADER: &str = "X-Chat-Timestamp";
+
+/// Header carrying the event type of the request body.
+pub const EVENT_HEADER: &str = "X-Chat-Event";
+
+/// Header identifying a delivery, which stays the same across retries.
+pub const DELIVERY_HEADER: &str = "X-Chat-Delivery";
+
+/// Event types that webhooks can subscribe to.
+const SUBSCRIBABLE_EVENTS: [PayloadEventType; 5] = [
+    PayloadEventType::Message,
+    PayloadEventType::Connected,
+    PayloadEventType::Disconnected,
+    PayloadEventType::Mention,
+    PayloadEventType::System,
+];
+
+/// Receiver of chat events of the subscribed types.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+
+    /// Key of request signatures. Only shown once the webhook is registered.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+
+    pub events: Vec<PayloadEventType>,
+}
+
+impl Webhook {
+    /// Validate a new webhook. A random secret is generated if not given.
+    pub fn new(
+        url: String,
+        events: Vec<PayloadEventType>,
+        secret: Option<String>,
+    ) -> Result<Self, String> {
+        let parsed = reqwest::Url::parse(&url).map_err(|e| format!("invalid url {url:?}: {e}"))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(format!("unsupported url scheme: {}", parsed.scheme()));
+        }
+        if events.is_empty() {
+            return Err("missing `events`".into());
+        }
+        if let Some(event_type) = events.iter().find(|e| !SUBSCRIBABLE_EVENTS.contains(e)) {
+            return Err(format!(
+                "unsupported event type: {}",
+                event_name(event_type)
+            ));
+        }
+        let secret = match secret {
+            Some(secret) if secret.is_empty() => return Err("empty `secret`".into()),
+            Some(secret) => secret,
+            None => uuid::Uuid::new_v4().simple().to_string(),
+        };
+        Ok(Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            url,
+            secret,
+            events,
+        })
+    }
+
+    /// Copy of the webhook without its secret, for listing.
+    pub fn redacted(&self) -> Self {
+        Self {
+            secret: String::new(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Delivery given up after the last attempt, saved as a line of the dead-letter log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub delivery_id: String,
+    pub webhook_id: String,
+    pub url: String,
+    pub attempts: u32,
+    pub error: String,
+
+    /// Time of giving up as Unix timestamp in milliseconds.
+    pub timestamp: u64,
+
+    pub payload: Payload,
+}
+
+/// `sha256=<hex>` signature sent in [`SIGNATURE_HEADER`] of `{timestamp}.{body}`, where
+/// `timestamp` is the value of [`TIMESTAMP_HEADER`].
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Name of event type as used in payloads, e.g. `message`.
+fn event_name(event_type: &PayloadEventType) -> String {
+    serde_json::to_value(event_type)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+pub struct WebhookRegistry {
+    /// File of registered webhooks, which are kept only in memory if not set.
+    file: Option<PathBuf>,
+    /// Held while saving a change, so that changes are saved one at a time.
+    saving: Arc<Mutex<()>>,
+    webhooks: Vec<Webhook>,
+    deliverer: Arc<Deliverer>,
+}
+
+impl Default for WebhookRegistry {
+    fn default() -> Self {
+        Self::from_config(&WebhookConfig::default()).expect("failed to create webhook registry")
+    }
+}
+
+impl fmt::Debug for WebhookRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookRegistry")
+            .field("file", &self.file)
+            .field("webhooks", &self.webhooks.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebhookRegistry {
+    /// Load webhooks registered earlier from the configured file, if it exists.
+    pub fn from_config(config: &WebhookConfig) -> Result<Self, String> {
+        let file = config.file.as_ref().map(PathBuf::from);
+        let webhooks = match &file {
+            Some(path) if path.exists() => load(path)?,
+            _ => Vec::new(),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .user_agent(concat!("chat-backend/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            file,
+            saving: Arc::default(),
+            webhooks,
+            deliverer: Arc::new(Deliverer {
+                client,
+                max_attempts: config.max_attempts.max(1),
+                initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+                dead_letter_file: config.dead_letter_file.as_ref().map(PathBuf::from),
+            }),
+        })
+    }
+
+    pub fn list(&self) -> &[Webhook] {
+        &self.webhooks
+    }
+
+    /// Deliver an event to every webhook subscribed to its type in the background.
+    pub fn notify(&self, payload: &Payload) {
+        let mut subscribers = self
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.events.contains(&payload.event_type))
+            .peekable();
+        if subscribers.peek().is_none() {
+            return;
+        }
+        let body = serde_json::to_string(payload).expect("failed to serialize payload");
+        let event = event_name(&payload.event_type);
+        for webhook in subscribers {
+            let delivery = Delivery {
+                id: uuid::Uuid::new_v4().simple().to_string(),
+                webhook: webhook.clone(),
+                event: event.clone(),
+                body: body.clone(),
+            };
+            tokio::spawn(self.deliverer.clone().deliver(delivery));
+        }
+    }
+}
+
+/// Register a webhook and save the list of webhooks.
+pub async fn register(server_state: &SharedServerState, webhook: Webhook) -> Result<(), String> {
+    update(server_state, |webhooks| {
+        webhooks.push(webhook);
+        true
+    })
+    .await
+    .map(|_| ())
+}
+
+/// Unregister a webhook and save the list of webhooks. Returns whether the webhook existed.
+pub async fn unregister(server_state: &SharedServerState, id: &str) -> Result<bool, String> {
+    update(server_state, |webhooks| {
+        let count = webhooks.len();
+        webhooks.retain(|webhook| webhook.id != id);
+        webhooks.len() < count
+    })
+    .await
+}
+
+/// Apply `change` to the list of webhooks if it can be saved. The file is written without
+/// holding the lock of the server state, while further changes wait for the saving to finish.
+/// Returns whether `change` changed anything.
+async fn update(
+    server_state: &SharedServerState,
+    change: impl FnOnce(&mut Vec<Webhook>) -> bool,
+) -> Result<bool, String> {
+    let (file, saving) = {
+        let server_state = lock_state(server_state).await;
+        let registry = &server_state.webhooks;
+        (registry.file.clone(), registry.saving.clone())
+    };
+    let _saving = saving.lock().await;
+    let mut webhooks = lock_state(server_state).await.webhooks.webhooks.clone();
+    if !change(&mut webhooks) {
+        return Ok(false);
+    }
+    if let Some(path) = &file {
+        save(path, &webhooks).await?;
+    }
+    lock_state(server_state).await.webhooks.webhooks = webhooks;
+    Ok(true)
+}
+
+async fn save(path: &Path, webhooks: &[Webhook]) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(webhooks).map_err(|e| e.to_string())?;
+    // Replace the file at once, so a crash never leaves it half-written
+    let temporary_path = path.with_extension("tmp");
+    let written = async {
+        tokio::fs::write(&temporary_path, json).await?;
+        tokio::fs::rename(&temporary_path, path).await
+    };
+    written
+        .await
+        .map_err(|e| format!("unable to save webhooks to {}: {e}", path.display()))
+}
+
+fn load(path: &Path) -> Result<Vec<Webhook>, String> {
+    let json = std::fs::read(path)
+        .map_err(|e| format!("unable to read webhooks from {}: {e}", path.display()))?;
+    serde_json::from_slice(&json)
+        .map_err(|e| format!("invalid webhooks in {}: {e}", path.display()))
+}
+
+struct Delivery {
+    id: String,
+    webhook: Webhook,
+    event: String,
+    body: String,
+}
+
+struct Deliverer {
+    client: reqwest::Client,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    dead_letter_file: Option<PathBuf>,
+}
+
+impl Deliverer {
+    /// POST the event until the receiver responds with a success status, doubling the wait
+    /// between attempts.
+    async fn deliver(self: Arc<Self>, delivery: Delivery) {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            // Retries are signed with the time they are sent at
+            let timestamp = unix_timestamp_millis();
+            let signature = sign(
+                &delivery.webhook.secret,
+                timestamp,
+                delivery.body.as_bytes(),
+            );
+            let result = self
+                .client
+                .post(&delivery.webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(TIMESTAMP_HEADER, timestamp)
+                .header(SIGNATURE_HEADER, &signature)
+                .header(EVENT_HEADER, &delivery.event)
+                .header(DELIVERY_HEADER, &delivery.id)
+                .body(delivery.body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            let error = match result {
+                Ok(_) => {
+                    tracing::trace!(
+                        event = %delivery.event,
+                        webhook_id = %delivery.webhook.id,
+                        "event is delivered to webhook"
+                    );
+                    return;
+                }
+                Err(e) => e.to_string(),
+            };
+            if attempt >= self.max_attempts {
+                self.give_up(delivery, attempt, error).await;
+                return;
+            }
+            tracing::info!(
+                delivery_id = %delivery.id,
+                webhook_id = %delivery.webhook.id,
+                ?backoff,
+                %error,
+                "webhook delivery failed, retrying"
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+            attempt += 1;
+        }
+    }
+
+    async fn give_up(&self, delivery: Delivery, attempts: u32, error: String) {
+        tracing::error!(
+            delivery_id = %delivery.id,
+            webhook_id = %delivery.webhook.id,
+            attempts,
+            %error,
+            "webhook delivery is given up"
+        );
+        let Some(path) = &self.dead_letter_file else {
+            return;
+        };
+        let dead_letter = DeadLetter {
+            delivery_id: delivery.id,
+            webhook_id: delivery.webhook.id,
+            url: delivery.webhook.url,
+            attempts,
+            error,
+            timestamp: unix_timestamp_millis(),
+            payload: serde_json::from_str(&delivery.body).expect("invalid payload"),
+        };
+        let mut line = serde_json::to_string(&dead_letter).expect("failed to serialize");
+        line.push('\n');
+        let written = async {
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?
+                .write_all(line.as_bytes())
+                .await
+        };
+        if let Err(e) = written.await {
+            tracing::error!(path = %path.display(), error = %e, "unable to write dead letter");
+        }
+    }
+}
@@ -3,78 +3,237 @@
 //!
 //! Messages sent by a client is broadcasted to all other clients currently connected to the chat.
 //! Client is removed from the chat on disconnect.
+//!
+//! The opening handshake is done with tungstenite, which negotiates subprotocols offered as a
+//! list, while frames are exchanged with soketto, which supports the permessage-deflate extension.
 
 use std::net::SocketAddr;
 
-use futures_util::{SinkExt, StreamExt, TryStreamExt};
+use futures_util::{Stream, TryStreamExt};
+use soketto::{
+    connection::{self, Builder, Mode},
+    extension::Extension,
+    Data,
+};
 use tokio::{
+    io::BufWriter,
     net::{TcpListener, TcpStream},
     sync::mpsc,
 };
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{Request, Response},
+    http::{
+        header::{SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL},
+        HeaderValue,
+    },
+    Message,
+};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use tracing::{field, Instrument, Span};
+
+use crate::{
+    chat,
+    compression::PerMessageDeflate,
+    lock_state, logging,
+    protocol::{self, Protocol},
+    ClientId, Payload, PayloadEventType, SharedServerState,
+};
+
+/// Largest message accepted from clients, after decompression. Chat events are small, larger
+/// files are uploaded as attachments through the REST API.
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
 
-use crate::{ChatClient, Payload, PayloadEventType, ServerState, SharedServerState, Tx};
+type Socket = Compat<BufWriter<TcpStream>>;
 
 /// Entry for starting WebSocket server to manage chat operations.
 pub async fn run_ws_server(listener: TcpListener, server_state: SharedServerState) {
     while let Ok((tcp_stream, client_address)) = listener.accept().await {
-        tokio::spawn(client_handler(
-            tcp_stream,
-            client_address,
-            server_state.clone(),
-        ));
+        let connection_id = lock_state(&server_state).await.next_connection_id();
+        // Username is recorded once the client joins the chat
+        let span = tracing::info_span!(
+            "connection",
+            connection_id,
+            %client_address,
+            username = field::Empty
+        );
+        tokio::spawn(
+            client_handler(tcp_stream, client_address, server_state.clone()).instrument(span),
+        );
     }
 }
 
 /// Task for accepting client, message broadcasting and disconnect when finished.
 async fn client_handler(
-    tcp_stream: TcpStream,
+    mut tcp_stream: TcpStream,
     client_address: SocketAddr,
     server_state: SharedServerState,
 ) {
-    let ws_stream = tokio_tungstenite::accept_async(tcp_stream)
-        .await
-        .expect("websocket handshake error");
+    let client_id = ClientId::WebSocket(client_address);
+    let (compression, metrics, log_message_bodies) = {
+        let server_state = lock_state(&server_state).await;
+        (
+            server_state.compression.clone(),
+            server_state.metrics.clone(),
+            server_state.log_message_bodies,
+        )
+    };
+    let mut protocol = Protocol::default();
+    let mut deflate = None;
+    // Error type is dictated by tungstenite
+    #[allow(clippy::result_large_err)]
+    let negotiate = |request: &Request, mut response: Response| {
+        let offered = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        if let Some(version) = offered(SEC_WEBSOCKET_PROTOCOL).and_then(Protocol::negotiate) {
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(version.subprotocol()),
+            );
+            protocol = version;
+        }
+        if let Some(extension) = offered(SEC_WEBSOCKET_EXTENSIONS).and_then(|offered| {
+            PerMessageDeflate::negotiate(offered, &compression, MAX_MESSAGE_BYTES)
+        }) {
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_EXTENSIONS,
+                HeaderValue::from_str(&extension.response_header())
+                    .expect("invalid extension header"),
+            );
+            deflate = Some(extension);
+        }
+        Ok(response)
+    };
+    // Frames are read by soketto from the socket, so none may be left in the buffer of
+    // tungstenite. Clients must wait for the handshake response before sending frames, and
+    // tungstenite fails the handshake of clients sending anything after their request.
+    if let Err(e) = tokio_tungstenite::accept_hdr_async(&mut tcp_stream, negotiate).await {
+        metrics.handshake_failures.inc();
+        tracing::info!(error = %e, "websocket handshake failed");
+        return;
+    }
+    let compressed = deflate.is_some();
+    let mut builder = Builder::new(BufWriter::new(tcp_stream).compat(), Mode::Server);
+    builder.set_max_message_size(MAX_MESSAGE_BYTES);
+    if let Some(extension) = deflate {
+        builder.add_extensions([Box::new(extension) as Box<dyn Extension + Send>]);
+    }
+    let (mut ws_writer, ws_reader) = builder.finish();
     let (tx, mut rx) = mpsc::unbounded_channel();
-    log::trace!("received new client connection");
+    tracing::trace!(
+        protocol = protocol.subprotocol(),
+        compression = compressed,
+        "received new client connection"
+    );
 
-    // Duplex stream, use it as reader/writer
-    let (mut ws_writer, ws_reader) = ws_stream.split();
+    // Token authenticates REST API requests of the member once the client joins
+    let session_token = uuid::Uuid::new_v4().to_string();
+    if let Some(hello) = protocol::hello(&*lock_state(&server_state).await, protocol) {
+        let hello = Payload {
+            session_token: Some(session_token.clone()),
+            ..hello
+        };
+        tx.send(protocol.encoding.encode(&hello))
+            .expect("unable to send hello");
+    }
 
     // Forward messages coming from current connected single client to all other clients
-    let send_broadcast = ws_reader.try_for_each(|msg| {
-        let server_state = server_state.clone();
+    let send_broadcast = incoming_messages(ws_reader).try_for_each(|msg| {
+        let shared_state = server_state.clone();
         let tx = tx.clone();
+        let session_token = session_token.clone();
         async move {
-            // Skip Ping, Pong and Close messages
-            if msg.is_text() {
-                log::trace!("received message {:?}", msg.to_text().unwrap());
+            tracing::trace!(
+                len = msg.len(),
+                body = logging::message_body(&msg, log_message_bodies),
+                "received message"
+            );
 
-                let mut server_state = server_state.lock().await;
+            let mut server_state = lock_state(&shared_state).await;
 
+            let mut payload = match protocol.decode(&msg) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    tracing::info!(error = %e, "invalid event is ignored");
+                    return Ok(());
+                }
+            };
+            match server_state.clients.get(&client_id) {
+                // Events are sent as the member the client has joined as, whatever username
+                // they carry
+                Some(client) => payload.username = client.username.clone(),
                 // User connecting for the first time
-                let payload: Payload = serde_json::from_str(&msg.to_string()).unwrap();
-                if payload.event_type == PayloadEventType::Connected {
-                    if !server_state.clients.contains_key(&client_address) {
-                        match add_client(
-                            &mut server_state,
-                            client_address,
-                            tx.clone(),
-                            &msg.to_text().unwrap(),
-                        )
-                        .await
-                        {
-                            Ok(()) => (),
-                            Err(e) => {
-                                log::error!("user add error: {e}");
-                                return Err(
-                                    tokio_tungstenite::tungstenite::Error::ConnectionClosed,
-                                );
-                            }
-                        }
+                None if payload.event_type == PayloadEventType::Connected => {
+                    if let Err(e) = chat::add_client(
+                        &mut server_state,
+                        client_id,
+                        tx.clone(),
+                        protocol,
+                        &payload.username,
+                    ) {
+                        tracing::error!(username = %payload.username, error = %e, "user add error");
+                        return Err(connection::Error::Closed);
                     }
+                    server_state.sessions.insert(session_token, client_id);
+                    Span::current().record("username", payload.username.as_str());
+                    // Sent before any live event to avoid gaps and duplicates
+                    chat::send_history_batch(&server_state, &server_state.clients[&client_id]);
+                    chat::send_motd(&server_state, &server_state.clients[&client_id]);
+                    chat::broadcast(&mut server_state, payload, Some(client_id)).await;
+                    return Ok(());
+                }
+                None => {
+                    tracing::info!(
+                        event_type = ?payload.event_type,
+                        "event of client that has not joined is ignored"
+                    );
+                    return Ok(());
                 }
-
-                broadcast(&mut server_state, &msg.to_string(), Some(client_address)).await;
+            }
+            match payload.event_type {
+                PayloadEventType::Message => {
+                    if let Err(e) = chat::send_message(
+                        &mut server_state,
+                        shared_state.clone(),
+                        payload,
+                        Some(client_id),
+                    )
+                    .await
+                    {
+                        tracing::info!(error = %e, "message is rejected");
+                    }
+                }
+                PayloadEventType::React | PayloadEventType::Unreact => {
+                    if let Err(e) = chat::update_reactions(&mut server_state, payload) {
+                        tracing::info!(error = %e, "reaction is ignored");
+                    }
+                }
+                PayloadEventType::Read => {
+                    if let Err(e) =
+                        chat::update_read_position(&mut server_state, payload, client_id)
+                    {
+                        tracing::info!(error = %e, "read receipt is ignored");
+                    }
+                }
+                // Joining is handled above and leaving once the connection is closed, so that
+                // only the server announces them
+                PayloadEventType::Connected | PayloadEventType::Disconnected => {
+                    tracing::info!(
+                        event_type = ?payload.event_type,
+                        "presence event of joined client is ignored"
+                    );
+                }
+                // Only sent by the server
+                PayloadEventType::Reactions
+                | PayloadEventType::Replies
+                | PayloadEventType::Mention
+                | PayloadEventType::MessagePreview
+                | PayloadEventType::HistoryBatch
+                | PayloadEventType::Hello
+                | PayloadEventType::System => (),
             }
             Ok(())
         }
@@ -83,9 +242,17 @@ async fn client_handler(
     // Receive message broadcasted by others
     let receive_broadcast = async move {
         while let Some(msg) = rx.recv().await {
-            ws_writer.send(msg.into()).await?;
+            metrics.outbound_queue_depth.observe(rx.len() as f64);
+            match msg {
+                Message::Text(text) => ws_writer.send_text(text.as_str()).await?,
+                Message::Binary(bytes) => ws_writer.send_binary(bytes).await?,
+                // Client is disconnected by the server, e.g. kicked by an admin
+                Message::Close(_) => return ws_writer.close().await,
+                _ => continue,
+            }
+            ws_writer.flush().await?;
         }
-        Ok::<(), tokio_tungstenite::tungstenite::Error>(())
+        Ok::<(), connection::Error>(())
     };
 
     // Use tokio::select!() instead of tokio::try_join!() to avoid deadlock. select!() waits for
@@ -96,84 +263,23 @@ async fn client_handler(
         _ = receive_broadcast => {},
     }
 
-    remove_client(server_state.clone(), client_address).await;
+    chat::remove_client(server_state.clone(), client_id).await;
 }
 
-async fn add_client(
-    server_state: &mut ServerState,
-    client_address: SocketAddr,
-    tx: Tx,
-    msg: &str,
-) -> Result<(), String> {
-    let payload: Payload = serde_json::from_str(msg).unwrap();
-    if payload.event_type == PayloadEventType::Connected {
-        let username = payload.username;
-        if server_state
-            .clients
-            .iter()
-            .find(|(_addr, client)| client.username == username)
-            .is_some()
-        {
-            return Err(format!("user already exists: {username}"));
-        }
-
-        server_state.clients.insert(
-            client_address,
-            ChatClient {
-                username: username.clone(),
-                tx,
+/// Data messages received from a client. Ping and Pong messages are answered by soketto, while a
+/// Close message ends the stream with an error.
+fn incoming_messages(
+    ws_reader: connection::Receiver<Socket>,
+) -> impl Stream<Item = Result<Message, connection::Error>> {
+    futures_util::stream::try_unfold(ws_reader, |mut ws_reader| async move {
+        let mut data = Vec::new();
+        let msg = match ws_reader.receive_data(&mut data).await? {
+            Data::Text(_) => match String::from_utf8(data) {
+                Ok(text) => Message::text(text),
+                Err(e) => return Err(connection::Error::Utf8(e.utf8_error())),
             },
-        );
-    }
-
-    Ok(())
-}
-
-/// Send out message to multiple users in the chat. `sender` is excluded from
-/// the list of message recipients. If `sender` is not specified, all members of the
-/// chat receive the message and is treated as a server status message.
-async fn broadcast(server_state: &mut ServerState, msg: &String, sender: Option<SocketAddr>) {
-    let broadcast_recipients = server_state
-        .clients
-        .iter()
-        .filter(|(addr, _client)| {
-            // Exclude message sender from broadcast
-            sender.map_or(true, |sender_addr| addr != &&sender_addr)
-        })
-        .map(|(_, ws_sink)| ws_sink);
-    for broadcast_user in broadcast_recipients {
-        broadcast_user
-            .tx
-            .send(msg.clone())
-            .expect("unable to broadcast message");
-        log::trace!("sent {:?} to {}", msg, broadcast_user.username);
-    }
-
-    // Save message to history
-    server_state
-        .history
-        .push(serde_json::from_str(msg).unwrap());
-}
-
-/// Remove user from the list of users. Notifies remaining members in the chat about
-/// the disconnected users.
-async fn remove_client(server_state: SharedServerState, disconnected_client_address: SocketAddr) {
-    let mut server_state = server_state.lock().await;
-    let Some(disconnected_client) = server_state.clients.get(&disconnected_client_address) else {
-        return;
-    };
-    let username = disconnected_client.username.clone();
-
-    // Update client list
-    server_state.clients.remove(&disconnected_client_address);
-    log::trace!("user {:?} left the chat", username);
-
-    // Notify remaining chat members
-    let payload = Payload {
-        event_type: PayloadEventType::Disconnected,
-        username: username.clone(),
-        message: None,
-    };
-    let j = serde_json::to_string(&payload).unwrap();
-    broadcast(&mut server_state, &j, None).await;
+            Data::Binary(_) => Message::binary(data),
+        };
+        Ok(Some((msg, ws_reader)))
+    })
 }
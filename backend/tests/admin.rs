@@ -0,0 +1,339 @@
+mod common;
+
+use std::time::Duration;
+
+use chat_backend::{
+    client::{Client, ClientConfig, Event},
+    ConnectedUser, Payload, PayloadEventType, ServerState, ServerStats, Transport,
+};
+use common::{wait_for, TestServer, ADMIN_TOKEN, TIMEOUT_SECONDS};
+use reqwest::{Method, StatusCode};
+
+/// Start a server with admin endpoints enabled.
+async fn spawn_server() -> TestServer {
+    TestServer::spawn(ServerState {
+        admin_token: Some(ADMIN_TOKEN.into()),
+        history_replay_count: 50,
+        ..Default::default()
+    })
+    .await
+}
+
+async fn ban(server: &TestServer, username: &str) -> StatusCode {
+    server
+        .admin(Method::POST, "/admin/bans")
+        .json(&serde_json::json!({ "username": username }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+/// Purge history, returning the number of removed events.
+async fn purge_history(server: &TestServer, query: &str) -> u64 {
+    let response = server
+        .admin(Method::DELETE, &format!("/admin/history{query}"))
+        .send()
+        .await
+        .unwrap();
+    let result: serde_json::Value = response.json().await.unwrap();
+    result["removed"].as_u64().unwrap()
+}
+
+#[tokio::test]
+async fn admin_endpoints_require_token() {
+    let server = spawn_server().await;
+
+    for path in ["/admin/users", "/admin/bans", "/admin/stats"] {
+        let response = reqwest::Client::new()
+            .get(server.url(path))
+            .bearer_auth("wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn connected_users_and_stats_are_listed() {
+    let server = spawn_server().await;
+    let user1 = server.join("user1").await;
+    let _user2 = server.join("user2").await;
+    user1.send_message("hello").unwrap();
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        while server.server_state.lock().await.history.len() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out");
+
+    let response = server.admin(Method::GET, "/admin/users").send().await;
+    let users: Vec<ConnectedUser> = response.unwrap().json().await.unwrap();
+    let usernames: Vec<_> = users.iter().map(|user| user.username.as_str()).collect();
+    assert_eq!(usernames, vec!["user1", "user2"]);
+    assert_eq!(users[0].transport, Transport::WebSocket);
+    assert!(users[0].address.is_some());
+
+    let response = server.admin(Method::GET, "/admin/stats").send().await;
+    let stats: ServerStats = response.unwrap().json().await.unwrap();
+    assert_eq!(stats.connected_users, 2);
+    assert_eq!(stats.websocket_clients, 2);
+    assert_eq!(stats.history_events, 3);
+    assert_eq!(stats.messages, 1);
+    assert_eq!(stats.last_history_id, 3);
+}
+
+#[tokio::test]
+async fn kicked_user_leaves_the_chat() {
+    let server = spawn_server().await;
+    let mut user1 = server.join("user1").await;
+    let mut user2 = server.join("user2").await;
+
+    let response = server
+        .admin(Method::POST, "/admin/users/user2/kick")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    wait_for(&mut user2, |event| *event == Event::ConnectionLost).await;
+    let left = wait_for(&mut user1, |event| matches!(event, Event::Left { .. })).await;
+    assert_eq!(
+        left,
+        Event::Left {
+            username: "user2".into()
+        }
+    );
+
+    let response = server
+        .admin(Method::POST, "/admin/users/user2/kick")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn users_are_kicked_while_others_keep_sending() {
+    let server = spawn_server().await;
+    let mut observer = server.join("observer").await;
+    let sender = server.join("sender").await;
+    // Usernames are percent-encoded in paths
+    let mut kicked = server.join("ops/team?#1").await;
+
+    let sending = tokio::spawn(async move {
+        for i in 0..200 {
+            sender.send_message(format!("message {i}")).unwrap();
+            tokio::task::yield_now().await;
+        }
+        sender
+    });
+    let response = server
+        .admin(Method::POST, "/admin/users/ops%2Fteam%3F%231/kick")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    wait_for(&mut kicked, |event| *event == Event::ConnectionLost).await;
+    let sender = sending.await.unwrap();
+
+    // Connection of the sender survives broadcasting to the leaving user
+    wait_for(&mut observer, |event| {
+        matches!(event, Event::Message(payload) if payload.message.as_deref() == Some("message 199"))
+    })
+    .await;
+    sender.send_message("still here").unwrap();
+    wait_for(&mut observer, |event| {
+        matches!(event, Event::Message(payload) if payload.message.as_deref() == Some("still here"))
+    })
+    .await;
+    let mut usernames: Vec<_> = server
+        .server_state
+        .lock()
+        .await
+        .clients
+        .values()
+        .map(|client| client.username.clone())
+        .collect();
+    usernames.sort();
+    assert_eq!(usernames, vec!["observer", "sender"]);
+}
+
+#[tokio::test]
+async fn banned_user_cannot_join_until_unbanned() {
+    let server = spawn_server().await;
+    let mut user1 = server.join("user1").await;
+
+    assert_eq!(ban(&server, "user1").await, StatusCode::CREATED);
+    assert_eq!(ban(&server, "user1").await, StatusCode::CONFLICT);
+    wait_for(&mut user1, |event| *event == Event::ConnectionLost).await;
+
+    // Connection is closed instead of joining
+    let mut banned = Client::connect(ClientConfig {
+        reconnect_delay: None,
+        ..ClientConfig::new(&server.ws_url, "user1")
+    })
+    .await
+    .unwrap();
+    wait_for(&mut banned, |event| *event == Event::ConnectionLost).await;
+    let response = server.admin(Method::GET, "/admin/bans").send().await;
+    let bans: Vec<String> = response.unwrap().json().await.unwrap();
+    assert_eq!(bans, vec!["user1"]);
+
+    let response = server
+        .admin(Method::DELETE, "/admin/bans/user1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let _user1 = server.join("user1").await;
+    assert_eq!(server.server_state.lock().await.clients.len(), 1);
+}
+
+#[tokio::test]
+async fn announcements_are_sent_to_every_member() {
+    let server = spawn_server().await;
+    let mut user1 = server.join("user1").await;
+
+    let response = server
+        .admin(Method::POST, "/admin/announcements")
+        .json(&serde_json::json!({ "message": "maintenance at 18:00" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let announcement: Payload = response.json().await.unwrap();
+    assert_eq!(announcement.event_type, PayloadEventType::System);
+    assert_eq!(
+        announcement.message.as_deref(),
+        Some("maintenance at 18:00")
+    );
+
+    let received = wait_for(&mut user1, |event| matches!(event, Event::System { .. })).await;
+    assert_eq!(
+        received,
+        Event::System {
+            message: "maintenance at 18:00".into()
+        }
+    );
+    let server_state = server.server_state.lock().await;
+    assert_eq!(server_state.history.last(), Some(&announcement));
+}
+
+#[tokio::test]
+async fn announcements_are_saved_without_presence_events() {
+    let server = spawn_server().await;
+    server
+        .server_state
+        .lock()
+        .await
+        .retention
+        .keep_presence_events = false;
+    let _user1 = server.join("user1").await;
+
+    let response = server
+        .admin(Method::POST, "/admin/announcements")
+        .json(&serde_json::json!({ "message": "maintenance at 18:00" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let announcement: Payload = response.json().await.unwrap();
+    assert_eq!(
+        announcement.message.as_deref(),
+        Some("maintenance at 18:00")
+    );
+    // Joining of user1 took the identifier before the announcement
+    assert_eq!(announcement.id, Some(2));
+
+    let server_state = server.server_state.lock().await;
+    assert_eq!(server_state.history, vec![announcement]);
+}
+
+#[tokio::test]
+async fn message_of_the_day_is_sent_privately_to_joining_clients() {
+    let server = spawn_server().await;
+    server.server_state.lock().await.motd = Some("welcome".into());
+
+    let mut user1 = Client::connect(ClientConfig::new(&server.ws_url, "user1"))
+        .await
+        .unwrap();
+    wait_for(&mut user1, |event| matches!(event, Event::History { .. })).await;
+    let motd = wait_for(&mut user1, |_| true).await;
+    assert_eq!(
+        motd,
+        Event::System {
+            message: "welcome".into()
+        }
+    );
+
+    // Changed at runtime for clients joining afterwards
+    let set_motd = |message: Option<&str>| {
+        server
+            .admin(Method::PUT, "/admin/motd")
+            .json(&serde_json::json!({ "message": message }))
+            .send()
+    };
+    let response = set_motd(Some("new rules")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = server.admin(Method::GET, "/admin/motd").send().await;
+    let motd: serde_json::Value = response.unwrap().json().await.unwrap();
+    assert_eq!(motd["message"], "new rules");
+
+    let mut user2 = Client::connect(ClientConfig::new(&server.ws_url, "user2"))
+        .await
+        .unwrap();
+    wait_for(&mut user2, |event| matches!(event, Event::History { .. })).await;
+    let motd = wait_for(&mut user2, |_| true).await;
+    assert_eq!(
+        motd,
+        Event::System {
+            message: "new rules".into()
+        }
+    );
+
+    // Only the joining client receives it, and it is not saved to history
+    user2.send_message("hi").unwrap();
+    let received = wait_for(&mut user1, |event| {
+        matches!(event, Event::System { .. } | Event::Message(_))
+    })
+    .await;
+    assert!(matches!(received, Event::Message(_)));
+    assert!(server
+        .server_state
+        .lock()
+        .await
+        .history
+        .iter()
+        .all(|payload| payload.event_type != PayloadEventType::System));
+
+    set_motd(None).await.unwrap();
+    assert_eq!(server.server_state.lock().await.motd, None);
+}
+
+#[tokio::test]
+async fn history_is_purged() {
+    let server = spawn_server().await;
+    {
+        let mut server_state = server.server_state.lock().await;
+        for message in ["first", "second", "third"] {
+            let payload = Payload {
+                event_type: PayloadEventType::Message,
+                username: "user1".into(),
+                message: Some(message.into()),
+                ..Default::default()
+            };
+            chat_backend::chat::broadcast(&mut server_state, payload, None).await;
+        }
+    }
+
+    assert_eq!(purge_history(&server, "?before=3").await, 2);
+    let history = server.server_state.lock().await.history.clone();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].message.as_deref(), Some("third"));
+    assert_eq!(server.server_state.lock().await.stats().messages, 1);
+
+    assert_eq!(purge_history(&server, "").await, 1);
+    assert!(server.server_state.lock().await.history.is_empty());
+}
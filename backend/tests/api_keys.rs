@@ -0,0 +1,147 @@
+mod common;
+
+use chat_backend::{configuration::ApiKey, Payload, ServerState, SharedServerState};
+use common::{
+    join, receive_history, receive_joined, receive_message, spawn_servers, ADMIN_TOKEN, HOST,
+};
+use reqwest::StatusCode;
+use tokio::sync::Mutex;
+
+fn server_state_with_api_key() -> SharedServerState {
+    SharedServerState::new(Mutex::new(ServerState {
+        admin_token: Some(ADMIN_TOKEN.into()),
+        api_keys: vec![ApiKey {
+            name: "deploy-bot".into(),
+            key: "bot-secret".into(),
+        }],
+        history_replay_count: 50,
+        ..Default::default()
+    }))
+}
+
+async fn post_message(rest_port: u16, token: &str, message: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{HOST}:{rest_port}/messages"))
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "message": message }))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn list_api_keys(rest_port: u16, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("http://{HOST}:{rest_port}/admin/api-keys"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn api_key_messages_are_flagged_as_bot() {
+    let server_state = server_state_with_api_key();
+    let (rest_port, ws_port) = spawn_servers(server_state.clone()).await;
+    let mut ws_client = join(ws_port, "user1").await;
+    receive_history(&mut ws_client).await;
+
+    let response = post_message(rest_port, "bot-secret", "deployed v1.2").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let saved: Payload = response.json().await.unwrap();
+    assert_eq!(saved.username, "deploy-bot");
+    assert!(saved.bot);
+
+    let received = receive_message(&mut ws_client).await;
+    assert_eq!(received, saved);
+    assert_eq!(
+        server_state.lock().await.history_entry(saved.id.unwrap()),
+        Some(&saved)
+    );
+}
+
+#[tokio::test]
+async fn invalid_api_key_is_rejected() {
+    let server_state = server_state_with_api_key();
+    let (rest_port, _) = spawn_servers(server_state.clone()).await;
+
+    let response = post_message(rest_port, "bot-secre", "hello").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(server_state.lock().await.history.is_empty());
+}
+
+#[tokio::test]
+async fn members_cannot_send_as_bot() {
+    let (_, ws_port) = spawn_servers(server_state_with_api_key()).await;
+    let mut ws_client = join(ws_port, "user1").await;
+    receive_history(&mut ws_client).await;
+    let sender = join(ws_port, "user2").await;
+    receive_joined(&mut ws_client, "user2").await;
+
+    let message = Payload {
+        username: "user2".into(),
+        message: Some("beep boop".into()),
+        bot: true,
+        ..Default::default()
+    };
+    sender.send(message).unwrap();
+    let received = receive_message(&mut ws_client).await;
+    assert_eq!(received.message.as_deref(), Some("beep boop"));
+    assert!(!received.bot);
+}
+
+#[tokio::test]
+async fn api_keys_are_managed_by_admin() {
+    let (rest_port, _) = spawn_servers(server_state_with_api_key()).await;
+    let client = reqwest::Client::new();
+    let api_keys_url = format!("http://{HOST}:{rest_port}/admin/api-keys");
+
+    assert_eq!(
+        list_api_keys(rest_port, "wrong").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let response = client
+        .post(&api_keys_url)
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&serde_json::json!({ "name": "ci" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: ApiKey = response.json().await.unwrap();
+    assert_eq!(created.name, "ci");
+    let response = client
+        .post(&api_keys_url)
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&serde_json::json!({ "name": "ci" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let names: Vec<String> = list_api_keys(rest_port, ADMIN_TOKEN)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(names, vec!["deploy-bot", "ci"]);
+    let response = post_message(rest_port, &created.key, "build passed").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = client
+        .delete(format!("{api_keys_url}/ci"))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .delete(format!("{api_keys_url}/ci"))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = post_message(rest_port, &created.key, "build failed").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
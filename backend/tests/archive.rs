@@ -0,0 +1,255 @@
+mod common;
+
+use chat_backend::{
+    archive::{self, ExportFormat, ImportSummary},
+    attachment::Attachment,
+    search::SearchQuery,
+    Payload, PayloadEventType, Reaction, ServerState,
+};
+use common::{TestServer, ADMIN_TOKEN};
+use reqwest::StatusCode;
+
+/// Start a server with admin endpoints enabled and the given history.
+async fn spawn_server(history: Vec<Payload>) -> TestServer {
+    TestServer::spawn(ServerState {
+        admin_token: Some(ADMIN_TOKEN.into()),
+        history,
+        ..Default::default()
+    })
+    .await
+}
+
+fn sample_history() -> Vec<Payload> {
+    vec![
+        Payload {
+            event_type: PayloadEventType::Connected,
+            username: "user1".into(),
+            id: Some(1),
+            timestamp: Some(1_000),
+            ..Default::default()
+        },
+        Payload {
+            username: "user1".into(),
+            message: Some("hello <world> & everyone".into()),
+            id: Some(2),
+            timestamp: Some(2_000),
+            reactions: vec![Reaction {
+                emoji: "👍".into(),
+                count: 1,
+                usernames: vec!["user2".into()],
+            }],
+            reply_count: 1,
+            ..Default::default()
+        },
+        Payload {
+            username: "user2".into(),
+            message: Some("see file".into()),
+            id: Some(5),
+            timestamp: Some(5_000),
+            reply_to: Some(2),
+            attachments: vec![Attachment {
+                id: "abc".into(),
+                filename: "notes.txt".into(),
+                content_type: "text/plain".into(),
+                size: 3,
+            }],
+            ..Default::default()
+        },
+        Payload {
+            event_type: PayloadEventType::Disconnected,
+            username: "user1".into(),
+            id: Some(6),
+            timestamp: Some(6_000),
+            ..Default::default()
+        },
+    ]
+}
+
+async fn export(server: &TestServer, format: &str) -> reqwest::Response {
+    reqwest::get(server.url(&format!("/history/export?format={format}")))
+        .await
+        .expect("failed to execute request")
+}
+
+async fn import(server: &TestServer, token: &str, dump: String) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(server.url("/history/import"))
+        .bearer_auth(token)
+        .body(dump)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn jsonl_export_round_trips_through_import() {
+    let source = spawn_server(sample_history()).await;
+
+    let response = export(&source, "jsonl").await;
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        ExportFormat::Jsonl.content_type()
+    );
+    let dump = response.text().await.unwrap();
+    assert_eq!(dump.lines().count(), 4);
+
+    let target = spawn_server(Vec::new()).await;
+    let response = import(&target, ADMIN_TOKEN, dump).await;
+    assert!(response.status().is_success());
+    let summary: ImportSummary = response.json().await.unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            imported: 4,
+            skipped: 0
+        }
+    );
+
+    let target_state = target.server_state.lock().await;
+    assert_eq!(target_state.history, sample_history());
+    assert_eq!(target_state.last_history_id, 6);
+    assert_eq!(target_state.stats().messages, 2);
+    let hits = target_state
+        .search_index
+        .search(&SearchQuery {
+            query: "file".into(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, 5);
+}
+
+#[tokio::test]
+async fn import_merges_with_existing_history() {
+    let mut history = sample_history();
+    let server = spawn_server(history.split_off(2)).await;
+    server.server_state.lock().await.last_history_id = 6;
+
+    // Existing events are skipped, so importing a full dump is harmless
+    let dump: String = sample_history()
+        .iter()
+        .map(|payload| ExportFormat::Jsonl.render(payload))
+        .collect();
+    let response = import(&server, ADMIN_TOKEN, dump).await;
+    assert!(response.status().is_success());
+    let summary: ImportSummary = response.json().await.unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            imported: 2,
+            skipped: 2
+        }
+    );
+
+    let server_state = server.server_state.lock().await;
+    assert_eq!(server_state.history, sample_history());
+    assert_eq!(server_state.last_history_id, 6);
+    assert!(server_state.history_entry(2).is_some());
+}
+
+#[tokio::test]
+async fn invalid_dump_is_rejected_as_a_whole() {
+    let server = spawn_server(Vec::new()).await;
+
+    let dump = format!(
+        "{}{}\n",
+        ExportFormat::Jsonl.render(&sample_history()[0]),
+        r#"{"event_type":"message","username":"user1","message":"no id"}"#
+    );
+    let response = import(&server, ADMIN_TOKEN, dump).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().starts_with("line 2:"));
+
+    let response = import(&server, ADMIN_TOKEN, "not json\n".into()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    assert!(server.server_state.lock().await.history.is_empty());
+}
+
+#[tokio::test]
+async fn dumps_over_default_body_limit_are_imported() {
+    let server = spawn_server(Vec::new()).await;
+
+    let dump: String = (1..=2000)
+        .map(|id| {
+            ExportFormat::Jsonl.render(&Payload {
+                username: "user1".into(),
+                message: Some(format!("message {id} {}", "x".repeat(200))),
+                id: Some(id),
+                timestamp: Some(id),
+                ..Default::default()
+            })
+        })
+        .collect();
+    assert!(dump.len() > 256 * 1024);
+
+    let response = import(&server, ADMIN_TOKEN, dump).await;
+    assert!(response.status().is_success());
+    assert_eq!(server.server_state.lock().await.history.len(), 2000);
+}
+
+#[tokio::test]
+async fn import_requires_admin_token() {
+    let server = spawn_server(Vec::new()).await;
+    let dump = ExportFormat::Jsonl.render(&sample_history()[0]);
+
+    let response = import(&server, "wrong", dump).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(server.server_state.lock().await.history.is_empty());
+}
+
+#[tokio::test]
+async fn plaintext_export_matches_chat_view() {
+    let server = spawn_server(sample_history()).await;
+
+    let response = export(&server, "txt").await;
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.text().await.unwrap(),
+        "user1 has joined the chat.\n\
+         [user1]: hello <world> & everyone  👍 1\n\
+         [user2] (reply to #2): see file  [notes.txt](/api/attachments/abc)\n\
+         user1 has left the chat.\n"
+    );
+}
+
+#[tokio::test]
+async fn html_export_escapes_messages() {
+    let server = spawn_server(sample_history()).await;
+
+    let response = export(&server, "html").await;
+    assert!(response.status().is_success());
+    let html = response.text().await.unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<li>[user1]: hello &lt;world&gt; &amp; everyone  👍 1</li>"));
+    assert!(html.trim_end().ends_with("</html>"));
+}
+
+#[tokio::test]
+async fn large_history_is_exported_completely() {
+    let history = (1..=1234)
+        .map(|id| Payload {
+            username: "user1".into(),
+            message: Some(format!("message {id}")),
+            id: Some(id),
+            timestamp: Some(id),
+            ..Default::default()
+        })
+        .collect();
+    let server = spawn_server(history).await;
+
+    let dump = export(&server, "jsonl").await.text().await.unwrap();
+    let entries = archive::parse_jsonl(&dump).unwrap();
+    let ids: Vec<_> = entries.iter().map(|payload| payload.id.unwrap()).collect();
+    assert_eq!(ids, (1..=1234).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn unknown_export_format_is_rejected() {
+    let server = spawn_server(Vec::new()).await;
+
+    let response = export(&server, "pdf").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
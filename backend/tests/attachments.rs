@@ -0,0 +1,147 @@
+mod common;
+
+use chat_backend::{
+    attachment::{Attachment, AttachmentStore},
+    configuration::AttachmentsConfig,
+    ServerState,
+};
+use common::TestServer;
+use reqwest::{multipart, StatusCode};
+
+const MAX_SIZE_BYTES: usize = 16;
+const MAX_TOTAL_BYTES: usize = 24;
+
+async fn spawn_server() -> TestServer {
+    let config = AttachmentsConfig {
+        directory: std::env::temp_dir()
+            .join("chat-attachments-test")
+            .to_string_lossy()
+            .into(),
+        max_size_bytes: MAX_SIZE_BYTES,
+        max_total_bytes: MAX_TOTAL_BYTES,
+    };
+    TestServer::spawn(ServerState {
+        attachments: AttachmentStore::new(&config),
+        ..Default::default()
+    })
+    .await
+}
+
+fn file_form(contents: &'static [u8]) -> multipart::Form {
+    typed_file_form(contents, "notes.txt", "text/plain")
+}
+
+fn typed_file_form(
+    contents: &'static [u8],
+    filename: &'static str,
+    content_type: &str,
+) -> multipart::Form {
+    let part = multipart::Part::bytes(contents)
+        .file_name(filename)
+        .mime_str(content_type)
+        .unwrap();
+    multipart::Form::new().part("file", part)
+}
+
+async fn upload(server: &TestServer, form: multipart::Form) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(server.url("/attachments"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn download(server: &TestServer, attachment: &Attachment) -> reqwest::Response {
+    reqwest::get(server.url(&format!("/attachments/{}", attachment.id)))
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn uploaded_attachment_can_be_downloaded() {
+    let server = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(server.url("/attachments"))
+        .multipart(file_form(b"hello"))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let attachment: Attachment = response.json().await.expect("wrong response format");
+    assert_eq!(attachment.filename, "notes.txt");
+    assert_eq!(attachment.content_type, "text/plain");
+    assert_eq!(attachment.size, 5);
+
+    let response = client
+        .get(server.url(&format!("/attachments/{}", attachment.id)))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["content-type"], "text/plain");
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"hello");
+}
+
+#[tokio::test]
+async fn only_images_are_displayed_inline() {
+    let server = spawn_server().await;
+
+    let response = upload(&server, typed_file_form(b"\x89PNG", "cat.png", "image/png")).await;
+    let image: Attachment = response.json().await.expect("wrong response format");
+    let response = download(&server, &image).await;
+    let disposition = response.headers()["content-disposition"].to_str().unwrap();
+    assert!(disposition.starts_with("inline"));
+
+    let response = upload(
+        &server,
+        typed_file_form(b"<script>", "page.html", "text/html"),
+    )
+    .await;
+    let page: Attachment = response.json().await.expect("wrong response format");
+    let response = download(&server, &page).await;
+    let disposition = response.headers()["content-disposition"].to_str().unwrap();
+    assert!(disposition.starts_with("attachment"));
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+}
+
+#[tokio::test]
+async fn uploads_over_total_limit_are_rejected() {
+    let server = spawn_server().await;
+
+    let response = upload(&server, file_form(b"sixteen bytes!!!")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = upload(&server, file_form(b"ten bytes!")).await;
+    assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+    let response = upload(&server, file_form(b"eight!!!")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn attachment_over_size_limit_is_rejected() {
+    let server = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(server.url("/attachments"))
+        .multipart(file_form(b"this is longer than the limit"))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn unknown_attachment_is_not_found() {
+    let server = spawn_server().await;
+
+    let response = reqwest::get(server.url("/attachments/unknown"))
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
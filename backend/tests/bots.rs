@@ -0,0 +1,195 @@
+mod common;
+
+use std::time::Duration;
+
+use chat_backend::{
+    bot::{self, Bot, BotContext, BotRegistry, ReminderBot},
+    client::{Client, Event},
+    configuration::BotConfig,
+    Payload, PayloadEventType, ServerState, SharedServerState,
+};
+use common::{join, receive_message, spawn_ws_server, wait_for, TIMEOUT_SECONDS};
+use tokio::sync::Mutex;
+
+/// Greets users joining the chat.
+struct GreeterBot;
+
+impl Bot for GreeterBot {
+    fn on_event(&self, event: &Payload, chat: &BotContext) {
+        if event.event_type == PayloadEventType::Connected {
+            chat.send(format!("welcome {}, I am {}", event.username, chat.name()));
+        }
+    }
+}
+
+fn bot_config(kind: &str, name: &str) -> BotConfig {
+    BotConfig {
+        kind: kind.into(),
+        name: name.into(),
+    }
+}
+
+/// Start WebSocket listener and the given bots, returning the WebSocket port once every bot has
+/// joined the chat.
+async fn spawn_server_with_bots(configs: &[BotConfig]) -> (SharedServerState, u16) {
+    let server_state = SharedServerState::new(Mutex::new(ServerState::default()));
+    let mut registry = BotRegistry::with_builtin_bots();
+    registry.register("greeter", |_| Ok(Box::new(GreeterBot)));
+    let bots = registry.create(configs).unwrap();
+    tokio::spawn(bot::run_bots(server_state.clone(), bots));
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        while server_state.lock().await.clients.len() < configs.len() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("bots did not join");
+
+    let ws_port = spawn_ws_server(server_state.clone()).await;
+    (server_state, ws_port)
+}
+
+/// Send a chat message and return the next message received from the chat.
+async fn ask(client: &mut Client, message: &str) -> Payload {
+    client.send_message(message).unwrap();
+    receive_message(client).await
+}
+
+#[test]
+fn unknown_bot_types_are_rejected() {
+    let registry = BotRegistry::with_builtin_bots();
+
+    assert!(registry.create(&[bot_config("echo", "echo-bot")]).is_ok());
+    assert!(registry
+        .create(&[bot_config("oracle", "oracle-bot")])
+        .is_err());
+    assert!(BotRegistry::default()
+        .create(&[bot_config("echo", "echo-bot")])
+        .is_err());
+}
+
+#[tokio::test]
+async fn bots_are_chat_members() {
+    let (server_state, ws_port) = spawn_server_with_bots(&[
+        bot_config("echo", "echo-bot"),
+        bot_config("reminder", "reminder-bot"),
+    ])
+    .await;
+
+    let mut usernames: Vec<String> = server_state
+        .lock()
+        .await
+        .clients
+        .values()
+        .map(|client| client.username.clone())
+        .collect();
+    usernames.sort();
+    assert_eq!(usernames, vec!["echo-bot", "reminder-bot"]);
+
+    // Usernames of bots are taken
+    let mut impostor = join(ws_port, "echo-bot").await;
+    wait_for(&mut impostor, |event| *event == Event::ConnectionLost).await;
+}
+
+#[tokio::test]
+async fn echo_bot_replies_to_echo_command() {
+    let (server_state, ws_port) = spawn_server_with_bots(&[bot_config("echo", "echo-bot")]).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    let reply = ask(&mut ws_client, "/echo  hello bot ").await;
+    assert_eq!(reply.username, "echo-bot");
+    assert_eq!(reply.message.as_deref(), Some("hello bot"));
+    assert!(reply.bot);
+    let question_id = reply.reply_to.expect("not a reply");
+    let question = server_state
+        .lock()
+        .await
+        .history_entry(question_id)
+        .cloned()
+        .unwrap();
+    assert_eq!(question.message.as_deref(), Some("/echo  hello bot "));
+    assert!(!question.bot);
+}
+
+#[tokio::test]
+async fn reminder_bot_reminds_after_delay() {
+    let (_, ws_port) = spawn_server_with_bots(&[bot_config("reminder", "reminder-bot")]).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    let ack = ask(&mut ws_client, "/remind 1s standup").await;
+    assert_eq!(ack.username, "reminder-bot");
+    assert_eq!(
+        ack.message.as_deref(),
+        Some("@user1 I will remind you in 1s")
+    );
+    assert_eq!(ack.mentions, vec!["user1"]);
+
+    let reminder = receive_message(&mut ws_client).await;
+    assert_eq!(
+        reminder.message.as_deref(),
+        Some("@user1 reminder: standup")
+    );
+    assert_eq!(reminder.reply_to, ack.reply_to);
+}
+
+#[tokio::test]
+async fn reminder_bot_explains_usage() {
+    let (_, ws_port) = spawn_server_with_bots(&[bot_config("reminder", "reminder-bot")]).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    let reply = ask(&mut ws_client, "/remind 120s lunch").await;
+    assert_eq!(
+        reply.message.as_deref(),
+        Some("@user1 I will remind you in 2m")
+    );
+    for invalid in [
+        "/remind",
+        "/remind 10m",
+        "/remind soon lunch",
+        "/remind 0s lunch",
+    ] {
+        let reply = ask(&mut ws_client, invalid).await;
+        assert!(reply.message.unwrap().starts_with("@user1 usage:"));
+    }
+}
+
+#[tokio::test]
+async fn reminder_bot_limits_pending_reminders_per_user() {
+    let (_, ws_port) = spawn_server_with_bots(&[bot_config("reminder", "reminder-bot")]).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    for _ in 0..ReminderBot::MAX_PENDING_PER_USER {
+        let reply = ask(&mut ws_client, "/remind 1d lunch").await;
+        assert_eq!(
+            reply.message.as_deref(),
+            Some("@user1 I will remind you in 1d")
+        );
+    }
+    let reply = ask(&mut ws_client, "/remind 1d lunch").await;
+    assert_eq!(
+        reply.message.as_deref(),
+        Some("@user1 you already have 5 pending reminders")
+    );
+
+    // Limit applies to each user on their own
+    let mut other_client = join(ws_port, "user2").await;
+    let reply = ask(&mut other_client, "/remind 1d lunch").await;
+    assert_eq!(
+        reply.message.as_deref(),
+        Some("@user2 I will remind you in 1d")
+    );
+}
+
+#[tokio::test]
+async fn custom_bots_receive_broadcasted_events() {
+    let (_, ws_port) = spawn_server_with_bots(&[bot_config("greeter", "greeter-bot")]).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    let greeting = receive_message(&mut ws_client).await;
+    assert_eq!(greeting.username, "greeter-bot");
+    assert_eq!(
+        greeting.message.as_deref(),
+        Some("welcome user1, I am greeter-bot")
+    );
+    assert!(greeting.bot);
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use std::time::Duration;
+
+use chat_backend::{
+    client::{Client, ClientConfig, Event},
+    protocol::Protocol,
+    Payload, PayloadEventType, ServerState,
+};
+use common::{TestServer, HOST, TIMEOUT_SECONDS};
+use futures_util::StreamExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    tungstenite::handshake::server::{Request, Response},
+    WebSocketStream,
+};
+
+/// Accept a WebSocket connection with the latest protocol, like the chat server does.
+async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+    let (stream, _) = listener.accept().await.unwrap();
+    // Error type is dictated by tungstenite
+    #[allow(clippy::result_large_err)]
+    let negotiate = |_: &Request, mut response: Response| {
+        response.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            Protocol::LATEST.subprotocol().parse().unwrap(),
+        );
+        Ok(response)
+    };
+    tokio_tungstenite::accept_hdr_async(stream, negotiate)
+        .await
+        .unwrap()
+}
+
+async fn receive(socket: &mut WebSocketStream<TcpStream>) -> Payload {
+    let msg = socket.next().await.unwrap().unwrap();
+    Protocol::LATEST.decode(&msg).unwrap()
+}
+
+#[tokio::test]
+async fn client_reconnects_and_sends_pending_messages() {
+    let listener = TcpListener::bind(format!("{HOST}:0")).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = ClientConfig {
+        reconnect_delay: Some(Duration::from_millis(50)),
+        ..ClientConfig::new(format!("ws://{HOST}:{port}"), "user1")
+    };
+
+    let (client, mut first) = tokio::join!(Client::connect(config), accept(&listener));
+    let mut client = client.unwrap();
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        assert_eq!(
+            receive(&mut first).await.event_type,
+            PayloadEventType::Connected
+        );
+        first.close(None).await.unwrap();
+        assert_eq!(client.next_event().await, Some(Event::ConnectionLost));
+
+        client.send_message("while away").unwrap();
+        let mut second = accept(&listener).await;
+        assert_eq!(client.next_event().await, Some(Event::Reconnected));
+
+        // Client joins again before anything else
+        let join = receive(&mut second).await;
+        assert_eq!(join.event_type, PayloadEventType::Connected);
+        assert_eq!(join.username, "user1");
+        let message = receive(&mut second).await;
+        assert_eq!(message.username, "user1");
+        assert_eq!(message.message.as_deref(), Some("while away"));
+    })
+    .await
+    .expect("timed out");
+}
+
+#[tokio::test]
+async fn client_without_reconnect_delay_stops_after_connection_loss() {
+    let listener = TcpListener::bind(format!("{HOST}:0")).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = ClientConfig {
+        reconnect_delay: None,
+        ..ClientConfig::new(format!("ws://{HOST}:{port}"), "user1")
+    };
+
+    let (client, mut socket) = tokio::join!(Client::connect(config), accept(&listener));
+    let mut client = client.unwrap();
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        receive(&mut socket).await;
+        socket.close(None).await.unwrap();
+        assert_eq!(client.next_event().await, Some(Event::ConnectionLost));
+        assert_eq!(client.next_event().await, None);
+        assert!(client.send_message("hello").is_err());
+    })
+    .await
+    .expect("timed out");
+}
+
+#[tokio::test]
+async fn client_fetches_history_and_users_from_rest_api() {
+    let server = TestServer::spawn(ServerState {
+        history_replay_count: 50,
+        ..Default::default()
+    })
+    .await;
+    let config = ClientConfig {
+        rest_url: Some(server.rest_url.clone()),
+        ..ClientConfig::new(&server.ws_url, "user1")
+    };
+    let client = Client::connect(config).await.unwrap();
+
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        client.send_message("hello").unwrap();
+        while server.server_state.lock().await.history.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out");
+
+    let history = client.history().await.unwrap();
+    let events: Vec<_> = history
+        .iter()
+        .map(|payload| (payload.event_type.clone(), payload.message.as_deref()))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (PayloadEventType::Connected, None),
+            (PayloadEventType::Message, Some("hello")),
+        ]
+    );
+    assert_eq!(client.users().await.unwrap(), vec!["user1"]);
+    // Authenticated with the session token of the connection
+    assert_eq!(client.unread().await.unwrap().username, "user1");
+}
+
+#[tokio::test]
+async fn history_requires_rest_api_url() {
+    let server = TestServer::spawn(ServerState::default()).await;
+    let client = Client::connect(ClientConfig::new(&server.ws_url, "user1"))
+        .await
+        .unwrap();
+
+    assert!(client.history().await.is_err());
+}
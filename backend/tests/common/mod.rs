@@ -0,0 +1,153 @@
+//! Helpers shared by integration tests. Tests join the chat with the `client` module of the
+//! crate, like other Rust tools do.
+
+// Every test binary uses only some of the helpers
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use chat_backend::{
+    client::{Client, ClientConfig, Event},
+    rest_server, ws_server, Payload, PayloadEventType, ServerState, SharedServerState,
+};
+use reqwest::Method;
+use tokio::{net::TcpListener, sync::Mutex};
+
+pub const HOST: &str = "127.0.0.1";
+pub const TIMEOUT_SECONDS: Duration = Duration::from_secs(5);
+pub const ADMIN_TOKEN: &str = "admin-secret";
+
+/// Chat server with both listeners running on a state of its own.
+pub struct TestServer {
+    pub server_state: SharedServerState,
+    pub rest_url: String,
+    pub ws_url: String,
+}
+
+impl TestServer {
+    pub async fn spawn(server_state: ServerState) -> Self {
+        let server_state = SharedServerState::new(Mutex::new(server_state));
+        let (rest_port, ws_port) = spawn_servers(server_state.clone()).await;
+        Self {
+            server_state,
+            rest_url: format!("http://{HOST}:{rest_port}"),
+            ws_url: ws_url(ws_port),
+        }
+    }
+
+    /// Join the chat without reconnecting, returning once the client is a member. Joining is
+    /// confirmed by the replayed history, so `history_replay_count` must be set.
+    pub async fn join(&self, username: &str) -> Client {
+        let config = ClientConfig {
+            rest_url: Some(self.rest_url.clone()),
+            reconnect_delay: None,
+            ..ClientConfig::new(&self.ws_url, username)
+        };
+        let mut client = Client::connect(config)
+            .await
+            .unwrap_or_else(|e| panic!("{username} failed to connect: {e}"));
+        receive_history(&mut client).await;
+        client
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.rest_url)
+    }
+
+    /// Request to the REST API authenticated with the admin token.
+    pub fn admin(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, self.url(path))
+            .bearer_auth(ADMIN_TOKEN)
+    }
+}
+
+/// Start WebSocket listener on the given state, returning its port.
+pub async fn spawn_ws_server(server_state: SharedServerState) -> u16 {
+    let listener = TcpListener::bind(format!("{HOST}:0"))
+        .await
+        .expect("unable to bind WebSocket port");
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(ws_server::run_ws_server(listener, server_state));
+    port
+}
+
+/// Start both listeners on a shared state, returning the REST API and WebSocket ports.
+pub async fn spawn_servers(server_state: SharedServerState) -> (u16, u16) {
+    let rest_listener =
+        std::net::TcpListener::bind(format!("{HOST}:0")).expect("unable to bind REST API port");
+    let rest_port = rest_listener.local_addr().unwrap().port();
+    tokio::spawn(rest_server::run_rest_server(
+        rest_listener,
+        server_state.clone(),
+    ));
+    let ws_port = spawn_ws_server(server_state).await;
+    (rest_port, ws_port)
+}
+
+pub fn ws_url(ws_port: u16) -> String {
+    format!("ws://{HOST}:{ws_port}")
+}
+
+pub async fn join(ws_port: u16, username: &str) -> Client {
+    Client::connect(ClientConfig::new(ws_url(ws_port), username))
+        .await
+        .unwrap_or_else(|e| panic!("{username} failed to connect: {e}"))
+}
+
+/// Receive the next event, skipping the protocol greeting, which is covered by protocol tests.
+pub async fn next_event(client: &mut Client) -> Event {
+    loop {
+        let event = tokio::time::timeout(TIMEOUT_SECONDS, client.next_event())
+            .await
+            .expect("timed out")
+            .expect("connection closed");
+        if !matches!(event, Event::Hello { .. }) {
+            return event;
+        }
+    }
+}
+
+/// Receive the next event matching `predicate`, skipping others.
+pub async fn wait_for(client: &mut Client, predicate: impl Fn(&Event) -> bool) -> Event {
+    loop {
+        let event = next_event(client).await;
+        if predicate(&event) {
+            return event;
+        }
+    }
+}
+
+/// Receive the next chat message, skipping other events.
+pub async fn receive_message(client: &mut Client) -> Payload {
+    match wait_for(client, |event| matches!(event, Event::Message(_))).await {
+        Event::Message(payload) => payload,
+        _ => unreachable!(),
+    }
+}
+
+/// Receive the history replayed after joining, which confirms that the client has joined.
+pub async fn receive_history(client: &mut Client) -> Vec<Payload> {
+    match wait_for(client, |event| matches!(event, Event::History { .. })).await {
+        Event::History { events, .. } => events,
+        _ => unreachable!(),
+    }
+}
+
+/// Wait until `username` joins the chat, skipping other events.
+pub async fn receive_joined(client: &mut Client, username: &str) {
+    wait_for(
+        client,
+        |event| matches!(event, Event::Joined { username: joined } if joined == username),
+    )
+    .await;
+}
+
+pub fn message(username: &str, text: &str) -> Payload {
+    Payload {
+        event_type: PayloadEventType::Message,
+        username: username.into(),
+        message: Some(text.into()),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,237 @@
+mod common;
+
+use chat_backend::{
+    compression::PerMessageDeflate, configuration::CompressionConfig, protocol::Protocol, Payload,
+    PayloadEventType, ServerState, SharedServerState,
+};
+use common::{spawn_ws_server, HOST, TIMEOUT_SECONDS};
+use futures_util::{SinkExt, StreamExt};
+use soketto::{
+    base::{Header, OpCode},
+    connection::Mode,
+    extension::{deflate::Deflate, Extension},
+    handshake::{Client, ServerResponse},
+    Storage,
+};
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+fn enabled_config() -> CompressionConfig {
+    CompressionConfig {
+        enabled: true,
+        ..Default::default()
+    }
+}
+
+async fn spawn_server(compression: CompressionConfig) -> u16 {
+    spawn_ws_server(SharedServerState::new(Mutex::new(ServerState {
+        compression,
+        ..Default::default()
+    })))
+    .await
+}
+
+/// Connect offering the given `Sec-WebSocket-Extensions` header, returning the accepted
+/// extensions.
+async fn accepted_extensions(port: u16, offered_extensions: &str) -> Option<String> {
+    let mut request = format!("ws://{HOST}:{port}").into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Extensions",
+        offered_extensions.parse().unwrap(),
+    );
+    let (_client, response) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("failed to connect");
+    response
+        .headers()
+        .get("Sec-WebSocket-Extensions")
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+/// Extension negotiated with an offer that is expected to be acceptable.
+fn negotiated(offered: &str, max_message_bytes: usize) -> PerMessageDeflate {
+    PerMessageDeflate::negotiate(offered, &enabled_config(), max_message_bytes)
+        .expect("offer is not accepted")
+}
+
+/// Encode a text message, returning whether it was compressed and the frame payload.
+fn encode(deflate: &mut PerMessageDeflate, message: &[u8]) -> (bool, Vec<u8>) {
+    let mut header = Header::new(OpCode::Text);
+    let mut data = Storage::Shared(message);
+    deflate.encode(&mut header, &mut data).unwrap();
+    (header.is_rsv1(), data.as_ref().to_vec())
+}
+
+/// Decode the payload of a compressed text frame.
+fn decode(deflate: &mut PerMessageDeflate, payload: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut header = Header::new(OpCode::Text);
+    header.set_rsv1(true);
+    header.set_fin(true);
+    let mut data = payload;
+    deflate
+        .decode(&mut header, &mut data)
+        .map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+#[test]
+fn acceptable_offers_are_negotiated() {
+    let negotiate = |offered| {
+        PerMessageDeflate::negotiate(offered, &enabled_config(), usize::MAX)
+            .map(|deflate| deflate.response_header())
+    };
+
+    // Messages of clients are decompressed one by one, so clients must not keep their context
+    assert_eq!(
+        negotiate("permessage-deflate; client_max_window_bits"),
+        Some("permessage-deflate; client_no_context_takeover".into())
+    );
+    assert_eq!(
+        negotiate("permessage-deflate; server_no_context_takeover; client_no_context_takeover"),
+        Some("permessage-deflate; server_no_context_takeover; client_no_context_takeover".into())
+    );
+    assert_eq!(
+        negotiate("permessage-deflate; server_max_window_bits=\"10\""),
+        Some("permessage-deflate; server_max_window_bits=10; client_no_context_takeover".into())
+    );
+    // zlib does not support windows of 8 bits, so the next offer is accepted
+    assert_eq!(
+        negotiate("permessage-deflate; server_max_window_bits=8, permessage-deflate"),
+        Some("permessage-deflate; client_no_context_takeover".into())
+    );
+    assert_eq!(negotiate("permessage-deflate; unknown_param"), None);
+    assert_eq!(negotiate("x-webkit-deflate-frame"), None);
+
+    let disabled = CompressionConfig::default();
+    assert!(PerMessageDeflate::negotiate("permessage-deflate", &disabled, usize::MAX).is_none());
+}
+
+#[test]
+fn messages_below_threshold_are_not_compressed() {
+    let mut deflate = negotiated("permessage-deflate", usize::MAX);
+
+    assert!(!encode(&mut deflate, &[b'a'; 255]).0);
+    assert!(encode(&mut deflate, &[b'a'; 256]).0);
+}
+
+#[test]
+fn compressed_messages_round_trip() {
+    let history_batch = serde_json::to_vec(&Payload {
+        event_type: PayloadEventType::HistoryBatch,
+        history: (1..=50)
+            .map(|id| Payload {
+                username: format!("user{}", id % 3),
+                message: Some(format!("message number {id}")),
+                id: Some(id),
+                timestamp: Some(1_700_000_000_000 + id),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut server = negotiated("permessage-deflate", usize::MAX);
+    let mut client = negotiated("permessage-deflate", usize::MAX);
+
+    for _ in 0..2 {
+        let (compressed, payload) = encode(&mut server, &history_batch);
+        assert!(compressed);
+        assert!(payload.len() < history_batch.len() / 4);
+        assert_eq!(decode(&mut client, payload).unwrap(), history_batch);
+    }
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    let mut server = negotiated("permessage-deflate", usize::MAX);
+    let mut client = negotiated("permessage-deflate", 1024);
+
+    let (_, payload) = encode(&mut server, &[b'a'; 4096]);
+    assert!(decode(&mut client, payload).is_err());
+}
+
+#[tokio::test]
+async fn server_answers_compression_offers() {
+    let port = spawn_server(enabled_config()).await;
+    assert_eq!(
+        accepted_extensions(port, "permessage-deflate; client_max_window_bits").await,
+        Some("permessage-deflate; client_no_context_takeover".into())
+    );
+    assert_eq!(accepted_extensions(port, "x-unknown").await, None);
+
+    let port = spawn_server(CompressionConfig::default()).await;
+    assert_eq!(accepted_extensions(port, "permessage-deflate").await, None);
+}
+
+#[tokio::test]
+async fn compressed_messages_are_exchanged() {
+    let port = spawn_server(enabled_config()).await;
+    let (mut uncompressed, _) = tokio_tungstenite::connect_async(format!("ws://{HOST}:{port}"))
+        .await
+        .expect("failed to connect");
+    let join = |username: &str| {
+        serde_json::to_string(&Payload {
+            event_type: PayloadEventType::Connected,
+            username: username.into(),
+            ..Default::default()
+        })
+        .unwrap()
+    };
+    uncompressed
+        .send(Message::text(join("user2")))
+        .await
+        .unwrap();
+
+    // soketto client compresses every message it sends and requires no context takeover
+    let tcp_stream = TcpStream::connect(format!("{HOST}:{port}")).await.unwrap();
+    let host = format!("{HOST}:{port}");
+    let mut handshake = Client::new(tcp_stream.compat(), &host, "/");
+    handshake.add_protocol(Protocol::LATEST.subprotocol());
+    handshake.add_extension(Box::new(Deflate::new(Mode::Client)));
+    match handshake.handshake().await.expect("handshake failed") {
+        ServerResponse::Accepted { .. } => (),
+        response => panic!("unexpected handshake response: {response:?}"),
+    }
+    let (mut sender, mut receiver) = handshake.into_builder().finish();
+    sender.send_text(join("user1")).await.unwrap();
+    sender.flush().await.unwrap();
+
+    // Decompressed join event of the compressing client is broadcasted
+    let joined = tokio::time::timeout(TIMEOUT_SECONDS, uncompressed.next())
+        .await
+        .expect("timed out")
+        .expect("connection closed")
+        .expect("error during receive");
+    let joined: Payload = serde_json::from_str(joined.to_text().unwrap()).unwrap();
+    assert_eq!(joined.username, "user1");
+
+    let long_message = "compressible ".repeat(100);
+    let message = Payload {
+        username: "user2".into(),
+        message: Some(long_message.clone()),
+        ..Default::default()
+    };
+    uncompressed
+        .send(Message::text(serde_json::to_string(&message).unwrap()))
+        .await
+        .unwrap();
+
+    let received = tokio::time::timeout(TIMEOUT_SECONDS, async {
+        loop {
+            let mut data = Vec::new();
+            receiver
+                .receive_data(&mut data)
+                .await
+                .expect("connection closed");
+            let payload: Payload = serde_json::from_slice(&data).unwrap();
+            if payload.event_type == PayloadEventType::Message {
+                return payload;
+            }
+        }
+    })
+    .await
+    .expect("timed out");
+    assert_eq!(received.username, "user2");
+    assert_eq!(received.message, Some(long_message));
+}
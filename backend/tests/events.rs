@@ -0,0 +1,196 @@
+mod common;
+
+use std::time::Duration;
+
+use chat_backend::{
+    client::Event, protocol::Protocol, Payload, PayloadEventType, ServerState, SharedServerState,
+};
+use common::{
+    join, receive_history, receive_joined, receive_message, spawn_servers, wait_for, HOST,
+    TIMEOUT_SECONDS,
+};
+use reqwest::StatusCode;
+use tokio::sync::Mutex;
+
+fn replaying_server_state() -> SharedServerState {
+    SharedServerState::new(Mutex::new(ServerState {
+        history_replay_count: 50,
+        ..Default::default()
+    }))
+}
+
+/// Client of `GET /events`, parsing Server-Sent Events from the response body.
+struct EventStream {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl EventStream {
+    async fn connect(rest_port: u16, username: &str) -> Result<Self, StatusCode> {
+        let response = reqwest::get(format!(
+            "http://{HOST}:{rest_port}/events?username={username}"
+        ))
+        .await
+        .expect("failed to execute request");
+        if !response.status().is_success() {
+            return Err(response.status());
+        }
+        assert_eq!(
+            response.headers()["content-type"].to_str().unwrap(),
+            "text/event-stream"
+        );
+        Ok(Self {
+            response,
+            buffer: String::new(),
+        })
+    }
+
+    async fn next_event(&mut self) -> Payload {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let event: String = self.buffer.drain(..end + 2).collect();
+                // Skip keep-alive comments
+                let Some(data) = event.strip_prefix("data: ") else {
+                    continue;
+                };
+                return serde_json::from_str(data.trim_end()).unwrap();
+            }
+            let chunk = tokio::time::timeout(TIMEOUT_SECONDS, self.response.chunk())
+                .await
+                .expect("timed out")
+                .expect("error during receive")
+                .expect("stream ended");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    /// Receive the `hello` event, returning the session token.
+    async fn session_token(&mut self) -> String {
+        let hello = self.next_event().await;
+        assert_eq!(hello.event_type, PayloadEventType::Hello);
+        assert_eq!(
+            hello.protocol.as_deref(),
+            Some(Protocol::LATEST.subprotocol())
+        );
+        hello.session_token.expect("missing session token")
+    }
+}
+
+async fn post_message(rest_port: u16, token: &str, message: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{HOST}:{rest_port}/messages"))
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "message": message }))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn event_stream_receives_websocket_broadcasts() {
+    let (rest_port, ws_port) = spawn_servers(replaying_server_state()).await;
+    let mut ws_client = join(ws_port, "user1").await;
+    receive_history(&mut ws_client).await;
+
+    let mut events = EventStream::connect(rest_port, "user2").await.unwrap();
+    events.session_token().await;
+    let history = events.next_event().await;
+    assert_eq!(history.event_type, PayloadEventType::HistoryBatch);
+    assert_eq!(history.history.len(), 1);
+
+    // HTTP members join the chat like WebSocket clients
+    receive_joined(&mut ws_client, "user2").await;
+
+    ws_client.send_message("hello over WebSocket").unwrap();
+    let received = events.next_event().await;
+    assert_eq!(received.event_type, PayloadEventType::Message);
+    assert_eq!(received.message.as_deref(), Some("hello over WebSocket"));
+    assert!(received.id.is_some());
+}
+
+#[tokio::test]
+async fn posted_messages_are_broadcasted_and_saved() {
+    let server_state = replaying_server_state();
+    let (rest_port, ws_port) = spawn_servers(server_state.clone()).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    let mut events = EventStream::connect(rest_port, "user2").await.unwrap();
+    let token = events.session_token().await;
+    receive_joined(&mut ws_client, "user2").await;
+
+    let response = post_message(rest_port, &token, "hello over HTTP").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let saved: Payload = response.json().await.unwrap();
+    assert_eq!(saved.username, "user2");
+    assert_eq!(saved.message.as_deref(), Some("hello over HTTP"));
+
+    let received = receive_message(&mut ws_client).await;
+    assert_eq!(received, saved);
+    assert_eq!(
+        server_state.lock().await.history_entry(saved.id.unwrap()),
+        Some(&saved)
+    );
+}
+
+#[tokio::test]
+async fn messages_require_session_token() {
+    let server_state = replaying_server_state();
+    let (rest_port, _) = spawn_servers(server_state.clone()).await;
+
+    let response = post_message(rest_port, "unknown", "hello").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(server_state.lock().await.history.is_empty());
+}
+
+#[tokio::test]
+async fn username_must_be_available() {
+    let (rest_port, ws_port) = spawn_servers(replaying_server_state()).await;
+    let mut ws_client = join(ws_port, "user1").await;
+    receive_history(&mut ws_client).await;
+
+    assert_eq!(
+        EventStream::connect(rest_port, "user1").await.err(),
+        Some(StatusCode::CONFLICT)
+    );
+    assert_eq!(
+        EventStream::connect(rest_port, "").await.err(),
+        Some(StatusCode::BAD_REQUEST)
+    );
+}
+
+#[tokio::test]
+async fn closing_event_stream_leaves_chat() {
+    let server_state = replaying_server_state();
+    let (rest_port, ws_port) = spawn_servers(server_state.clone()).await;
+    let mut ws_client = join(ws_port, "user1").await;
+
+    let mut events = EventStream::connect(rest_port, "user2").await.unwrap();
+    let token = events.session_token().await;
+    receive_joined(&mut ws_client, "user2").await;
+
+    drop(events);
+    // Closed streams are noticed once writing to them fails, so keep the chat busy
+    let left = tokio::time::timeout(TIMEOUT_SECONDS, async {
+        loop {
+            ws_client.send_message("anyone there?").unwrap();
+            let event = tokio::time::timeout(
+                Duration::from_millis(100),
+                wait_for(&mut ws_client, |event| matches!(event, Event::Left { .. })),
+            );
+            if let Ok(left) = event.await {
+                return left;
+            }
+        }
+    })
+    .await
+    .expect("timed out");
+    assert_eq!(
+        left,
+        Event::Left {
+            username: "user2".into()
+        }
+    );
+
+    let response = post_message(rest_port, &token, "too late").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -1,20 +1,15 @@
-use chat_backend::{rest_server, SharedServerState};
+mod common;
+
+use chat_backend::ServerState;
+use common::TestServer;
 
 #[tokio::test]
 async fn health_check_works() {
-    let server_state = SharedServerState::default();
-    let address = "127.0.0.1";
-    let rest_listener =
-        std::net::TcpListener::bind(format!("{address}:0")).expect("unable to bind REST API port");
-    let port = rest_listener.local_addr().unwrap().port();
-    let _ = tokio::spawn(rest_server::run_rest_server(
-        rest_listener,
-        server_state.clone(),
-    ));
+    let server = TestServer::spawn(ServerState::default()).await;
     let client = reqwest::Client::new();
 
     let response = client
-        .get(format!("http://{}:{}/health", address, port))
+        .get(server.url("/health"))
         .send()
         .await
         .expect("failed to execute request");
@@ -0,0 +1,179 @@
+mod common;
+
+use chat_backend::{configuration::ApiKey, Payload, PayloadEventType, ServerState, UnreadCount};
+use common::TestServer;
+use reqwest::StatusCode;
+
+fn message(id: u64, username: &str, message: &str, reply_to: Option<u64>) -> Payload {
+    Payload {
+        event_type: PayloadEventType::Message,
+        username: username.into(),
+        message: Some(message.into()),
+        id: Some(id),
+        reply_to,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn thread_contains_parent_and_replies_in_order() {
+    let history = vec![
+        Payload {
+            reply_count: 2,
+            ..message(1, "user1", "question", None)
+        },
+        message(2, "user2", "unrelated", None),
+        message(3, "user2", "answer 1", Some(1)),
+        message(4, "user3", "reply to unrelated", Some(2)),
+        message(5, "user3", "answer 2", Some(1)),
+    ];
+    let server = TestServer::spawn(ServerState {
+        history,
+        ..Default::default()
+    })
+    .await;
+
+    let response = reqwest::get(server.url("/history/1/thread"))
+        .await
+        .expect("failed to execute request");
+
+    assert!(response.status().is_success());
+    let thread: Vec<Payload> = response.json().await.expect("wrong response format");
+    let ids: Vec<_> = thread.iter().map(|payload| payload.id.unwrap()).collect();
+    assert_eq!(ids, vec![1, 3, 5]);
+    assert_eq!(thread[0].reply_count, 2);
+}
+
+#[tokio::test]
+async fn thread_of_unknown_message_is_not_found() {
+    let server = TestServer::spawn(ServerState {
+        history: vec![message(1, "user1", "hello", None)],
+        ..Default::default()
+    })
+    .await;
+
+    let response = reqwest::get(server.url("/history/42/thread"))
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unread_count_includes_messages_of_others_since_last_read() {
+    let server = TestServer::spawn(ServerState {
+        history: vec![
+            message(1, "user1", "hello", None),
+            message(2, "user2", "hi", None),
+            message(3, "user1", "how are you?", None),
+            message(4, "user2", "fine", None),
+            message(5, "user1", "great", None),
+        ],
+        read_positions: [("user2".into(), 3)].into(),
+        history_replay_count: 50,
+        ..Default::default()
+    })
+    .await;
+    let user1 = server.join("user1").await;
+    let user2 = server.join("user2").await;
+
+    let expected = UnreadCount {
+        username: "user2".into(),
+        last_read_id: Some(3),
+        unread: 1,
+    };
+    assert_eq!(user2.unread().await.unwrap(), expected);
+    let expected = UnreadCount {
+        username: "user1".into(),
+        last_read_id: None,
+        unread: 2,
+    };
+    assert_eq!(user1.unread().await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn unread_count_is_only_available_to_the_user() {
+    let server = TestServer::spawn(ServerState {
+        api_keys: vec![ApiKey {
+            name: "user2".into(),
+            key: "user2-key".into(),
+        }],
+        ..Default::default()
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(server.url("/users/user1/unread"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(server.url("/users/user1/unread"))
+        .bearer_auth("user2-key")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .get(server.url("/users/user2/unread"))
+        .bearer_auth("user2-key")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn mentions_of_the_user_are_queryable() {
+    let history = vec![
+        Payload {
+            mentions: vec!["user2".into()],
+            ..message(1, "user1", "hi @user2", None)
+        },
+        Payload {
+            mentions: vec!["user3".into()],
+            ..message(2, "user1", "hi @user3", None)
+        },
+        Payload {
+            mentions: vec!["user3".into(), "user2".into()],
+            ..message(3, "user1", "@user3 @user2 lunch?", None)
+        },
+    ];
+    let server = TestServer::spawn(ServerState {
+        history,
+        api_keys: vec![ApiKey {
+            name: "user2".into(),
+            key: "user2-key".into(),
+        }],
+        ..Default::default()
+    })
+    .await;
+
+    let mentions = |query: &str, token: &str| {
+        reqwest::Client::new()
+            .get(server.url(&format!("/mentions{query}")))
+            .bearer_auth(token)
+            .send()
+    };
+    let mention_ids = |response: reqwest::Response| async move {
+        response
+            .json::<Vec<Payload>>()
+            .await
+            .expect("wrong response format")
+            .iter()
+            .map(|payload| payload.id.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let response = mentions("", "user2-key").await.unwrap();
+    assert_eq!(mention_ids(response).await, vec![1, 3]);
+    let response = mentions("?after=1", "user2-key").await.unwrap();
+    assert_eq!(mention_ids(response).await, vec![3]);
+    // Mentions of other users are not available
+    let response = mentions("?username=user3", "user2-key").await.unwrap();
+    assert_eq!(mention_ids(response).await, vec![1, 3]);
+    let response = mentions("", "wrong").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,155 @@
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chat_backend::{
+    configuration::LinkPreviewConfig,
+    unfurl::{
+        extract_urls, is_public, parse_metadata, HttpLinkFetcher, LinkFetcher, LinkPreview,
+        LinkUnfurler,
+    },
+};
+use futures_util::future::BoxFuture;
+
+/// Fetcher counting its calls, returning a preview titled after the URL.
+struct CountingFetcher {
+    calls: Arc<AtomicUsize>,
+}
+
+impl LinkFetcher for CountingFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<LinkPreview, String>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move {
+            if url.contains("broken") {
+                return Err("unavailable".into());
+            }
+            Ok(LinkPreview {
+                url: url.into(),
+                title: Some(format!("title of {url}")),
+                description: None,
+            })
+        })
+    }
+}
+
+#[test]
+fn metadata_prefers_opengraph_tags() {
+    let html = r#"<html><head>
+        <title>Plain title</title>
+        <meta name="description" content="Plain description">
+        <meta property="og:title" content="Rust &amp; WebSockets" />
+        <meta data-name="og:description" content="not this">
+    </head></html>"#;
+
+    let preview = parse_metadata("https://example.com", html);
+
+    assert_eq!(preview.title, Some("Rust & WebSockets".into()));
+    assert_eq!(preview.description, Some("Plain description".into()));
+}
+
+#[test]
+fn metadata_falls_back_to_title_tag() {
+    let preview = parse_metadata("https://example.com", "<TITLE> Example </TITLE>");
+
+    assert_eq!(preview.title, Some("Example".into()));
+    assert_eq!(preview.description, None);
+}
+
+#[test]
+fn urls_are_extracted_without_trailing_punctuation() {
+    let message = "see https://example.com/a, http://example.org. and https://example.com/a";
+
+    assert_eq!(
+        extract_urls(message, 3),
+        vec!["https://example.com/a", "http://example.org"]
+    );
+    assert_eq!(extract_urls(message, 1), vec!["https://example.com/a"]);
+}
+
+#[tokio::test]
+async fn previews_are_cached_including_failures() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let fetcher = CountingFetcher {
+        calls: calls.clone(),
+    };
+    let unfurler = LinkUnfurler::new(Box::new(fetcher), &LinkPreviewConfig::default());
+
+    let preview = unfurler.preview("https://example.com").await;
+    assert_eq!(
+        preview.and_then(|p| p.title),
+        Some("title of https://example.com".into())
+    );
+    assert!(unfurler.preview("https://example.com").await.is_some());
+    assert!(unfurler
+        .preview("https://broken.example.com")
+        .await
+        .is_none());
+    assert!(unfurler
+        .preview("https://broken.example.com")
+        .await
+        .is_none());
+
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn failed_lookups_expire() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let fetcher = CountingFetcher {
+        calls: calls.clone(),
+    };
+    let config = LinkPreviewConfig {
+        failed_lookup_ttl_ms: 0,
+        ..Default::default()
+    };
+    let unfurler = LinkUnfurler::new(Box::new(fetcher), &config);
+
+    for _ in 0..2 {
+        assert!(unfurler.preview("https://example.com").await.is_some());
+        assert!(unfurler
+            .preview("https://broken.example.com")
+            .await
+            .is_none());
+    }
+
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn only_public_addresses_are_public() {
+    for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+        assert!(is_public(ip.parse::<IpAddr>().unwrap()), "{ip}");
+    }
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(!is_public(ip.parse::<IpAddr>().unwrap()), "{ip}");
+    }
+}
+
+#[tokio::test]
+async fn pages_on_private_addresses_are_not_fetched() {
+    let fetcher = HttpLinkFetcher::new(Duration::from_secs(5));
+
+    let error = fetcher.fetch("http://169.254.169.254/").await.unwrap_err();
+    assert!(error.contains("not a public address"), "{error}");
+    let error = fetcher.fetch("http://[::1]:9000/").await.unwrap_err();
+    assert!(error.contains("not a public address"), "{error}");
+    let error = fetcher.fetch("http://localhost:9000/").await.unwrap_err();
+    assert!(error.contains("no public address"), "{error}");
+}
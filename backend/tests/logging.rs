@@ -0,0 +1,180 @@
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chat_backend::ServerState;
+use common::{TestServer, HOST, TIMEOUT_SECONDS};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+/// JSON log output of every test, as servers log from threads of their own. Like the default
+/// filter of the server, events of dependencies are not included.
+static LOGS: Lazy<Logs> = Lazy::new(|| {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter("chat_backend=trace")
+        .with_writer(move || writer.clone())
+        .init();
+    logs
+});
+
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Logs {
+    fn events(&self) -> Vec<Value> {
+        let output = self.0.lock().unwrap();
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    /// Wait for a logged event matching `predicate`.
+    async fn wait_for(&self, predicate: impl Fn(&Value) -> bool) -> Value {
+        tokio::time::timeout(TIMEOUT_SECONDS, async {
+            loop {
+                if let Some(event) = self.events().into_iter().find(|event| predicate(event)) {
+                    return event;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out")
+    }
+}
+
+/// Whether a logged event is recorded within a span named `name` having `field` of `value`.
+fn in_span(event: &Value, name: &str, field: &str, value: &Value) -> bool {
+    event["spans"].as_array().is_some_and(|spans| {
+        spans
+            .iter()
+            .any(|span| span["name"] == name && &span[field] == value)
+    })
+}
+
+async fn spawn_server(log_message_bodies: bool) -> TestServer {
+    TestServer::spawn(ServerState {
+        history_replay_count: 50,
+        log_message_bodies,
+        ..Default::default()
+    })
+    .await
+}
+
+/// Join the chat as `username` and send `message`.
+async fn send_message(server: &TestServer, username: &str, message: &str) {
+    let client = server.join(username).await;
+    client.send_message(message).unwrap();
+}
+
+#[tokio::test]
+async fn connection_events_are_logged_within_its_span_with_redacted_bodies() {
+    let logs = &*LOGS;
+    let server = spawn_server(false).await;
+    send_message(&server, "logging-user1", "secret plans").await;
+
+    let username = Value::from("logging-user1");
+    let received = logs
+        .wait_for(|event| {
+            in_span(event, "connection", "username", &username)
+                && event["fields"]["message"] == "received message"
+        })
+        .await;
+    assert_eq!(received["fields"]["body"], "[redacted]");
+    let connection = &received["spans"][0];
+    assert!(connection["connection_id"].is_u64());
+    assert!(connection["client_address"]
+        .as_str()
+        .unwrap()
+        .starts_with(HOST));
+
+    let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(!output.contains("secret plans"));
+}
+
+#[tokio::test]
+async fn message_bodies_are_logged_if_enabled() {
+    let logs = &*LOGS;
+    let server = spawn_server(true).await;
+    send_message(&server, "logging-user2", "public plans").await;
+
+    let username = Value::from("logging-user2");
+    let received = logs
+        .wait_for(|event| {
+            in_span(event, "connection", "username", &username)
+                && event["fields"]["message"] == "received message"
+        })
+        .await;
+    assert!(received["fields"]["body"]
+        .as_str()
+        .unwrap()
+        .contains("public plans"));
+}
+
+#[tokio::test]
+async fn event_streams_and_websocket_connections_have_distinct_ids() {
+    let logs = &*LOGS;
+    let server = spawn_server(false).await;
+    let _stream = reqwest::get(server.url("/events?username=logging-user4"))
+        .await
+        .unwrap();
+    send_message(&server, "logging-user5", "hello").await;
+
+    let connection_id = |username: &str| {
+        let username = Value::from(username);
+        async move {
+            let event = logs
+                .wait_for(|event| in_span(event, "connection", "username", &username))
+                .await;
+            let spans = event["spans"].as_array().unwrap();
+            let connection = spans.iter().find(|span| span["name"] == "connection");
+            connection.unwrap()["connection_id"].as_u64().unwrap()
+        }
+    };
+    let session_id = connection_id("logging-user4").await;
+    let websocket_id = connection_id("logging-user5").await;
+    assert_ne!(session_id, websocket_id);
+}
+
+#[tokio::test]
+async fn requests_are_logged_within_their_span() {
+    let logs = &*LOGS;
+    let server = spawn_server(false).await;
+
+    let client = server.join("logging-user3").await;
+    client.unread().await.unwrap();
+
+    let path = Value::from("/users/logging-user3/unread");
+    let handled = logs
+        .wait_for(|event| {
+            in_span(event, "request", "path", &path)
+                && event["fields"]["message"] == "request is handled"
+        })
+        .await;
+    assert_eq!(handled["fields"]["status"], 200);
+    assert_eq!(handled["span"]["method"], "GET");
+    logs.wait_for(|event| {
+        in_span(event, "request", "path", &path)
+            && event["fields"]["message"] == "unread count is queried"
+            && event["fields"]["username"] == "logging-user3"
+    })
+    .await;
+}
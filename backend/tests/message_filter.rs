@@ -0,0 +1,93 @@
+use chat_backend::{
+    configuration::{FilterConfig, LinkFilterMode, ProfanityAction},
+    filter::{LinkFilter, MaxLengthFilter, MessageFilter, MessageFilterChain, ProfanityFilter},
+};
+
+#[test]
+fn max_length_filter_truncates_long_messages() {
+    let filter = MaxLengthFilter::new(5);
+
+    assert_eq!(filter.apply("hello world".into()), Ok("hello".into()));
+    assert_eq!(filter.apply("héllö".into()), Ok("héllö".into()));
+    assert_eq!(filter.apply("hi".into()), Ok("hi".into()));
+}
+
+#[test]
+fn profanity_filter_redacts_or_rejects_listed_words() {
+    let word_list = "# comment\ndarn\n\nHeck\n";
+    let redact = ProfanityFilter::from_word_list(word_list, ProfanityAction::Redact);
+    let reject = ProfanityFilter::from_word_list(word_list, ProfanityAction::Reject);
+
+    assert_eq!(
+        redact.apply("Darn, what the heck!".into()),
+        Ok("****, what the ****!".into())
+    );
+    assert_eq!(
+        redact.apply("darning socks".into()),
+        Ok("darning socks".into())
+    );
+    assert!(reject.apply("oh heck".into()).is_err());
+    assert_eq!(reject.apply("oh well".into()), Ok("oh well".into()));
+}
+
+#[test]
+fn link_filter_strips_links_outside_of_allowlist() {
+    let strip = LinkFilter::new(LinkFilterMode::Strip, vec![]);
+    let allowlist = LinkFilter::new(LinkFilterMode::Allowlist, vec!["example.com".into()]);
+
+    assert_eq!(
+        strip.apply("see https://example.com/page now".into()),
+        Ok("see [link removed] now".into())
+    );
+    assert_eq!(
+        allowlist.apply("see https://docs.example.com/page and http://evil.org".into()),
+        Ok("see https://docs.example.com/page and [link removed]".into())
+    );
+    assert_eq!(
+        allowlist.apply("www.notexample.com".into()),
+        Ok("[link removed]".into())
+    );
+    assert_eq!(
+        strip.apply("docs (https://example.com/page), \"HTTPS://EXAMPLE.COM\".".into()),
+        Ok("docs ([link removed]), \"[link removed]\".".into())
+    );
+    assert_eq!(
+        allowlist.apply("(https://example.com) or (https://evil.org)".into()),
+        Ok("(https://example.com) or ([link removed])".into())
+    );
+    assert_eq!(
+        strip.apply("awww.example.com".into()),
+        Ok("awww.example.com".into())
+    );
+    assert_eq!(
+        strip.apply("see\thttps://example.com\nand\u{a0}www.example.com".into()),
+        Ok("see\t[link removed]\nand\u{a0}[link removed]".into())
+    );
+}
+
+#[test]
+fn filter_chain_applies_filters_in_order() {
+    let configs = vec![
+        FilterConfig::Links {
+            mode: LinkFilterMode::Strip,
+            allowed_domains: vec![],
+        },
+        FilterConfig::MaxLength { max_chars: 12 },
+    ];
+    let chain = MessageFilterChain::from_config(&configs, std::path::Path::new(".")).unwrap();
+
+    assert_eq!(
+        chain.apply("https://example.com is down".into()),
+        Ok("[link remove".into())
+    );
+}
+
+#[test]
+fn filter_chain_fails_on_missing_word_list() {
+    let configs = vec![FilterConfig::Profanity {
+        word_list: "does-not-exist.txt".into(),
+        action: ProfanityAction::Redact,
+    }];
+
+    assert!(MessageFilterChain::from_config(&configs, std::path::Path::new(".")).is_err());
+}
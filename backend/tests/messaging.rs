@@ -1,282 +1,492 @@
-use std::time::Duration;
-
-use chat_backend::{ws_server, Payload, PayloadEventType, SharedServerState};
-use futures_util::{stream::SplitSink, SinkExt, StreamExt};
-use once_cell::sync::Lazy;
-use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::mpsc::Receiver,
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use chat_backend::{
+    attachment::Attachment,
+    chat,
+    client::{Client, Event},
+    configuration::LinkPreviewConfig,
+    configuration::ProfanityAction,
+    filter::{MessageFilterChain, ProfanityFilter},
+    protocol::Protocol,
+    unfurl::{LinkFetcher, LinkPreview, LinkUnfurler},
+    ClientId, Payload, PayloadEventType, Reaction, ServerState, SharedServerState,
 };
-use tokio_tungstenite::{
-    tungstenite::{client::IntoClientRequest, Message},
-    MaybeTlsStream, WebSocketStream,
-};
-
-static LOGGER: Lazy<()> = Lazy::new(|| {
-    env_logger::init();
-});
-const HOST: &str = "127.0.0.1";
-const TIMEOUT_SECONDS: Duration = tokio::time::Duration::from_secs(5);
+use common::{join, message, next_event, receive_message, spawn_ws_server, TIMEOUT_SECONDS};
+use futures_util::future::BoxFuture;
+use tokio::sync::Mutex;
+
+async fn check_message(client: &mut Client, expected: &Payload) {
+    let mut actual = receive_message(client).await;
+    // Identifiers depend on the order in which the server processes events of concurrent clients
+    actual.id = None;
+    actual.timestamp = None;
+    assert_eq!(actual, *expected);
+}
 
-struct TestClient {
-    username: String,
-    writer: Option<StreamWriter>,
-    on_message_callback: Option<OnMessageCallback>,
+async fn check_joined(client: &mut Client, username: &str) {
+    let expected = Event::Joined {
+        username: username.into(),
+    };
+    assert_eq!(next_event(client).await, expected);
 }
-type StreamWriter = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
-type OnMessageCallback = Box<dyn Fn(String) + Send + 'static>;
-
-impl TestClient {
-    fn new(username: &str) -> Self {
-        TestClient {
-            username: username.into(),
-            writer: None,
-            on_message_callback: None,
-        }
-    }
 
-    fn set_callback(&mut self, callback: OnMessageCallback) {
-        self.on_message_callback = Some(callback);
-    }
+#[tokio::test]
+async fn single_user_gets_notified_on_new_user_join() {
+    let ws_port = spawn_ws_server(SharedServerState::default()).await;
 
-    async fn connect(&mut self, address: &str, port: u16) {
-        let request = format!("ws://{}:{}", address, port)
-            .into_client_request()
-            .unwrap();
-        let (stream, _) = tokio_tungstenite::connect_async(request.clone())
-            .await
-            .expect(&format!("{} failed to connect", self.username));
-        let (writer, mut reader) = stream.split();
-        self.writer = Some(writer);
-        let callback = self.on_message_callback.take();
-        let receiver = async move {
-            while let Some(msg) = reader.next().await {
-                match msg {
-                    Ok(msg) => {
-                        if let Ok(text) = msg.into_text() {
-                            if let Some(ref callback) = callback {
-                                callback(text.to_string());
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        panic!("error during receive: {e}");
-                    }
-                }
-            }
-        };
-        tokio::spawn(receiver);
+    let mut user1 = join(ws_port, "user1").await;
+    let _user2 = join(ws_port, "user2").await;
 
-        let connect_payload = Payload {
-            event_type: PayloadEventType::Connected,
-            username: self.username.clone(),
-            message: None,
-        };
-        self.send(&connect_payload).await;
-    }
+    tokio::time::timeout(TIMEOUT_SECONDS, check_joined(&mut user1, "user2"))
+        .await
+        .expect("timed out");
+}
 
-    async fn send(&mut self, payload: &Payload) {
-        let j = serde_json::to_string(&payload).unwrap();
-        self.writer
-            .as_mut()
-            .unwrap()
-            .send(j.into())
-            .await
-            .expect(&format!(
-                "unable to send {:?} from {}",
-                payload, self.username,
-            ));
-    }
+#[tokio::test]
+async fn multiple_users_get_notified_on_third_user_join() {
+    let ws_port = spawn_ws_server(SharedServerState::default()).await;
 
-    async fn send_message(&mut self, msg: &str) {
-        let payload = Payload {
-            event_type: PayloadEventType::Message,
-            username: self.username.clone(),
-            message: Some(msg.into()),
-        };
-        self.send(&payload).await;
-    }
+    let mut user1 = join(ws_port, "user1").await;
+    let mut user2 = join(ws_port, "user2").await;
+    let _user3 = join(ws_port, "user3").await;
+
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+        check_joined(&mut user1, "user3").await;
+        check_joined(&mut user2, "user3").await;
+    })
+    .await
+    .expect("timed out");
 }
 
-async fn check_payload(rx: &mut Receiver<String>, expected: &Payload) {
-    let msg = rx.recv().await.expect("unable to receive message");
-    let actual: Payload = serde_json::from_str(&msg).expect("wrong message format");
-    assert_eq!(actual, *expected);
+#[tokio::test]
+async fn bidirectional_messaging_between_two_users() {
+    let ws_port = spawn_ws_server(SharedServerState::default()).await;
+
+    let mut user1 = join(ws_port, "user1").await;
+    let mut user2 = join(ws_port, "user2").await;
+
+    user1.send_message("hello 1").unwrap();
+    user2.send_message("hello 2").unwrap();
+
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+        check_message(&mut user2, &message("user1", "hello 1")).await;
+        check_message(&mut user1, &message("user2", "hello 2")).await;
+    })
+    .await
+    .expect("timed out");
 }
 
-// TODO: Minimize boilerplate in test code
+#[tokio::test]
+async fn disallow_two_clients_with_same_username() {
+    let server_state = SharedServerState::default();
+    let ws_port = spawn_ws_server(server_state.clone()).await;
+
+    let _user1 = join(ws_port, "user1").await;
+    let _user2 = join(ws_port, "user1").await;
+
+    let user_count = server_state.lock().await.clients.len();
+    assert_eq!(user_count, 1);
+}
 
 #[tokio::test]
-async fn single_user_gets_notified_on_new_user_join() {
-    Lazy::force(&LOGGER);
+async fn only_the_server_announces_joining_and_leaving() {
+    let server_state = SharedServerState::default();
+    let ws_port = spawn_ws_server(server_state.clone()).await;
+
+    let mut user1 = join(ws_port, "user1").await;
+    let user2 = join(ws_port, "user2").await;
+    check_joined(&mut user1, "user2").await;
+
+    for event_type in [PayloadEventType::Disconnected, PayloadEventType::Connected] {
+        user2
+            .send(Payload {
+                event_type,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    user2.send_message("still here").unwrap();
+    check_message(&mut user1, &message("user2", "still here")).await;
+    assert_eq!(server_state.lock().await.clients.len(), 2);
+
+    drop(user2);
+    let expected = Event::Left {
+        username: "user2".into(),
+    };
+    assert_eq!(next_event(&mut user1).await, expected);
+}
 
-    let listener = TcpListener::bind(format!("{HOST}:0"))
-        .await
-        .expect("unable to bind socket");
-    let port = listener.local_addr().unwrap().port();
-    tokio::spawn(async move {
-        let server_state = SharedServerState::default();
-        ws_server::run_ws_server(listener, server_state).await
-    });
-
-    let mut user1 = TestClient::new("user1");
-    let (user1_msg_tx, mut user1_msg_rx) = tokio::sync::mpsc::channel(1);
-    user1.set_callback(Box::new(move |msg| {
-        let tx = user1_msg_tx.clone();
-        tokio::spawn(async move {
-            tx.send(msg).await.expect("unable to send");
-        });
+#[tokio::test]
+async fn messages_go_through_content_filters_before_broadcast() {
+    let server_state = SharedServerState::new(Mutex::new(ServerState {
+        message_filters: MessageFilterChain::new(vec![Box::new(ProfanityFilter::from_word_list(
+            "darn",
+            ProfanityAction::Redact,
+        ))]),
+        ..Default::default()
     }));
-    user1.connect(HOST, port).await;
+    let ws_port = spawn_ws_server(server_state.clone()).await;
 
-    let mut user2 = TestClient::new("user2");
-    user2.connect(HOST, port).await;
+    let mut user1 = join(ws_port, "user1").await;
+    let user2 = join(ws_port, "user2").await;
+    user2.send_message("darn it").unwrap();
 
     tokio::time::timeout(TIMEOUT_SECONDS, async {
-        let expected = Payload {
-            event_type: PayloadEventType::Connected,
-            username: user2.username,
-            message: None,
+        check_joined(&mut user1, "user2").await;
+        check_message(&mut user1, &message("user2", "**** it")).await;
+    })
+    .await
+    .expect("timed out");
+
+    let history = &server_state.lock().await.history;
+    assert_eq!(history.last().unwrap().message, Some("**** it".into()));
+}
+
+#[tokio::test]
+async fn reactions_are_aggregated_per_user_and_broadcasted() {
+    let server_state = SharedServerState::default();
+    let ws_port = spawn_ws_server(server_state.clone()).await;
+
+    let mut user1 = join(ws_port, "user1").await;
+    let mut user2 = join(ws_port, "user2").await;
+
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+
+        user1.send_message("hello").unwrap();
+        let message = receive_message(&mut user2).await;
+        let message_id = message.id.expect("message has no id");
+
+        user2.react(message_id, "👍").unwrap();
+        let expected = Event::Reactions {
+            message_id,
+            reactions: vec![Reaction {
+                emoji: "👍".into(),
+                count: 1,
+                usernames: vec!["user2".into()],
+            }],
+        };
+        assert_eq!(next_event(&mut user1).await, expected);
+        assert_eq!(next_event(&mut user2).await, expected);
+
+        // Reacting with the same emoji again has no effect
+        user2.react(message_id, "👍").unwrap();
+        user2.unreact(message_id, "👍").unwrap();
+        let expected = Event::Reactions {
+            message_id,
+            reactions: Vec::new(),
         };
-        check_payload(&mut user1_msg_rx, &expected).await;
+        assert_eq!(next_event(&mut user1).await, expected);
     })
     .await
     .expect("timed out");
+
+    let history = &server_state.lock().await.history;
+    assert!(history.last().unwrap().reactions.is_empty());
 }
 
 #[tokio::test]
-async fn multiple_users_get_notified_on_third_user_join() {
-    Lazy::force(&LOGGER);
+async fn replies_are_counted_and_require_existing_parent() {
+    let server_state = SharedServerState::default();
+    let ws_port = spawn_ws_server(server_state.clone()).await;
 
-    let listener = TcpListener::bind(format!("{HOST}:0"))
-        .await
-        .expect("unable to bind socket");
-    let port = listener.local_addr().unwrap().port();
-    tokio::spawn(async move {
-        let server_state = SharedServerState::default();
-        ws_server::run_ws_server(listener, server_state).await
-    });
-
-    let mut user1 = TestClient::new("user1");
-    let (user1_msg_tx, mut user1_msg_rx) = tokio::sync::mpsc::channel(2);
-    user1.set_callback(Box::new(move |msg| {
-        let tx = user1_msg_tx.clone();
-        tokio::spawn(async move {
-            tx.send(msg).await.expect("unable to send");
-        });
-    }));
-    user1.connect(HOST, port).await;
-
-    let mut user2 = TestClient::new("user2");
-    let (user2_msg_tx, mut user2_msg_rx) = tokio::sync::mpsc::channel(1);
-    user2.set_callback(Box::new(move |msg| {
-        let tx = user2_msg_tx.clone();
-        tokio::spawn(async move {
-            tx.send(msg).await.expect("unable to send");
-        });
-    }));
-    user2.connect(HOST, port).await;
+    let mut user1 = join(ws_port, "user1").await;
+    let mut user2 = join(ws_port, "user2").await;
 
-    let mut user3 = TestClient::new("user3");
-    user3.connect(HOST, port).await;
+    let parent_id = tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+        user1.send_message("question").unwrap();
+        let parent_id = receive_message(&mut user2).await.id.unwrap();
 
-    tokio::time::timeout(TIMEOUT_SECONDS, async {
-        let user2_connect_payload = Payload {
-            event_type: PayloadEventType::Connected,
-            username: user2.username,
-            message: None,
+        // Dropped, because parent does not exist
+        user2.reply(parent_id + 100, "answer").unwrap();
+        user2.reply(parent_id, "answer").unwrap();
+
+        let expected = Payload {
+            reply_to: Some(parent_id),
+            ..message("user2", "answer")
         };
-        let user3_connect_payload = Payload {
-            event_type: PayloadEventType::Connected,
-            username: user3.username,
-            message: None,
+        check_message(&mut user1, &expected).await;
+
+        // Updated count follows the reply, and is sent to its sender as well
+        let expected = Event::Replies {
+            message_id: parent_id,
+            reply_count: 1,
         };
-        check_payload(&mut user1_msg_rx, &user2_connect_payload).await;
-        check_payload(&mut user1_msg_rx, &user3_connect_payload).await;
-        check_payload(&mut user2_msg_rx, &user3_connect_payload).await;
+        assert_eq!(next_event(&mut user1).await, expected);
+        assert_eq!(next_event(&mut user2).await, expected);
+        parent_id
     })
     .await
     .expect("timed out");
+
+    let server_state = server_state.lock().await;
+    let parent = server_state.history_entry(parent_id).unwrap();
+    assert_eq!(parent.reply_count, 1);
 }
 
 #[tokio::test]
-async fn bidirectional_messaging_between_two_users() {
-    Lazy::force(&LOGGER);
+async fn read_positions_are_saved_and_broadcasted() {
+    let server_state = SharedServerState::default();
+    let ws_port = spawn_ws_server(server_state.clone()).await;
+
+    let mut user1 = join(ws_port, "user1").await;
+    let mut user2 = join(ws_port, "user2").await;
+
+    let message_id = tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+        user1.send_message("hello").unwrap();
+        let message_id = receive_message(&mut user2).await.id.unwrap();
+
+        user2.mark_read(message_id).unwrap();
+        let expected = Event::Read {
+            username: "user2".into(),
+            message_id,
+        };
+        assert_eq!(next_event(&mut user1).await, expected);
+        message_id
+    })
+    .await
+    .expect("timed out");
+
+    let server_state = server_state.lock().await;
+    assert_eq!(server_state.read_positions.get("user2"), Some(&message_id));
+    assert_eq!(server_state.unread_count("user2").unread, 0);
+}
 
-    let listener = TcpListener::bind(format!("{HOST}:0"))
+#[test]
+fn read_positions_are_saved_for_the_joined_user() {
+    let mut server_state = ServerState {
+        last_history_id: 5,
+        ..Default::default()
+    };
+    let client_id = ClientId::Session(1);
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    chat::add_client(
+        &mut server_state,
+        client_id,
+        tx,
+        Protocol::default(),
+        "user2",
+    )
+    .unwrap();
+
+    let read = Payload {
+        event_type: PayloadEventType::Read,
+        username: "user1".into(),
+        message_id: Some(5),
+        ..Default::default()
+    };
+    chat::update_read_position(&mut server_state, read.clone(), client_id).unwrap();
+    assert_eq!(server_state.read_positions.get("user2"), Some(&5));
+    assert_eq!(server_state.read_positions.get("user1"), None);
+
+    // Clients must join first
+    assert!(chat::update_read_position(&mut server_state, read, ClientId::Session(2)).is_err());
+}
+
+#[tokio::test]
+async fn members_leaving_during_broadcast_are_skipped() {
+    let mut server_state = ServerState::default();
+    let mut receivers = Vec::new();
+    for (id, username) in ["leaving", "staying"].into_iter().enumerate() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        chat::add_client(
+            &mut server_state,
+            ClientId::Session(id as u64),
+            tx,
+            Protocol::default(),
+            username,
+        )
+        .unwrap();
+        receivers.push(rx);
+    }
+    // Receiver of a closed connection is dropped before the member is removed from the chat
+    let mut staying = receivers.pop().unwrap();
+    drop(receivers);
+
+    let payload = message("user1", "hello @leaving and @staying");
+    chat::broadcast(&mut server_state, payload, None).await;
+    let received = Protocol::default().decode(&staying.recv().await.unwrap());
+    assert_eq!(
+        received.unwrap().message.as_deref(),
+        Some("hello @leaving and @staying")
+    );
+    assert_eq!(server_state.clients.len(), 2);
+}
+
+#[tokio::test]
+async fn mentioned_users_receive_notification() {
+    let ws_port = spawn_ws_server(SharedServerState::default()).await;
+
+    let mut user1 = join(ws_port, "user1").await;
+    let user2 = join(ws_port, "user2").await;
+
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+        user2
+            .send_message("ping @user1, @user1 and @nobody.")
+            .unwrap();
+
+        let message = receive_message(&mut user1).await;
+        assert_eq!(message.mentions, vec!["user1", "nobody"]);
+
+        let expected = Event::Mention {
+            username: "user2".into(),
+            message: message.message.unwrap(),
+            message_id: message.id.unwrap(),
+        };
+        assert_eq!(next_event(&mut user1).await, expected);
+    })
+    .await
+    .expect("timed out");
+}
+
+#[tokio::test]
+async fn messages_with_unknown_attachments_are_rejected() {
+    let uploaded = Attachment {
+        id: "uploaded".into(),
+        filename: "cat.png".into(),
+        content_type: "image/png".into(),
+        size: 42,
+    };
+    let server_state = SharedServerState::default();
+    server_state
+        .lock()
         .await
-        .expect("unable to bind socket");
-    let port = listener.local_addr().unwrap().port();
-    tokio::spawn(async move {
-        let server_state = SharedServerState::default();
-        ws_server::run_ws_server(listener, server_state).await
-    });
-
-    let mut user1 = TestClient::new("user1");
-    let (user1_msg_tx, mut user1_msg_rx) = tokio::sync::mpsc::channel(2);
-    user1.set_callback(Box::new(move |msg| {
-        let tx = user1_msg_tx.clone();
-        tokio::spawn(async move {
-            tx.send(msg).await.expect("unable to send");
-        });
-    }));
-    user1.connect(HOST, port).await;
-
-    let mut user2 = TestClient::new("user2");
-    let (user2_msg_tx, mut user2_msg_rx) = tokio::sync::mpsc::channel(2);
-    user2.set_callback(Box::new(move |msg| {
-        let tx = user2_msg_tx.clone();
-        tokio::spawn(async move {
-            tx.send(msg).await.expect("unable to send");
-        });
-    }));
-    user2.connect(HOST, port).await;
+        .attachments
+        .insert(uploaded.clone())
+        .unwrap();
+    let ws_port = spawn_ws_server(server_state).await;
 
-    user1.send_message("hello 1").await;
-    user2.send_message("hello 2").await;
+    let mut user1 = join(ws_port, "user1").await;
+    let user2 = join(ws_port, "user2").await;
 
     tokio::time::timeout(TIMEOUT_SECONDS, async {
-        let expected = Payload {
-            event_type: PayloadEventType::Connected,
-            username: user2.username.clone(),
-            message: None,
+        check_joined(&mut user1, "user2").await;
+
+        let with_attachment = |id: &str| Payload {
+            attachments: vec![Attachment {
+                id: id.into(),
+                filename: String::new(),
+                content_type: String::new(),
+                size: 0,
+            }],
+            ..message("user2", "look")
         };
-        check_payload(&mut user1_msg_rx, &expected).await;
+        user2.send(with_attachment("not-uploaded")).unwrap();
+        user2.send(with_attachment("uploaded")).unwrap();
 
+        // Metadata is filled in by the server
         let expected = Payload {
-            event_type: PayloadEventType::Message,
-            username: user1.username.clone(),
-            message: Some("hello 1".into()),
+            attachments: vec![uploaded],
+            ..with_attachment("uploaded")
         };
-        check_payload(&mut user2_msg_rx, &expected).await;
-        let expected = Payload {
-            event_type: PayloadEventType::Message,
-            username: user2.username.clone(),
-            message: Some("hello 2".into()),
+        check_message(&mut user1, &expected).await;
+    })
+    .await
+    .expect("timed out");
+}
+
+/// Link preview source answering without network access.
+struct StaticLinkFetcher;
+
+impl LinkFetcher for StaticLinkFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<LinkPreview, String>> {
+        Box::pin(async move {
+            Ok(LinkPreview {
+                url: url.into(),
+                title: Some("Example".into()),
+                description: Some("Example page".into()),
+            })
+        })
+    }
+}
+
+#[tokio::test]
+async fn link_previews_follow_broadcasted_message() {
+    let unfurler = LinkUnfurler::new(Box::new(StaticLinkFetcher), &LinkPreviewConfig::default());
+    let server_state = SharedServerState::new(Mutex::new(ServerState {
+        link_unfurler: Some(Arc::new(unfurler)),
+        ..Default::default()
+    }));
+    let ws_port = spawn_ws_server(server_state.clone()).await;
+
+    let mut user1 = join(ws_port, "user1").await;
+    let user2 = join(ws_port, "user2").await;
+
+    let expected_previews = vec![LinkPreview {
+        url: "https://example.com".into(),
+        title: Some("Example".into()),
+        description: Some("Example page".into()),
+    }];
+    let message_id = tokio::time::timeout(TIMEOUT_SECONDS, async {
+        check_joined(&mut user1, "user2").await;
+        user2
+            .send_message("have a look: https://example.com")
+            .unwrap();
+
+        // Original message is delivered without previews
+        let message = receive_message(&mut user1).await;
+        assert!(message.previews.is_empty());
+
+        let expected = Event::Previews {
+            message_id: message.id.unwrap(),
+            previews: expected_previews.clone(),
         };
-        check_payload(&mut user1_msg_rx, &expected).await;
+        assert_eq!(next_event(&mut user1).await, expected);
+        message.id.unwrap()
     })
     .await
     .expect("timed out");
+
+    let server_state = server_state.lock().await;
+    let message = server_state.history_entry(message_id).unwrap();
+    assert_eq!(message.previews, expected_previews);
 }
 
 #[tokio::test]
-async fn disallow_two_clients_with_same_username() {
-    Lazy::force(&LOGGER);
+async fn joining_user_receives_latest_history_before_live_messages() {
+    let server_state = Arc::new(Mutex::new(ServerState {
+        history_replay_count: 2,
+        ..Default::default()
+    }));
+    let ws_port = spawn_ws_server(server_state.clone()).await;
 
-    let listener = TcpListener::bind(format!("{HOST}:0"))
-        .await
-        .expect("unable to bind socket");
-    let port = listener.local_addr().unwrap().port();
-    let server_state = SharedServerState::default();
-    let server_state_clone = server_state.clone();
-    tokio::spawn(async move { ws_server::run_ws_server(listener, server_state_clone).await });
+    let user1 = join(ws_port, "user1").await;
+    for msg in ["first", "second", "third"] {
+        user1.send_message(msg).unwrap();
+    }
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        while server_state.lock().await.history.len() < 4 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out");
 
-    let mut user1 = TestClient::new("user1");
-    let mut user2 = TestClient::new("user1");
-    user1.connect(HOST, port).await;
-    user2.connect(HOST, port).await;
+    let mut user2 = join(ws_port, "user2").await;
 
-    let user_count = server_state.lock().await.clients.len();
-    assert_eq!(user_count, 1);
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        let Event::History { events, last_id } = next_event(&mut user2).await else {
+            panic!("expected history batch");
+        };
+        assert_eq!(last_id, 4);
+        let messages: Vec<_> = events
+            .iter()
+            .map(|payload| (payload.id.unwrap(), payload.message.as_deref().unwrap()))
+            .collect();
+        assert_eq!(messages, vec![(3, "second"), (4, "third")]);
+
+        user1.send_message("live").unwrap();
+        let live = receive_message(&mut user2).await;
+        assert_eq!(live.message.as_deref(), Some("live"));
+        assert!(live.id > Some(last_id));
+    })
+    .await
+    .expect("timed out");
 }
@@ -0,0 +1,98 @@
+mod common;
+
+use std::time::Duration;
+
+use chat_backend::ServerState;
+use common::{receive_message, TestServer, TIMEOUT_SECONDS};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+async fn spawn_server() -> TestServer {
+    TestServer::spawn(ServerState {
+        history_replay_count: 50,
+        ..Default::default()
+    })
+    .await
+}
+
+/// Value of a sample in Prometheus text format, like `chat_history_events 3`.
+fn sample(metrics: &str, name: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix(' ')?;
+        value.parse().ok()
+    })
+}
+
+/// Scrape metrics until `sample_name` reaches `value`, returning the scraped metrics.
+async fn wait_for_sample(server: &TestServer, sample_name: &str, value: f64) -> String {
+    tokio::time::timeout(TIMEOUT_SECONDS, async {
+        loop {
+            let response = reqwest::get(server.url("/metrics")).await.unwrap();
+            let metrics = response.text().await.unwrap();
+            if sample(&metrics, sample_name) == Some(value) {
+                return metrics;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("{sample_name} did not reach {value}"))
+}
+
+#[tokio::test]
+async fn metrics_are_exposed_in_prometheus_format() {
+    let server = spawn_server().await;
+
+    let response = reqwest::get(server.url("/metrics")).await.unwrap();
+    assert!(response.status().is_success());
+    let content_type = response.headers()["content-type"].to_str().unwrap();
+    assert!(content_type.starts_with("text/plain; version=0.0.4"));
+    let metrics = response.text().await.unwrap();
+    assert!(metrics.contains("# TYPE chat_broadcast_fanout_seconds histogram"));
+    assert_eq!(sample(&metrics, "chat_messages_received_total"), Some(0.0));
+}
+
+#[tokio::test]
+async fn chat_activity_is_counted() {
+    let server = spawn_server().await;
+    let user1 = server.join("user1").await;
+    let mut user2 = server.join("user2").await;
+    user1.send_message("hello").unwrap();
+    receive_message(&mut user2).await;
+
+    let metrics = wait_for_sample(&server, "chat_messages_received_total", 1.0).await;
+    assert_eq!(
+        sample(&metrics, r#"chat_connected_clients{transport="websocket"}"#),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(&metrics, r#"chat_connected_clients{transport="http"}"#),
+        Some(0.0)
+    );
+    // Join of user2 is sent to user1, then the message to user2
+    assert_eq!(sample(&metrics, "chat_messages_sent_total"), Some(2.0));
+    assert_eq!(sample(&metrics, "chat_history_events"), Some(3.0));
+    assert_eq!(
+        sample(&metrics, "chat_broadcast_fanout_seconds_count"),
+        Some(3.0)
+    );
+    assert!(sample(&metrics, "chat_outbound_queue_depth_count").unwrap() >= 2.0);
+    assert!(sample(&metrics, "chat_state_lock_wait_seconds_count").unwrap() > 0.0);
+}
+
+#[tokio::test]
+async fn failed_handshakes_are_counted() {
+    let server = spawn_server().await;
+
+    let mut stream = TcpStream::connect(server.ws_url.trim_start_matches("ws://"))
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    drop(stream);
+
+    wait_for_sample(&server, "chat_websocket_handshake_failures_total", 1.0).await;
+    // Server keeps accepting connections
+    server.join("user1").await;
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use chat_backend::{Payload, PayloadEventType, ServerState, SharedServerState};
+use common::{join, spawn_servers, HOST, TIMEOUT_SECONDS};
+use tokio::sync::Mutex;
+
+fn server_state_with_history(count: u64) -> SharedServerState {
+    SharedServerState::new(Mutex::new(ServerState {
+        history: (1..=count)
+            .map(|id| Payload {
+                username: "user1".into(),
+                message: Some(format!("message {id}")),
+                id: Some(id),
+                timestamp: Some(id),
+                ..Default::default()
+            })
+            .collect(),
+        last_history_id: count,
+        ..Default::default()
+    }))
+}
+
+async fn poll(rest_port: u16, query: &str) -> Payload {
+    let response = reqwest::get(format!("http://{HOST}:{rest_port}/poll?{query}"))
+        .await
+        .expect("failed to execute request");
+    assert!(response.status().is_success());
+    let batch: Payload = response.json().await.unwrap();
+    assert_eq!(batch.event_type, PayloadEventType::HistoryBatch);
+    batch
+}
+
+fn ids(batch: &Payload) -> Vec<u64> {
+    batch
+        .history
+        .iter()
+        .map(|payload| payload.id.unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn missed_events_are_returned_immediately() {
+    let (rest_port, _) = spawn_servers(server_state_with_history(5)).await;
+
+    let batch = poll(rest_port, "after=3").await;
+    assert_eq!(ids(&batch), vec![4, 5]);
+    assert_eq!(batch.message_id, Some(5));
+}
+
+#[tokio::test]
+async fn poll_waits_for_new_events() {
+    let (rest_port, ws_port) = spawn_servers(server_state_with_history(2)).await;
+
+    let pending_poll = tokio::spawn(poll(rest_port, "after=2"));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!pending_poll.is_finished());
+
+    let _ws_client = join(ws_port, "user2").await;
+
+    let batch = tokio::time::timeout(TIMEOUT_SECONDS, pending_poll)
+        .await
+        .expect("timed out")
+        .unwrap();
+    assert_eq!(ids(&batch), vec![3]);
+    assert_eq!(batch.history[0].event_type, PayloadEventType::Connected);
+    assert_eq!(batch.history[0].username, "user2");
+    assert_eq!(batch.message_id, Some(3));
+}
+
+#[tokio::test]
+async fn poll_times_out_with_empty_batch() {
+    let (rest_port, _) = spawn_servers(server_state_with_history(2)).await;
+
+    let start = Instant::now();
+    let batch = poll(rest_port, "timeout=1").await;
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert!(batch.history.is_empty());
+    assert_eq!(batch.message_id, Some(2));
+}
+
+#[tokio::test]
+async fn following_message_id_misses_no_event() {
+    let (rest_port, _) = spawn_servers(server_state_with_history(1234)).await;
+
+    let mut after = 0;
+    let mut received = Vec::new();
+    while after < 1234 {
+        let batch = poll(rest_port, &format!("after={after}")).await;
+        assert!(batch.history.len() <= 500);
+        received.extend(ids(&batch));
+        after = batch.message_id.unwrap();
+    }
+    assert_eq!(received, (1..=1234).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn clients_ahead_of_server_start_over() {
+    let (rest_port, _) = spawn_servers(server_state_with_history(2)).await;
+
+    let batch = poll(rest_port, "after=10").await;
+    assert!(batch.history.is_empty());
+    assert_eq!(batch.message_id, Some(2));
+}
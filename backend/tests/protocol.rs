@@ -0,0 +1,412 @@
+mod common;
+
+use chat_backend::{
+    attachment::Attachment,
+    protocol::{Encoding, Protocol, ProtocolVersion, V1_SYSTEM_USERNAME},
+    unfurl::LinkPreview,
+    Payload, PayloadEventType, Reaction, SharedServerState,
+};
+use common::{spawn_ws_server, HOST, TIMEOUT_SECONDS};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+// Clients speak raw frames, as these tests cover the wire format hidden by `client::Client`
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Connect offering the given `Sec-WebSocket-Protocol` header, returning the accepted protocol.
+async fn connect(port: u16, offered_protocols: Option<&str>) -> (Client, Option<String>) {
+    let mut request = format!("ws://{HOST}:{port}").into_client_request().unwrap();
+    if let Some(offered_protocols) = offered_protocols {
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", offered_protocols.parse().unwrap());
+    }
+    let (client, response) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("failed to connect");
+    let accepted = response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .map(|value| value.to_str().unwrap().to_string());
+    (client, accepted)
+}
+
+async fn send(client: &mut Client, json: &str) {
+    client
+        .send(Message::text(json))
+        .await
+        .expect("unable to send");
+}
+
+async fn receive(client: &mut Client) -> Message {
+    tokio::time::timeout(TIMEOUT_SECONDS, client.next())
+        .await
+        .expect("timed out")
+        .expect("connection closed")
+        .expect("error during receive")
+}
+
+async fn receive_text(client: &mut Client) -> String {
+    receive(client)
+        .await
+        .into_text()
+        .expect("not a text message")
+        .to_string()
+}
+
+#[test]
+fn latest_offered_version_is_negotiated() {
+    assert_eq!(
+        Protocol::negotiate("chat.v1, chat.v2").map(Protocol::subprotocol),
+        Some("chat.v2")
+    );
+    assert_eq!(
+        Protocol::negotiate("chat.v9,chat.v1").map(Protocol::subprotocol),
+        Some("chat.v1")
+    );
+    assert_eq!(Protocol::negotiate("graphql-ws"), None);
+}
+
+#[test]
+fn encodings_are_chosen_in_order_of_client_preference() {
+    assert_eq!(
+        Protocol::negotiate("chat.v1, chat.v2.cbor, chat.v2"),
+        Some(Protocol {
+            version: ProtocolVersion::V2,
+            encoding: Encoding::Cbor
+        })
+    );
+    assert_eq!(
+        Protocol::negotiate("chat.v2.msgpack, chat.v2.cbor").map(|protocol| protocol.encoding),
+        Some(Encoding::MessagePack)
+    );
+}
+
+#[test]
+fn encodings_are_equivalent() {
+    let payload = Payload {
+        event_type: PayloadEventType::HistoryBatch,
+        message_id: Some(3),
+        history: vec![
+            Payload {
+                event_type: PayloadEventType::Connected,
+                username: "user1".into(),
+                id: Some(1),
+                timestamp: Some(1_700_000_000_000),
+                ..Default::default()
+            },
+            Payload {
+                username: "user1".into(),
+                message: Some("hi @user2 👋 https://github.com".into()),
+                id: Some(3),
+                timestamp: Some(1_700_000_000_500),
+                reactions: vec![Reaction {
+                    emoji: "🎉".into(),
+                    count: 2,
+                    usernames: vec!["user2".into(), "user3".into()],
+                }],
+                reply_to: Some(2),
+                reply_count: 1,
+                mentions: vec!["user2".into()],
+                attachments: vec![Attachment {
+                    id: "abc".into(),
+                    filename: "notes.txt".into(),
+                    content_type: "text/plain".into(),
+                    size: 3,
+                }],
+                previews: vec![LinkPreview {
+                    url: "https://github.com".into(),
+                    title: Some("GitHub".into()),
+                    description: None,
+                }],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    for encoding in [Encoding::Json, Encoding::MessagePack, Encoding::Cbor] {
+        let msg = encoding.encode(&payload);
+        assert_eq!(msg.is_binary(), encoding != Encoding::Json);
+        assert_eq!(encoding.decode(&msg).unwrap(), payload, "{encoding:?}");
+    }
+
+    // Binary encodings are the point for mobile clients
+    let json_size = Encoding::Json.encode(&payload).len();
+    assert!(Encoding::MessagePack.encode(&payload).len() < json_size);
+    assert!(Encoding::Cbor.encode(&payload).len() < json_size);
+}
+
+#[test]
+fn system_events_are_messages_of_the_server_for_legacy_clients() {
+    let announcement = Payload {
+        event_type: PayloadEventType::System,
+        message: Some("maintenance at 18:00".into()),
+        id: Some(7),
+        ..Default::default()
+    };
+
+    let translated = ProtocolVersion::V1.translate(&announcement).unwrap();
+    assert_eq!(translated.event_type, PayloadEventType::Message);
+    assert_eq!(translated.username, V1_SYSTEM_USERNAME);
+    assert_eq!(translated.message, announcement.message);
+    assert_eq!(translated.id, None);
+    assert_eq!(
+        ProtocolVersion::V2.translate(&announcement),
+        Some(announcement)
+    );
+}
+
+#[test]
+fn binary_frames_are_rejected_for_json() {
+    let msg = Encoding::MessagePack.encode(&Payload::default());
+    assert!(Encoding::Json.decode(&msg).is_err());
+}
+
+#[tokio::test]
+async fn hello_announces_negotiated_protocol_and_capabilities() {
+    let port = spawn_ws_server(SharedServerState::default()).await;
+
+    let (mut client, accepted) = connect(port, Some("chat.v1, chat.v2, chat.v9")).await;
+    assert_eq!(accepted.as_deref(), Some("chat.v2"));
+
+    let hello: Payload = serde_json::from_str(&receive_text(&mut client).await).unwrap();
+    assert_eq!(hello.event_type, PayloadEventType::Hello);
+    assert_eq!(hello.protocol.as_deref(), Some("chat.v2"));
+    assert!(hello.capabilities.contains(&"reactions".to_string()));
+    assert!(!hello.capabilities.contains(&"link_previews".to_string()));
+}
+
+#[tokio::test]
+async fn legacy_clients_receive_translated_events() {
+    let port = spawn_ws_server(SharedServerState::default()).await;
+
+    let (mut current, _) = connect(port, Some("chat.v2")).await;
+    let hello: Payload = serde_json::from_str(&receive_text(&mut current).await).unwrap();
+    assert_eq!(hello.event_type, PayloadEventType::Hello);
+    send(
+        &mut current,
+        r#"{"event_type":"connected","username":"new"}"#,
+    )
+    .await;
+
+    // Clients without `Sec-WebSocket-Protocol` header speak the original protocol
+    let (mut legacy, accepted) = connect(port, None).await;
+    assert_eq!(accepted, None);
+    send(
+        &mut legacy,
+        r#"{"event_type":"connected","username":"old"}"#,
+    )
+    .await;
+    let connected: Payload = serde_json::from_str(&receive_text(&mut current).await).unwrap();
+    assert_eq!(connected.username, "old");
+
+    // Mention notifications and reactions are not part of the original protocol
+    send(
+        &mut current,
+        r#"{"event_type":"message","username":"new","message":"hello @old"}"#,
+    )
+    .await;
+    send(
+        &mut current,
+        r#"{"event_type":"react","username":"new","message_id":3,"emoji":"👍"}"#,
+    )
+    .await;
+    send(
+        &mut current,
+        r#"{"event_type":"message","username":"new","message":"bye"}"#,
+    )
+    .await;
+
+    assert_eq!(
+        receive_text(&mut legacy).await,
+        r#"{"event_type":"message","username":"new","message":"hello @old"}"#
+    );
+    assert_eq!(
+        receive_text(&mut legacy).await,
+        r#"{"event_type":"message","username":"new","message":"bye"}"#
+    );
+}
+
+#[tokio::test]
+async fn legacy_clients_cannot_send_newer_event_types() {
+    let port = spawn_ws_server(SharedServerState::default()).await;
+
+    let (mut current, _) = connect(port, Some("chat.v2")).await;
+    receive_text(&mut current).await;
+    send(
+        &mut current,
+        r#"{"event_type":"connected","username":"new"}"#,
+    )
+    .await;
+
+    let (mut legacy, _) = connect(port, Some("chat.v1")).await;
+    send(
+        &mut legacy,
+        r#"{"event_type":"connected","username":"old"}"#,
+    )
+    .await;
+    send(
+        &mut legacy,
+        r#"{"event_type":"read","username":"old","message_id":1}"#,
+    )
+    .await;
+    send(
+        &mut legacy,
+        r#"{"event_type":"message","username":"old","message":"hi"}"#,
+    )
+    .await;
+
+    let connected: Payload = serde_json::from_str(&receive_text(&mut current).await).unwrap();
+    assert_eq!(connected.event_type, PayloadEventType::Connected);
+    let message: Payload = serde_json::from_str(&receive_text(&mut current).await).unwrap();
+    assert_eq!(message.event_type, PayloadEventType::Message);
+    assert_eq!(message.message.as_deref(), Some("hi"));
+}
+
+#[tokio::test]
+async fn clients_receive_same_events_in_their_encoding() {
+    let port = spawn_ws_server(SharedServerState::default()).await;
+
+    let (mut sender, _) = connect(port, Some("chat.v2")).await;
+    receive(&mut sender).await;
+    send(
+        &mut sender,
+        r#"{"event_type":"connected","username":"sender"}"#,
+    )
+    .await;
+
+    let mut receivers = Vec::new();
+    for (username, subprotocol) in [
+        ("json", "chat.v2"),
+        ("msgpack", "chat.v2.msgpack"),
+        ("cbor", "chat.v2.cbor"),
+    ] {
+        let (mut client, accepted) = connect(port, Some(subprotocol)).await;
+        assert_eq!(accepted.as_deref(), Some(subprotocol));
+        let encoding = Protocol::from_subprotocol(subprotocol).unwrap().encoding;
+
+        let hello = encoding.decode(&receive(&mut client).await).unwrap();
+        assert_eq!(hello.protocol.as_deref(), Some(subprotocol));
+
+        // Binary clients send binary frames too
+        let connected = Payload {
+            event_type: PayloadEventType::Connected,
+            username: username.into(),
+            ..Default::default()
+        };
+        client.send(encoding.encode(&connected)).await.unwrap();
+        let connected = receive_text(&mut sender).await;
+        assert!(connected.contains(username));
+        receivers.push((client, encoding));
+    }
+
+    send(
+        &mut sender,
+        r#"{"event_type":"message","username":"sender","message":"hello @cbor"}"#,
+    )
+    .await;
+
+    let mut received = Vec::new();
+    for (client, encoding) in &mut receivers {
+        // Skip presence events of clients that joined later
+        loop {
+            let msg = receive(client).await;
+            assert_eq!(msg.is_binary(), *encoding != Encoding::Json);
+            let payload = encoding.decode(&msg).unwrap();
+            if payload.event_type == PayloadEventType::Message {
+                received.push(payload);
+                break;
+            }
+        }
+    }
+    assert_eq!(received[0].message.as_deref(), Some("hello @cbor"));
+    assert_eq!(received[0].mentions, vec!["cbor".to_string()]);
+    assert_eq!(received[0], received[1]);
+    assert_eq!(received[0], received[2]);
+
+    let (cbor_client, encoding) = &mut receivers[2];
+    let mention = encoding.decode(&receive(cbor_client).await).unwrap();
+    assert_eq!(mention.event_type, PayloadEventType::Mention);
+}
+
+#[tokio::test]
+async fn events_are_sent_as_the_joined_user() {
+    let port = spawn_ws_server(SharedServerState::default()).await;
+
+    let (mut victim, _) = connect(port, None).await;
+    send(
+        &mut victim,
+        r#"{"event_type":"connected","username":"victim"}"#,
+    )
+    .await;
+
+    // Events are ignored until the client has joined
+    let (mut mallory, _) = connect(port, None).await;
+    send(
+        &mut mallory,
+        r#"{"event_type":"message","username":"victim","message":"before joining"}"#,
+    )
+    .await;
+    send(
+        &mut mallory,
+        r#"{"event_type":"connected","username":"mallory"}"#,
+    )
+    .await;
+    send(
+        &mut mallory,
+        r#"{"event_type":"message","username":"victim","message":"spoofed"}"#,
+    )
+    .await;
+
+    let connected: Payload = serde_json::from_str(&receive_text(&mut victim).await).unwrap();
+    assert_eq!(connected.event_type, PayloadEventType::Connected);
+    assert_eq!(connected.username, "mallory");
+    assert_eq!(
+        receive_text(&mut victim).await,
+        r#"{"event_type":"message","username":"mallory","message":"spoofed"}"#
+    );
+}
+
+#[tokio::test]
+async fn clients_sending_frames_before_the_handshake_response_are_rejected() {
+    let port = spawn_ws_server(SharedServerState::default()).await;
+    let (mut observer, _) = connect(port, None).await;
+    send(
+        &mut observer,
+        r#"{"event_type":"connected","username":"observer"}"#,
+    )
+    .await;
+
+    // Masked text frame, with a zero mask leaving the payload as is
+    let join = br#"{"event_type":"connected","username":"eager"}"#;
+    let mut request = format!(
+        "GET / HTTP/1.1\r\nHost: {HOST}:{port}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    )
+    .into_bytes();
+    request.extend_from_slice(&[0x81, 0x80 | join.len() as u8, 0, 0, 0, 0]);
+    request.extend_from_slice(join);
+    let mut eager = TcpStream::connect(format!("{HOST}:{port}")).await.unwrap();
+    eager.write_all(&request).await.unwrap();
+
+    // Connection is closed instead of losing the frame
+    let mut response = Vec::new();
+    tokio::time::timeout(TIMEOUT_SECONDS, eager.read_to_end(&mut response))
+        .await
+        .expect("timed out")
+        .unwrap();
+    assert!(!response.starts_with(b"HTTP/1.1 101"));
+
+    let (mut late, _) = connect(port, None).await;
+    send(&mut late, r#"{"event_type":"connected","username":"late"}"#).await;
+    let connected: Payload = serde_json::from_str(&receive_text(&mut observer).await).unwrap();
+    assert_eq!(connected.username, "late");
+}
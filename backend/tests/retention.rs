@@ -0,0 +1,153 @@
+mod common;
+
+use chat_backend::{
+    configuration::RetentionConfig,
+    retention::enforce_retention,
+    search::{SearchIndex, SearchQuery},
+    Payload, PayloadEventType, ServerState,
+};
+use common::{TestServer, ADMIN_TOKEN};
+use reqwest::StatusCode;
+
+const NOW_MILLIS: u64 = 100_000;
+
+fn event(id: u64, event_type: PayloadEventType, age_secs: u64) -> Payload {
+    Payload {
+        event_type,
+        username: "user1".into(),
+        message: Some(format!("message {id}")),
+        id: Some(id),
+        timestamp: Some(NOW_MILLIS - age_secs * 1000),
+        ..Default::default()
+    }
+}
+
+fn server_state_with_history(retention: RetentionConfig) -> ServerState {
+    let history = vec![
+        event(1, PayloadEventType::Connected, 90),
+        event(2, PayloadEventType::Message, 80),
+        event(3, PayloadEventType::Message, 30),
+        event(4, PayloadEventType::Disconnected, 20),
+        event(5, PayloadEventType::Message, 10),
+    ];
+    let search_index = SearchIndex::new().unwrap();
+    for payload in &history {
+        search_index.insert(payload).unwrap();
+    }
+    ServerState {
+        history,
+        search_index,
+        retention,
+        ..Default::default()
+    }
+}
+
+fn history_ids(server_state: &ServerState) -> Vec<u64> {
+    server_state
+        .history
+        .iter()
+        .map(|payload| payload.id.unwrap())
+        .collect()
+}
+
+#[test]
+fn events_older_than_max_age_are_removed() {
+    let mut server_state = server_state_with_history(RetentionConfig {
+        max_age_secs: Some(60),
+        ..Default::default()
+    });
+
+    assert_eq!(enforce_retention(&mut server_state, NOW_MILLIS), 2);
+    assert_eq!(history_ids(&server_state), vec![3, 4, 5]);
+    assert_eq!(server_state.stats().messages, 2);
+
+    let query = SearchQuery {
+        query: "message".into(),
+        ..Default::default()
+    };
+    let mut found: Vec<_> = server_state
+        .search_index
+        .search(&query)
+        .unwrap()
+        .iter()
+        .map(|hit| hit.id)
+        .collect();
+    found.sort();
+    assert_eq!(found, vec![3, 5]);
+}
+
+#[test]
+fn ages_beyond_any_timestamp_keep_every_event() {
+    let mut server_state = server_state_with_history(RetentionConfig {
+        max_age_secs: Some(u64::MAX),
+        ..Default::default()
+    });
+
+    assert_eq!(enforce_retention(&mut server_state, NOW_MILLIS), 0);
+    assert_eq!(history_ids(&server_state), vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn unset_retention_settings_have_defaults() {
+    let config: RetentionConfig = serde_json::from_str(r#"{"max_count": 100}"#).unwrap();
+    assert_eq!(config.max_count, Some(100));
+    assert_eq!(config.max_age_secs, None);
+    assert!(config.keep_presence_events);
+    assert_eq!(config.interval_secs, 60);
+}
+
+#[test]
+fn oldest_events_beyond_max_count_are_removed() {
+    let mut server_state = server_state_with_history(RetentionConfig {
+        max_count: Some(2),
+        ..Default::default()
+    });
+
+    assert_eq!(enforce_retention(&mut server_state, NOW_MILLIS), 3);
+    assert_eq!(history_ids(&server_state), vec![4, 5]);
+    assert_eq!(server_state.stats().messages, 1);
+}
+
+#[test]
+fn presence_events_can_be_dropped() {
+    let mut server_state = server_state_with_history(RetentionConfig {
+        keep_presence_events: false,
+        max_count: Some(2),
+        ..Default::default()
+    });
+
+    enforce_retention(&mut server_state, NOW_MILLIS);
+    assert_eq!(history_ids(&server_state), vec![3, 5]);
+}
+
+#[tokio::test]
+async fn retention_policy_is_only_available_to_admins() {
+    let server = TestServer::spawn(ServerState {
+        retention: RetentionConfig {
+            max_count: Some(100),
+            ..Default::default()
+        },
+        admin_token: Some(ADMIN_TOKEN.into()),
+        ..Default::default()
+    })
+    .await;
+    let client = reqwest::Client::new();
+    let url = server.url("/admin/retention");
+
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.get(&url).bearer_auth("wrong").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get(&url)
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let policy: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(policy["max_count"], 100);
+    assert_eq!(policy["max_age_secs"], serde_json::Value::Null);
+    assert_eq!(policy["keep_presence_events"], true);
+}
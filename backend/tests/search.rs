@@ -0,0 +1,144 @@
+mod common;
+
+use chat_backend::{
+    search::{SearchHit, SearchIndex, SearchQuery},
+    Payload, PayloadEventType, ServerState,
+};
+use common::TestServer;
+
+fn message(id: u64, username: &str, message: &str, timestamp: u64) -> Payload {
+    Payload {
+        event_type: PayloadEventType::Message,
+        username: username.into(),
+        message: Some(message.into()),
+        id: Some(id),
+        timestamp: Some(timestamp),
+        ..Default::default()
+    }
+}
+
+fn populated_index() -> SearchIndex {
+    let index = SearchIndex::new().unwrap();
+    for payload in [
+        message(1, "user1", "the build is broken again", 1000),
+        message(2, "user2", "broken build, who broke it?", 2000),
+        message(3, "user1", "is the build fixed?", 3000),
+        message(4, "user3", "lunch is ready", 4000),
+        message(
+            6,
+            "user2",
+            "<img src=x onerror=alert(1)> pizza & salad",
+            5000,
+        ),
+        Payload {
+            event_type: PayloadEventType::Connected,
+            username: "user4".into(),
+            id: Some(5),
+            ..Default::default()
+        },
+    ] {
+        index.insert(&payload).unwrap();
+    }
+    index
+}
+
+fn search_ids(index: &SearchIndex, query: SearchQuery) -> Vec<u64> {
+    let mut ids: Vec<_> = index
+        .search(&query)
+        .unwrap()
+        .iter()
+        .map(|hit| hit.id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn words_and_phrases_must_all_match() {
+    let index = populated_index();
+    let query = |q: &str| SearchQuery {
+        query: q.into(),
+        ..Default::default()
+    };
+
+    assert_eq!(search_ids(&index, query("build")), vec![1, 2, 3]);
+    assert_eq!(search_ids(&index, query("BUILD broken")), vec![1, 2]);
+    assert_eq!(search_ids(&index, query("\"build is broken\"")), vec![1]);
+    // Query syntax of the index is not exposed
+    assert_eq!(
+        search_ids(&index, query("body:lunch OR build")),
+        Vec::<u64>::new()
+    );
+}
+
+#[test]
+fn results_are_filtered_by_user_and_time_range() {
+    let index = populated_index();
+
+    let query = SearchQuery {
+        query: "build".into(),
+        user: Some("user1".into()),
+        ..Default::default()
+    };
+    assert_eq!(search_ids(&index, query), vec![1, 3]);
+
+    let query = SearchQuery {
+        query: "build".into(),
+        from: Some(1500),
+        to: Some(3000),
+        ..Default::default()
+    };
+    assert_eq!(search_ids(&index, query), vec![2, 3]);
+}
+
+#[test]
+fn removed_messages_are_not_found() {
+    let index = populated_index();
+    index.remove(4).unwrap();
+
+    let query = SearchQuery {
+        query: "lunch".into(),
+        ..Default::default()
+    };
+    assert!(search_ids(&index, query).is_empty());
+}
+
+#[tokio::test]
+async fn search_endpoint_returns_highlighted_snippets() {
+    let server = TestServer::spawn(ServerState {
+        search_index: populated_index(),
+        ..Default::default()
+    })
+    .await;
+
+    let hits: Vec<SearchHit> = reqwest::get(server.url("/search?q=lunch"))
+        .await
+        .expect("failed to execute request")
+        .json()
+        .await
+        .expect("wrong response format");
+    let expected = SearchHit {
+        id: 4,
+        username: "user3".into(),
+        timestamp: Some(4000),
+        snippet: "<mark>lunch</mark> is ready".into(),
+    };
+    assert_eq!(hits, vec![expected]);
+
+    // Message text is escaped, so that snippets can be rendered as HTML
+    let hits: Vec<SearchHit> = reqwest::get(server.url("/search?q=pizza"))
+        .await
+        .expect("failed to execute request")
+        .json()
+        .await
+        .expect("wrong response format");
+    assert_eq!(
+        hits[0].snippet,
+        "&lt;img src=x onerror=alert(1)&gt; <mark>pizza</mark> &amp; salad"
+    );
+
+    let response = reqwest::get(server.url("/search?q=%20"))
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,184 @@
+use chat_backend::{
+    client::Event,
+    tui::{Action, ChatView, ConnectionStatus},
+    Payload, PayloadEventType,
+};
+use ratatui::{
+    backend::TestBackend,
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+    Terminal,
+};
+
+fn history_event(id: u64, event_type: PayloadEventType, username: &str, message: &str) -> Payload {
+    Payload {
+        id: Some(id),
+        event_type,
+        username: username.into(),
+        message: (!message.is_empty()).then(|| message.into()),
+        ..Default::default()
+    }
+}
+
+fn type_text(view: &mut ChatView, text: &str) {
+    for c in text.chars() {
+        assert_eq!(view.handle_key(KeyEvent::from(KeyCode::Char(c))), None);
+    }
+}
+
+/// Text of a rendered terminal, one string per row.
+fn render(view: &ChatView, width: u16, height: u16) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal.draw(|frame| view.render(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    (0..height)
+        .map(|y| (0..width).map(|x| buffer[(x, y)].symbol()).collect())
+        .collect()
+}
+
+#[test]
+fn scrollback_follows_history_and_live_events() {
+    let mut view = ChatView::new("user1", "ws://localhost:9001");
+    view.load_history(&[
+        history_event(1, PayloadEventType::Connected, "user2", ""),
+        history_event(2, PayloadEventType::Message, "user2", "hello"),
+        history_event(3, PayloadEventType::Read, "user2", ""),
+    ]);
+    view.set_users(["user1".to_string(), "user2".to_string()]);
+
+    // Replayed history overlaps with the fetched one
+    view.apply(&Event::History {
+        events: vec![
+            history_event(2, PayloadEventType::Message, "user2", "hello"),
+            history_event(4, PayloadEventType::Message, "user2", "anyone?"),
+        ],
+        last_id: 4,
+    });
+    view.apply(&Event::Joined {
+        username: "user3".into(),
+    });
+    view.apply(&Event::Left {
+        username: "user2".into(),
+    });
+
+    assert_eq!(
+        view.scrollback(),
+        [
+            "user2 has joined the chat.",
+            "[user2]: hello",
+            "[user2]: anyone?",
+            "user3 has joined the chat.",
+            "user2 has left the chat.",
+        ]
+    );
+    assert_eq!(view.users().collect::<Vec<_>>(), ["user1", "user3"]);
+}
+
+#[test]
+fn input_line_is_sent_on_enter() {
+    let mut view = ChatView::new("user1", "ws://localhost:9001");
+
+    // Blank input is not sent
+    type_text(&mut view, "  ");
+    assert_eq!(view.handle_key(KeyEvent::from(KeyCode::Enter)), None);
+
+    type_text(&mut view, "hellp");
+    view.handle_key(KeyEvent::from(KeyCode::Backspace));
+    type_text(&mut view, "o");
+    assert_eq!(view.input(), "hello");
+    assert_eq!(
+        view.handle_key(KeyEvent::from(KeyCode::Enter)),
+        Some(Action::Send("hello".into()))
+    );
+    assert_eq!(view.input(), "");
+
+    let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+    assert_eq!(view.handle_key(ctrl_c), Some(Action::Quit));
+    assert_eq!(view.input(), "");
+}
+
+#[test]
+fn connection_status_follows_client_events() {
+    let mut view = ChatView::new("user1", "ws://localhost:9001");
+    assert_eq!(view.status(), ConnectionStatus::Connected);
+
+    view.apply(&Event::ConnectionLost);
+    assert_eq!(view.status(), ConnectionStatus::Reconnecting);
+    assert!(render(&view, 80, 10)[9].starts_with(" reconnecting  user1 at ws://localhost:9001"));
+
+    view.apply(&Event::Reconnected);
+    assert_eq!(view.status(), ConnectionStatus::Connected);
+}
+
+#[test]
+fn rendering_shows_latest_events_users_and_input() {
+    let mut view = ChatView::new("user1", "ws://localhost:9001");
+    let history: Vec<_> = (1..=20)
+        .map(|id| {
+            history_event(
+                id,
+                PayloadEventType::Message,
+                "user2",
+                &format!("message {id}"),
+            )
+        })
+        .collect();
+    view.load_history(&history);
+    view.set_users(["user1".to_string(), "user2".to_string()]);
+    type_text(&mut view, "draft");
+    view.show_sent("sent");
+
+    let rows = render(&view, 60, 10);
+    // Scrollback has room for 4 lines between borders
+    assert!(rows[0].starts_with("┌ Chat "));
+    assert!(rows[0].contains("┌ Users (2) "));
+    assert!(rows[1].starts_with("│[user2]: message 18"));
+    assert!(rows[3].starts_with("│[user2]: message 20"));
+    assert!(rows[4].starts_with("│[user1]: sent"));
+    assert!(rows[1].contains("│user1"));
+    assert!(rows[2].contains("│user2"));
+    assert!(rows[7].starts_with("│draft"));
+
+    view.handle_key(KeyEvent::from(KeyCode::PageUp));
+    let rows = render(&view, 60, 10);
+    assert!(rows[4].starts_with("│[user2]: message 11"));
+}
+
+#[test]
+fn sent_messages_are_pending_until_received_from_history() {
+    let mut view = ChatView::new("user1", "ws://localhost:9001");
+    view.load_history(&[history_event(1, PayloadEventType::Message, "user2", "hi")]);
+    assert_eq!(view.pending_since(), None);
+
+    view.show_sent("what the heck");
+    view.show_sent("too long");
+    view.show_sent("bye");
+    assert_eq!(view.pending_since(), Some(1));
+    assert_eq!(view.scrollback()[1], "[user1]: what the heck (sending)");
+
+    // Server's version replaces the sent one, like messages filtered by the server
+    let filtered = history_event(2, PayloadEventType::Message, "user1", "what the ****");
+    let live = history_event(3, PayloadEventType::Message, "user2", "hello");
+    view.apply(&Event::Message(live.clone()));
+    view.load_history(&[filtered.clone(), live]);
+    assert_eq!(view.pending_since(), Some(2));
+    // Messages after a skipped one are handled later, so the skipped one is rejected
+    view.load_history(&[history_event(4, PayloadEventType::Message, "user1", "bye")]);
+    assert_eq!(view.pending_since(), None);
+    assert_eq!(
+        view.scrollback(),
+        [
+            "[user2]: hi",
+            "[user1]: what the ****",
+            "[user1]: too long (not sent)",
+            "[user1]: bye",
+            "[user2]: hello",
+        ]
+    );
+
+    // Replayed history is not shown twice
+    view.apply(&Event::History {
+        events: vec![filtered],
+        last_id: 4,
+    });
+    assert_eq!(view.scrollback().len(), 5);
+}
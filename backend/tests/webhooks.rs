@@ -0,0 +1,362 @@
+mod common;
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use chat_backend::{
+    configuration::WebhookConfig,
+    webhook::{self, DeadLetter, Webhook, WebhookRegistry},
+    Payload, ServerState, SharedServerState,
+};
+use common::{
+    join, receive_history, receive_joined, receive_message, spawn_servers, ADMIN_TOKEN, HOST,
+    TIMEOUT_SECONDS,
+};
+use reqwest::StatusCode;
+use tokio::sync::{mpsc, Mutex};
+
+/// POST request received by a stand-in webhook receiver.
+struct Delivery {
+    signature: String,
+    timestamp: String,
+    event: String,
+    delivery_id: String,
+    body: String,
+}
+
+struct ReceiverState {
+    /// Number of requests to fail before responding with success.
+    failures: AtomicUsize,
+    deliveries: mpsc::UnboundedSender<Delivery>,
+}
+
+async fn receive(
+    request: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<ReceiverState>,
+) -> HttpResponse {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default()
+    };
+    let delivery = Delivery {
+        signature: header(webhook::SIGNATURE_HEADER),
+        timestamp: header(webhook::TIMESTAMP_HEADER),
+        event: header(webhook::EVENT_HEADER),
+        delivery_id: header(webhook::DELIVERY_HEADER),
+        body: String::from_utf8(body.to_vec()).unwrap(),
+    };
+    state.deliveries.send(delivery).unwrap();
+    let failed = state
+        .failures
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok();
+    if failed {
+        HttpResponse::InternalServerError().finish()
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
+
+/// Start a local HTTP server standing in for a webhook receiver, failing the first `failures`
+/// requests. Returns its URL and the requests it receives.
+fn spawn_receiver(failures: usize) -> (String, mpsc::UnboundedReceiver<Delivery>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let state = web::Data::new(ReceiverState {
+        failures: AtomicUsize::new(failures),
+        deliveries: tx,
+    });
+    let listener = std::net::TcpListener::bind(format!("{HOST}:0")).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .default_service(web::to(receive))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    tokio::spawn(server);
+    (format!("http://{HOST}:{port}/hook"), rx)
+}
+
+async fn next_delivery(deliveries: &mut mpsc::UnboundedReceiver<Delivery>) -> Delivery {
+    tokio::time::timeout(TIMEOUT_SECONDS, deliveries.recv())
+        .await
+        .expect("timed out")
+        .unwrap()
+}
+
+fn webhook_config() -> WebhookConfig {
+    WebhookConfig {
+        max_attempts: 3,
+        initial_backoff_ms: 50,
+        ..Default::default()
+    }
+}
+
+fn server_state(config: &WebhookConfig) -> SharedServerState {
+    SharedServerState::new(Mutex::new(ServerState {
+        admin_token: Some(ADMIN_TOKEN.into()),
+        webhooks: WebhookRegistry::from_config(config).unwrap(),
+        history_replay_count: 50,
+        ..Default::default()
+    }))
+}
+
+async fn register_webhook(rest_port: u16, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{HOST}:{rest_port}/admin/webhooks"))
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&body)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn messages_are_delivered_signed() {
+    let (url, mut deliveries) = spawn_receiver(0);
+    let (rest_port, ws_port) = spawn_servers(server_state(&webhook_config())).await;
+    let response = register_webhook(
+        rest_port,
+        serde_json::json!({ "url": url, "events": ["message"], "secret": "hook-secret" }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let ws_client = join(ws_port, "user1").await;
+    ws_client.send_message("hello hooks").unwrap();
+
+    let delivery = next_delivery(&mut deliveries).await;
+    assert_eq!(delivery.event, "message");
+    let timestamp: u64 = delivery.timestamp.parse().unwrap();
+    assert!(timestamp.abs_diff(chat_backend::unix_timestamp_millis()) < 60_000);
+    assert_eq!(
+        delivery.signature,
+        webhook::sign("hook-secret", timestamp, delivery.body.as_bytes())
+    );
+    // A replayed body can not be signed with a later timestamp without the secret
+    assert_ne!(
+        delivery.signature,
+        webhook::sign("hook-secret", timestamp + 1, delivery.body.as_bytes())
+    );
+    let payload: Payload = serde_json::from_str(&delivery.body).unwrap();
+    assert_eq!(payload.username, "user1");
+    assert_eq!(payload.message.as_deref(), Some("hello hooks"));
+    assert!(payload.id.is_some());
+}
+
+#[tokio::test]
+async fn only_subscribed_events_are_delivered() {
+    let (url, mut deliveries) = spawn_receiver(0);
+    let (rest_port, ws_port) = spawn_servers(server_state(&webhook_config())).await;
+    let response = register_webhook(
+        rest_port,
+        serde_json::json!({ "url": url, "events": ["connected", "disconnected", "mention"] }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let ws_client = join(ws_port, "user1").await;
+    assert_eq!(next_delivery(&mut deliveries).await.event, "connected");
+
+    ws_client.send_message("no mention here").unwrap();
+    ws_client.send_message("ping @user2 and @user3").unwrap();
+    let mention = next_delivery(&mut deliveries).await;
+    assert_eq!(mention.event, "mention");
+    let payload: Payload = serde_json::from_str(&mention.body).unwrap();
+    assert_eq!(payload.username, "user1");
+    assert_eq!(payload.mentions, vec!["user2", "user3"]);
+
+    drop(ws_client);
+    assert_eq!(next_delivery(&mut deliveries).await.event, "disconnected");
+    assert!(deliveries.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let (url, mut deliveries) = spawn_receiver(2);
+    let (rest_port, ws_port) = spawn_servers(server_state(&webhook_config())).await;
+    register_webhook(
+        rest_port,
+        serde_json::json!({ "url": url, "events": ["message"] }),
+    )
+    .await;
+
+    let ws_client = join(ws_port, "user1").await;
+    ws_client.send_message("eventually").unwrap();
+
+    let first = next_delivery(&mut deliveries).await;
+    let second = next_delivery(&mut deliveries).await;
+    let third = next_delivery(&mut deliveries).await;
+    assert_eq!(first.delivery_id, second.delivery_id);
+    assert_eq!(first.delivery_id, third.delivery_id);
+    assert_eq!(first.body, third.body);
+    // Delivered at the third attempt, so no more retries
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(deliveries.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn given_up_deliveries_are_dead_lettered() {
+    let dead_letter_file = std::env::temp_dir().join(format!(
+        "chat-dead-letters-{}.jsonl",
+        uuid::Uuid::new_v4().simple()
+    ));
+    let config = WebhookConfig {
+        dead_letter_file: Some(dead_letter_file.to_string_lossy().into()),
+        ..webhook_config()
+    };
+    let (url, mut deliveries) = spawn_receiver(usize::MAX);
+    let (rest_port, ws_port) = spawn_servers(server_state(&config)).await;
+    register_webhook(
+        rest_port,
+        serde_json::json!({ "url": url, "events": ["message"] }),
+    )
+    .await;
+
+    let ws_client = join(ws_port, "user1").await;
+    ws_client.send_message("into the void").unwrap();
+    for _ in 0..3 {
+        next_delivery(&mut deliveries).await;
+    }
+
+    let dead_letter = tokio::time::timeout(TIMEOUT_SECONDS, async {
+        loop {
+            if let Ok(log) = tokio::fs::read_to_string(&dead_letter_file).await {
+                if log.ends_with('\n') {
+                    return log;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("timed out");
+    let dead_letter: DeadLetter = serde_json::from_str(dead_letter.trim_end()).unwrap();
+    assert_eq!(dead_letter.attempts, 3);
+    assert_eq!(dead_letter.url, url);
+    assert_eq!(
+        dead_letter.payload.message.as_deref(),
+        Some("into the void")
+    );
+    assert!(deliveries.try_recv().is_err());
+    std::fs::remove_file(dead_letter_file).unwrap();
+}
+
+#[tokio::test]
+async fn unresponsive_receivers_do_not_delay_chat() {
+    // Connections are queued by the OS, but requests are never answered
+    let silent_listener = std::net::TcpListener::bind(format!("{HOST}:0")).unwrap();
+    let url = format!("http://{}/hook", silent_listener.local_addr().unwrap());
+    let config = WebhookConfig {
+        timeout_ms: 60_000,
+        ..webhook_config()
+    };
+    let (rest_port, ws_port) = spawn_servers(server_state(&config)).await;
+    register_webhook(
+        rest_port,
+        serde_json::json!({ "url": url, "events": ["connected", "message"] }),
+    )
+    .await;
+
+    let mut receiver = join(ws_port, "user1").await;
+    receive_history(&mut receiver).await;
+    let sender = join(ws_port, "user2").await;
+    receive_joined(&mut receiver, "user2").await;
+    for i in 0..10 {
+        sender.send_message(format!("message {i}")).unwrap();
+    }
+    let last = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            let received = receive_message(&mut receiver).await;
+            if received.message.as_deref() == Some("message 9") {
+                return received;
+            }
+        }
+    })
+    .await;
+    assert!(last.is_ok());
+}
+
+#[tokio::test]
+async fn webhooks_are_persisted() {
+    let file = std::env::temp_dir().join(format!(
+        "chat-webhooks-{}.json",
+        uuid::Uuid::new_v4().simple()
+    ));
+    let config = WebhookConfig {
+        file: Some(file.to_string_lossy().into()),
+        ..webhook_config()
+    };
+    let (rest_port, _) = spawn_servers(server_state(&config)).await;
+    let client = reqwest::Client::new();
+    let webhooks_url = format!("http://{HOST}:{rest_port}/admin/webhooks");
+
+    let response = register_webhook(
+        rest_port,
+        serde_json::json!({ "url": "https://example.com/hook", "events": ["message"] }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: Webhook = response.json().await.unwrap();
+    assert!(!created.secret.is_empty());
+
+    // Secrets are only shown once
+    let listed: Vec<Webhook> = client
+        .get(&webhooks_url)
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed, vec![created.redacted()]);
+
+    let reloaded = WebhookRegistry::from_config(&config).unwrap();
+    assert_eq!(reloaded.list(), std::slice::from_ref(&created));
+
+    let response = client
+        .delete(format!("{webhooks_url}/{}", created.id))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let reloaded = WebhookRegistry::from_config(&config).unwrap();
+    assert!(reloaded.list().is_empty());
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_webhooks_are_rejected() {
+    let (rest_port, _) = spawn_servers(server_state(&webhook_config())).await;
+
+    for body in [
+        serde_json::json!({ "url": "ftp://example.com", "events": ["message"] }),
+        serde_json::json!({ "url": "not a url", "events": ["message"] }),
+        serde_json::json!({ "url": "https://example.com", "events": [] }),
+        serde_json::json!({ "url": "https://example.com", "events": ["hello"] }),
+    ] {
+        let response = register_webhook(rest_port, body).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{HOST}:{rest_port}/admin/webhooks"))
+        .bearer_auth("wrong")
+        .json(&serde_json::json!({ "url": "https://example.com", "events": ["message"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
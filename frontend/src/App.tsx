@@ -1,6 +1,6 @@
 import { useEffect, useState, useRef } from 'react';
 
-import { Payload, PayloadEventType, payloadToMessageLine } from './payload';
+import { PROTOCOL_VERSION, Payload, PayloadEventType, payloadToMessageLine } from './payload';
 import './App.css';
 
 /**
@@ -8,7 +8,9 @@ import './App.css';
  * for sending messages.
  *
  * Establishes new WebSocket connection using browser window/tab acting as new client.
- * Fetches message history from REST API.
+ * Latest message history is replayed by the server after joining. Servers not announcing
+ * the `history_batch` capability, e.g. with `history_replay_count: 0`, don't replay
+ * history, so it's fetched from REST API instead.
  */
 export default function App() {
     const [username, setUsername] = useState<string>('');
@@ -70,16 +72,11 @@ export default function App() {
                 }
 
                 const historyPayload: Payload[] = await resp.json();
-                const historyMessages: string[] = [];
-
-                historyPayload.forEach((payload: Payload) => {
-                    historyMessages.push(payloadToMessageLine(payload));
-                });
-
-                setMessages(historyMessages);
+                const historyMessages = historyPayload.map(payloadToMessageLine);
+                setMessages(prev => [...historyMessages, ...prev]);
             } catch (e) {
                 console.error('Error fetching history:', e);
-                setMessages(['Error: Unable to fetch message history.']);
+                setMessages(prev => ['Error: Unable to fetch message history.', ...prev]);
             }
         }
 
@@ -87,7 +84,7 @@ export default function App() {
             const protocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
             const host = window.location.hostname;
             const port = '9001';    // TODO: Read from YAML
-            const socket = new WebSocket(`${protocol}://${host}:${port}`);
+            const socket = new WebSocket(`${protocol}://${host}:${port}`, PROTOCOL_VERSION);
             socketRef.current = socket;
 
             socket.onerror = (e) => {
@@ -111,19 +108,27 @@ export default function App() {
 
             socket.onmessage = (e) => {
                 const payload: Payload = JSON.parse(e.data);
+                if (payload.event_type === PayloadEventType.Hello) {
+                    if (!payload.capabilities?.includes('history_batch')) {
+                        fetchHistory();
+                    }
+                    return;
+                }
+                if (payload.event_type === PayloadEventType.HistoryBatch) {
+                    // History precedes the join message shown when the connection opened
+                    const historyMessages = (payload.history ?? []).map(payloadToMessageLine);
+                    setMessages(prev => [...historyMessages, ...prev]);
+                    return;
+                }
                 setMessages(prev => [...prev, payloadToMessageLine(payload)]);
             }
         }
 
-        fetchHistory().then(() => connectToServer());
+        connectToServer();
 
         return () => {
+            // Server announces leaving once the connection is closed
             if (socketRef.current && socketRef.current.readyState === WebSocket.OPEN) {
-                const payload = {
-                    event_type: PayloadEventType.Disconnected,
-                    username: name,
-                };
-                socketRef.current.send(JSON.stringify(payload));
                 socketRef.current.close();
             }
         };
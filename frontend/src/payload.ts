@@ -1,3 +1,9 @@
+/**
+ * Version of the WebSocket protocol that payloads below belong to, requested
+ * with `Sec-WebSocket-Protocol` header.
+ */
+export const PROTOCOL_VERSION = 'chat.v2';
+
 /**
  * Message payload is the same JSON event payload that is passed and serialized
  * by the server backend.
@@ -5,22 +11,112 @@
 export interface Payload {
     event_type: PayloadEventType,
     username: string,
-    message?: string
+    message?: string,
+    id?: number,
+    timestamp?: number,
+    message_id?: number,
+    emoji?: string,
+    reactions?: Reaction[],
+    reply_to?: number,
+    reply_count?: number,
+    bot?: boolean,
+    mentions?: string[],
+    attachments?: Attachment[],
+    previews?: LinkPreview[],
+    history?: Payload[],
+    protocol?: string,
+    capabilities?: string[],
+    session_token?: string,
 }
 
 export enum PayloadEventType {
     Connected = 'connected',
     Disconnected = 'disconnected',
     Message = 'message',
+    React = 'react',
+    Unreact = 'unreact',
+    Reactions = 'reactions',
+    Replies = 'replies',
+    Read = 'read',
+    Mention = 'mention',
+    MessagePreview = 'message_preview',
+    HistoryBatch = 'history_batch',
+    Hello = 'hello',
+    System = 'system',
+}
+
+/**
+ * Summary of a single emoji reaction on a message.
+ */
+export interface Reaction {
+    emoji: string,
+    count: number,
+    usernames: string[],
+}
+
+/**
+ * Metadata of a file uploaded with `POST /attachments`, downloadable from
+ * `/attachments/{id}`.
+ */
+export interface Attachment {
+    id: string,
+    filename: string,
+    content_type: string,
+    size: number,
 }
 
+/**
+ * Title and description of a page linked in a message.
+ */
+export interface LinkPreview {
+    url: string,
+    title?: string,
+    description?: string,
+}
+
+const previewsToText = (previews?: LinkPreview[]) =>
+    (previews ?? []).map((p) => `> ${p.title ?? p.url}${p.description ? ` - ${p.description}` : ''}`).join('\n');
+
+const attachmentsToText = (attachments?: Attachment[]) =>
+    (attachments ?? []).map((a) => `[${a.filename}](/api/attachments/${a.id})`).join(' ');
+
+const reactionsToText = (reactions?: Reaction[]) =>
+    (reactions ?? []).map((r) => `${r.emoji} ${r.count}`).join(' ');
+
 export const payloadToMessageLine = (payload: Payload) => {
     switch (payload.event_type) {
         case PayloadEventType.Connected:
             return `${payload.username} has joined the chat.`;
         case PayloadEventType.Disconnected:
             return `${payload.username} has left the chat.`;
-        case PayloadEventType.Message:
-            return `[${payload.username}]: ${payload.message}`;
+        case PayloadEventType.Message: {
+            const reactions = reactionsToText(payload.reactions);
+            const bot = payload.bot ? ' (bot)' : '';
+            const replyTo = payload.reply_to !== undefined ? ` (reply to #${payload.reply_to})` : '';
+            const attachments = attachmentsToText(payload.attachments);
+            return [`[${payload.username}${bot}]${replyTo}: ${payload.message}`, attachments, reactions]
+                .filter((part) => part !== '')
+                .join('  ');
+        }
+        case PayloadEventType.React:
+            return `${payload.username} reacted ${payload.emoji} to message #${payload.message_id}.`;
+        case PayloadEventType.Unreact:
+            return `${payload.username} removed ${payload.emoji} from message #${payload.message_id}.`;
+        case PayloadEventType.Reactions:
+            return `Reactions on message #${payload.message_id}: ${reactionsToText(payload.reactions)}`;
+        case PayloadEventType.Replies:
+            return `Replies to message #${payload.message_id}: ${payload.reply_count ?? 0}`;
+        case PayloadEventType.Read:
+            return `${payload.username} has seen messages until #${payload.message_id}.`;
+        case PayloadEventType.Mention:
+            return `${payload.username} mentioned you: ${payload.message}`;
+        case PayloadEventType.MessagePreview:
+            return previewsToText(payload.previews);
+        case PayloadEventType.Hello:
+            return `Connected to chat server using ${payload.protocol}.`;
+        case PayloadEventType.System:
+            return `*** ${payload.message}`;
+        case PayloadEventType.HistoryBatch:
+            return (payload.history ?? []).map(payloadToMessageLine).join('\n');
     }
 }